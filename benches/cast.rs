@@ -0,0 +1,43 @@
+//! Times `Scene::cast` on its own, for a fixed set of camera rays into
+//! `grid_scene`. Run with `cargo bench`.
+
+extern crate racy;
+
+use racy::ray::Ray;
+use racy::scene::grid_scene;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::time::Instant;
+
+const RAYS: usize = 100_000;
+
+fn main() {
+  for &n in [2, 4, 8].iter() {
+    let scene = grid_scene(n);
+
+    // The same rays every run, aimed at random spots on the screen:
+    let mut rng = StdRng::seed_from_u64(0);
+    let (width, height) = (scene.settings.width as f32, scene.settings.height as f32);
+    let rays: Vec<Ray> = (0..RAYS)
+      .filter_map(|_| {
+        let (u, v) = (rng.gen_range(0.0, width), rng.gen_range(0.0, height));
+        scene.cam.get_ray_from_uv(&mut rng, u, v)
+      })
+      .collect();
+
+    let start = Instant::now();
+    let hits = rays
+      .iter()
+      .filter(|ray| scene.cast(ray, 0).is_some())
+      .count();
+    let elapsed = start.elapsed();
+
+    println!(
+      "cast into grid_scene({}): {} rays ({} hits), {:.0} ns/ray",
+      n,
+      rays.len(),
+      hits,
+      elapsed.as_secs_f64() * 1e9 / rays.len() as f64
+    );
+  }
+}
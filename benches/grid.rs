@@ -0,0 +1,35 @@
+//! Times headless renders of `grid_scene` at a few sizes. Run with
+//! `cargo bench`.
+
+extern crate racy;
+
+use racy::render::render_to_buffer;
+use racy::scene::grid_scene;
+use std::time::{Duration, Instant};
+
+const FRAMES: u32 = 5;
+
+fn main() {
+  for &n in [2, 4, 8].iter() {
+    let mut scene = grid_scene(n);
+    scene.settings.seed = Some(0);
+
+    // One untimed frame first, so we aren't also measuring thread pool startup:
+    render_to_buffer(&scene);
+
+    let mut total = Duration::default();
+    for _ in 0..FRAMES {
+      let start = Instant::now();
+      render_to_buffer(&scene);
+      total += start.elapsed();
+    }
+    println!(
+      "grid_scene({}): {} spheres, {}x{}, {:.1} ms/frame",
+      n,
+      n * n * n,
+      scene.settings.width,
+      scene.settings.height,
+      total.as_secs_f64() * 1000.0 / FRAMES as f64
+    );
+  }
+}
@@ -0,0 +1,183 @@
+use crate::ray::Ray;
+use crate::vector::Vector;
+
+/// An axis-aligned bounding box.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Aabb {
+  pub min: Vector,
+  pub max: Vector,
+}
+
+impl Aabb {
+  /// A box containing nothing at all; the union of it with any other box is
+  /// that other box.
+  pub const EMPTY: Aabb = Aabb {
+    min: Vector {
+      x: f64::INFINITY,
+      y: f64::INFINITY,
+      z: f64::INFINITY,
+    },
+    max: Vector {
+      x: f64::NEG_INFINITY,
+      y: f64::NEG_INFINITY,
+      z: f64::NEG_INFINITY,
+    },
+  };
+
+  pub fn new(min: Vector, max: Vector) -> Self {
+    Aabb { min, max }
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+  }
+
+  /// The smallest box containing both `self` and `other`.
+  pub fn union(&self, other: &Aabb) -> Aabb {
+    Aabb {
+      min: self.min.min(&other.min),
+      max: self.max.max(&other.max),
+    }
+  }
+
+  pub fn center(&self) -> Vector {
+    (self.min + self.max) / 2.0
+  }
+
+  /// Vector from the `min` corner to the `max` corner.
+  pub fn diagonal(&self) -> Vector {
+    self.max - self.min
+  }
+
+  /// How far along `ray` it first gets inside the box (`0` if it starts
+  /// there), or `None` if it never does.
+  pub fn entry(&self, ray: &Ray) -> Option<f64> {
+    if self.is_empty() {
+      return None;
+    }
+    // Where the ray is between each pair of parallel faces (the "slabs"); it's
+    // inside the box wherever those overlap.
+    let (mut near, mut far) = (0.0f64, f64::INFINITY);
+    let axes = [
+      (ray.origin.x, ray.direction.x, self.min.x, self.max.x),
+      (ray.origin.y, ray.direction.y, self.min.y, self.max.y),
+      (ray.origin.z, ray.direction.z, self.min.z, self.max.z),
+    ];
+    for &(origin, direction, min, max) in axes.iter() {
+      let inverse = 1.0 / direction;
+      let (t0, t1) = ((min - origin) * inverse, (max - origin) * inverse);
+      // (Rays parallel to a slab get infinities here, or NaNs right on its
+      // edge, which `max` and `min` skip over.)
+      near = near.max(t0.min(t1));
+      far = far.min(t0.max(t1));
+    }
+    if near <= far {
+      Some(near)
+    } else {
+      None
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn union_with_empty_is_identity() {
+    let a = Aabb::new(
+      Vector {
+        x: -1.0,
+        y: 0.0,
+        z: 1.0,
+      },
+      Vector {
+        x: 1.0,
+        y: 2.0,
+        z: 3.0,
+      },
+    );
+
+    assert!(Aabb::EMPTY.is_empty());
+    assert!(!a.is_empty());
+    assert_eq!(Aabb::EMPTY.union(&a), a);
+  }
+
+  #[test]
+  fn rays_enter_boxes_they_point_at() {
+    let unit = Aabb::new(
+      Vector::new(),
+      Vector {
+        x: 1.0,
+        y: 1.0,
+        z: 1.0,
+      },
+    );
+    let ray = |x, z| Ray {
+      origin: Vector { x, y: 0.5, z },
+      direction: Vector {
+        x: 0.0,
+        y: 0.0,
+        z: 1.0,
+      },
+      time: 0.0,
+      channel: None,
+    };
+
+    assert_eq!(unit.entry(&ray(0.5, -2.0)), Some(2.0));
+    assert_eq!(unit.entry(&ray(0.5, 0.5)), Some(0.0));
+    // Beside it, and already past it:
+    assert_eq!(unit.entry(&ray(1.5, -2.0)), None);
+    assert_eq!(unit.entry(&ray(0.5, 2.0)), None);
+    assert_eq!(Aabb::EMPTY.entry(&ray(0.5, -2.0)), None);
+  }
+
+  #[test]
+  fn union_contains_both() {
+    let a = Aabb::new(
+      Vector::new(),
+      Vector {
+        x: 1.0,
+        y: 1.0,
+        z: 1.0,
+      },
+    );
+    let b = Aabb::new(
+      Vector {
+        x: -2.0,
+        y: 0.5,
+        z: 0.5,
+      },
+      Vector {
+        x: 0.0,
+        y: 3.0,
+        z: 0.5,
+      },
+    );
+    let both = a.union(&b);
+
+    assert_eq!(
+      both,
+      Aabb::new(
+        Vector {
+          x: -2.0,
+          y: 0.0,
+          z: 0.0,
+        },
+        Vector {
+          x: 1.0,
+          y: 3.0,
+          z: 1.0,
+        },
+      )
+    );
+    assert_eq!(
+      both.center(),
+      Vector {
+        x: -0.5,
+        y: 1.5,
+        z: 0.5,
+      }
+    );
+  }
+}
@@ -0,0 +1,169 @@
+use crate::scene::Scene;
+use crate::vector::Vector;
+
+/// Something that moves part of a scene over time.
+///
+/// Each animation sets whatever it drives from scratch for the given time,
+/// rather than nudging it from where it was last frame, so they can be run in
+/// any order, skipped, or scrubbed backward. Animations that drive different
+/// things (say, one a light's height and one its position around the scene)
+/// can be combined freely.
+pub trait Animation {
+  /// Pose `scene` as it should be at time `t`, in whatever units the
+  /// animation's speeds are given in.
+  fn update(&self, scene: &mut Scene, t: f64);
+}
+
+/// Circles light number `light` around the vertical line through `center`,
+/// `speed` radians per unit of time. Leaves its height alone.
+pub struct OrbitLight {
+  pub light: usize,
+  pub center: Vector,
+  pub radius: f64,
+  pub speed: f64,
+}
+
+impl Animation for OrbitLight {
+  fn update(&self, scene: &mut Scene, t: f64) {
+    let angle = t * self.speed;
+    let light = &mut scene.lights[self.light];
+    light.center.x = self.center.x + self.radius * angle.sin();
+    light.center.z = self.center.z + self.radius * angle.cos();
+  }
+}
+
+/// Bobs light number `light` up and down by `amplitude` around `height`,
+/// `speed` radians (of a full up-and-down cycle) per unit of time. Leaves the
+/// rest of its position alone.
+pub struct BobLight {
+  pub light: usize,
+  pub height: f64,
+  pub amplitude: f64,
+  pub speed: f64,
+}
+
+impl Animation for BobLight {
+  fn update(&self, scene: &mut Scene, t: f64) {
+    scene.lights[self.light].center.y = self.height + self.amplitude * (t * self.speed).cos();
+  }
+}
+
+/// Moves the camera along the straight lines joining `points`, reaching the
+/// next one every `1 / speed` units of time, and looping back to the first
+/// after the last. Doesn't turn it.
+pub struct CameraPath {
+  pub points: Vec<Vector>,
+  pub speed: f64,
+}
+
+impl Animation for CameraPath {
+  fn update(&self, scene: &mut Scene, t: f64) {
+    if self.points.is_empty() {
+      return;
+    }
+    let progress = (t * self.speed).rem_euclid(self.points.len() as f64);
+    let from = progress.floor() as usize % self.points.len();
+    let to = (from + 1) % self.points.len();
+    let along = progress - progress.floor();
+    scene.cam.eye = self.points[from].lerp(&self.points[to], along);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::scene::grid_scene;
+  use crate::vector::approx_eq;
+  use std::f64::consts::PI;
+
+  #[test]
+  fn orbit_moves_a_light_in_a_circle() {
+    let mut scene = grid_scene(1);
+    let height = scene.lights[0].center.y;
+    let center = Vector {
+      x: 1.0,
+      y: 0.0,
+      z: 7.0,
+    };
+    let orbit = OrbitLight {
+      light: 0,
+      center,
+      radius: 3.0,
+      speed: 0.5,
+    };
+
+    for step in 0..16 {
+      orbit.update(&mut scene, step as f64 * PI / 8.0);
+      let light = scene.lights[0].center;
+      assert!(approx_eq(light.y, height, 1e-9));
+      let offset = light - center;
+      assert!(approx_eq(
+        (offset.x * offset.x + offset.z * offset.z).sqrt(),
+        3.0,
+        1e-9
+      ));
+    }
+
+    // A full turn takes `2π / speed`, so halfway there we're on the far side:
+    orbit.update(&mut scene, 0.0);
+    let start = scene.lights[0].center;
+    orbit.update(&mut scene, 2.0 * PI);
+    assert!(approx_eq(
+      scene.lights[0].center.distance(&start),
+      6.0,
+      1e-9
+    ));
+    orbit.update(&mut scene, 4.0 * PI);
+    assert!(scene.lights[0].center.approx_eq(&start, 1e-9));
+  }
+
+  #[test]
+  fn orbit_and_bob_compose() {
+    let mut scene = grid_scene(1);
+    let animations: Vec<Box<dyn Animation>> = vec![
+      Box::new(BobLight {
+        light: 0,
+        height: 4.0,
+        amplitude: 1.0,
+        speed: 1.0,
+      }),
+      Box::new(OrbitLight {
+        light: 0,
+        center: Vector::new(),
+        radius: 2.0,
+        speed: 1.0,
+      }),
+    ];
+    for animation in &animations {
+      animation.update(&mut scene, 0.0);
+    }
+    assert!(scene.lights[0].center.approx_eq(
+      &Vector {
+        x: 0.0,
+        y: 5.0,
+        z: 2.0,
+      },
+      1e-9
+    ));
+  }
+
+  #[test]
+  fn camera_path_loops_through_its_points() {
+    let mut scene = grid_scene(1);
+    let at = |x| Vector { x, y: 1.0, z: 0.0 };
+    let path = CameraPath {
+      points: vec![at(0.0), at(4.0), at(2.0)],
+      speed: 0.5,
+    };
+
+    path.update(&mut scene, 1.0);
+    assert!(scene.cam.eye.approx_eq(&at(2.0), 1e-9));
+    path.update(&mut scene, 3.0);
+    assert!(scene.cam.eye.approx_eq(&at(3.0), 1e-9));
+    // Back from the last point to the first:
+    path.update(&mut scene, 5.0);
+    assert!(scene.cam.eye.approx_eq(&at(1.0), 1e-9));
+    path.update(&mut scene, 6.0);
+    assert!(scene.cam.eye.approx_eq(&at(0.0), 1e-9));
+  }
+}
@@ -18,9 +18,24 @@
 //   getRayFromUV: (u, v)->
 //     p = @look.sub((@perp.mul((@xstart + u*@xmult))))
 //     return new Vector(p.x, @ystart + v*@ymult, p.z).normal()
+use rand::Rng;
+
+use crate::aabb::Aabb;
 use crate::ray::Ray;
 use crate::vector::Vector;
 
+/// How screen coordinates are mapped onto ray directions.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Projection {
+  /// A regular pinhole camera; straight lines stay straight.
+  Rectilinear,
+  /// Equidistant fisheye: a ray's angle away from `look` is proportional to
+  /// its pixel's distance from the center of the screen, reaching `fov / 2`
+  /// degrees at the edge of the largest circle that fits on screen. `fov` can
+  /// go all the way up to 360.
+  Fisheye { fov: f64 },
+}
+
 #[derive(Clone, Copy)]
 pub struct Camera {
   pub eye: Vector,
@@ -29,6 +44,16 @@ pub struct Camera {
   pub angle: f64,
   pub screen_width: u32,
   pub screen_height: u32,
+  /// How long the shutter stays open, as a fraction of a frame. Each ray gets
+  /// a random time in `[0, shutter)`, so anything moving during that window is
+  /// blurred; `0.0` freezes everything at the start of the frame.
+  pub shutter: f64,
+  pub projection: Projection,
+  /// Lateral chromatic aberration, like a cheap lens that focuses each color
+  /// at a slightly different size: the red image is magnified by this
+  /// fraction relative to green, and the blue one shrunk by it, so edges pick
+  /// up colored fringes toward the sides of the frame. `0.0` turns it off.
+  pub chromatic_aberration: f64,
   xstart: f64,
   ystart: f64,
   xmult: f64,
@@ -50,6 +75,9 @@ impl Camera {
       angle: 0.0,
       screen_width,
       screen_height,
+      shutter: 1.0,
+      projection: Projection::Rectilinear,
+      chromatic_aberration: 0.0,
       xstart,
       ystart,
       xmult,
@@ -79,20 +107,326 @@ impl Camera {
     self
   }
 
-  pub fn get_ray_from_uv(&self, u: f32, v: f32) -> Ray {
-    let p = self.look - (self.perp * (self.xstart + (u as f64 * self.xmult)));
+  /// Move the eye relative to where the camera is facing: `forward` along
+  /// `look`, `right` toward the right-hand edge of the screen, and `up`
+  /// straight up.
+  pub fn walk(&mut self, forward: f64, right: f64, up: f64) -> &mut Camera {
+    // `perp` points to the left of the screen:
+    self.eye += self.look * forward - self.perp * right;
+    self.eye.y += up;
+    self
+  }
 
-    let mut direction = Vector {
-      x: p.x,
-      y: self.ystart + (v as f64 * self.ymult),
-      z: p.z,
-    };
+  /// Move and turn the camera so it looks along `direction` (only its
+  /// horizontal part matters, since the camera can't pitch) at the center of
+  /// `bounds`, backed off far enough that all of `bounds` fits on screen.
+  pub fn frame(&mut self, bounds: &Aabb, direction: Vector) -> &mut Camera {
+    if bounds.is_empty() {
+      return self;
+    }
 
-    direction.normalize();
+    // `look` is `(-sin(angle), 0, -cos(angle))`, so:
+    self.set_angle((-direction.x).atan2(-direction.z));
 
-    Ray {
+    // Fit a sphere around the box, and back off until that sphere fits inside
+    // the narrower of our two fields of view. `xstart`/`ystart` are the
+    // tangents of the half-angles of the view at the screen's edges.
+    let radius = bounds.diagonal().length() / 2.0;
+    let half_fov = (-self.xstart).min(self.ystart).atan();
+    let distance = radius / half_fov.sin();
+
+    self.eye = bounds.center() - self.look * distance;
+    self
+  }
+
+  /// The ray passing through screen position `(u, v)`, in pixels from the
+  /// top-left corner.
+  ///
+  /// Returns `None` for positions that aren't part of the image, which happens
+  /// outside the image circle of a fisheye projection.
+  pub fn get_ray_from_uv<R: Rng + ?Sized>(&self, rng: &mut R, u: f32, v: f32) -> Option<Ray> {
+    Some(Ray {
       origin: self.eye,
-      direction,
+      direction: self.direction_at(u, v)?,
+      time: self.shutter_time(rng),
+      channel: None,
+    })
+  }
+
+  /// The rays for the red, green and blue parts of the image at screen
+  /// position `(u, v)`, which (with `chromatic_aberration`) pass through
+  /// slightly different spots. They all share the same moment in time, and
+  /// each carries its own `channel`.
+  pub fn get_channel_rays<R: Rng + ?Sized>(&self, rng: &mut R, u: f32, v: f32) -> Option<[Ray; 3]> {
+    let (center_u, center_v) = (
+      self.screen_width as f32 / 2.0,
+      self.screen_height as f32 / 2.0,
+    );
+    let time = self.shutter_time(rng);
+    let ray = |magnification: f64, channel: usize| {
+      // Magnifying the image means each pixel sees a spot closer to the
+      // center:
+      let scale = (1.0 / magnification) as f32;
+      Some(Ray {
+        origin: self.eye,
+        direction: self.direction_at(
+          center_u + (u - center_u) * scale,
+          center_v + (v - center_v) * scale,
+        )?,
+        time,
+        channel: Some(channel),
+      })
+    };
+    Some([
+      ray(1.0 + self.chromatic_aberration, 0)?,
+      ray(1.0, 1)?,
+      ray(1.0 - self.chromatic_aberration, 2)?,
+    ])
+  }
+
+  fn shutter_time<R: Rng + ?Sized>(&self, rng: &mut R) -> f64 {
+    if self.shutter > 0.0 {
+      rng.gen_range(0.0, self.shutter)
+    } else {
+      0.0
+    }
+  }
+
+  fn direction_at(&self, u: f32, v: f32) -> Option<Vector> {
+    let direction = match self.projection {
+      Projection::Rectilinear => {
+        let p = self.look - (self.perp * (self.xstart + (u as f64 * self.xmult)));
+
+        let mut direction = Vector {
+          x: p.x,
+          y: self.ystart + (v as f64 * self.ymult),
+          z: p.z,
+        };
+
+        direction.normalize();
+        direction
+      }
+      Projection::Fisheye { fov } => {
+        let dx = u as f64 - self.screen_width as f64 / 2.0;
+        let dy = v as f64 - self.screen_height as f64 / 2.0;
+        let max_radius = self.screen_width.min(self.screen_height) as f64 / 2.0;
+        let radius = (dx * dx + dy * dy).sqrt();
+        if radius > max_radius {
+          return None;
+        }
+
+        // Angle away from the center of view:
+        let theta = (radius / max_radius) * (fov / 2.0).to_radians();
+        if radius == 0.0 {
+          self.look
+        } else {
+          // `perp` points to the left of the screen, and our camera never
+          // rolls, so screen-up is always world-up:
+          let right = self.perp * -1.0;
+          let up = Vector {
+            x: 0.0,
+            y: 1.0,
+            z: 0.0,
+          };
+          let toward_pixel = right * (dx / radius) + up * (-dy / radius);
+          (self.look * theta.cos() + toward_pixel * theta.sin()).normalized()
+        }
+      }
+    };
+    Some(direction)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn ray_time_is_within_shutter() {
+    let mut rng = rand::thread_rng();
+    let mut camera = Camera::new(Vector::new(), 45.0, 16, 16);
+    camera.shutter = 0.25;
+
+    for _ in 0..1000 {
+      let ray = camera.get_ray_from_uv(&mut rng, 8.0, 8.0).unwrap();
+      assert!(ray.time >= 0.0 && ray.time < 0.25);
+    }
+
+    camera.shutter = 0.0;
+    assert_eq!(
+      camera.get_ray_from_uv(&mut rng, 8.0, 8.0).unwrap().time,
+      0.0
+    );
+  }
+
+  #[test]
+  fn without_aberration_channels_share_a_ray() {
+    let mut rng = rand::thread_rng();
+    let mut camera = Camera::new(Vector::new(), 45.0, 16, 16);
+    camera.shutter = 0.0;
+
+    let expected = camera.get_ray_from_uv(&mut rng, 3.0, 12.5).unwrap();
+    let [red, green, blue] = camera.get_channel_rays(&mut rng, 3.0, 12.5).unwrap();
+    // (Apart from which channel each carries:)
+    let carrying = |channel| Ray {
+      channel: Some(channel),
+      ..expected
+    };
+    assert_eq!(red, carrying(0));
+    assert_eq!(green, carrying(1));
+    assert_eq!(blue, carrying(2));
+  }
+
+  #[test]
+  fn aberration_spreads_channels_apart_away_from_the_center() {
+    let mut rng = rand::thread_rng();
+    let mut camera = Camera::new(Vector::new(), 45.0, 16, 16);
+    camera.shutter = 0.0;
+    camera.chromatic_aberration = 0.05;
+
+    // Dead center, every channel sees the same thing...
+    let [red, green, blue] = camera.get_channel_rays(&mut rng, 8.0, 8.0).unwrap();
+    assert_eq!(red.direction, green.direction);
+    assert_eq!(blue.direction, green.direction);
+
+    // ...but near the edge, the magnified red image sees a spot nearer the
+    // middle than green does, and blue one farther out:
+    let [red, green, blue] = camera.get_channel_rays(&mut rng, 15.0, 8.0).unwrap();
+    assert_eq!(
+      green.direction,
+      camera
+        .get_ray_from_uv(&mut rng, 15.0, 8.0)
+        .unwrap()
+        .direction
+    );
+    let off_center = |ray: Ray| ray.direction.dot(&camera.look).acos();
+    assert!(off_center(red) < off_center(green));
+    assert!(off_center(blue) > off_center(green));
+  }
+
+  #[test]
+  fn walk_is_relative_to_facing() {
+    let mut rng = rand::thread_rng();
+    let mut camera = Camera::new(Vector::new(), 45.0, 16, 16);
+    camera.set_angle(1.0);
+
+    camera.walk(2.0, 0.0, 0.0);
+    assert!(camera.eye.approx_eq(&(camera.look * 2.0), 1e-9));
+
+    // Walking right moves the eye toward what's on the right of the screen:
+    let right_edge = camera.get_ray_from_uv(&mut rng, 16.0, 8.0).unwrap();
+    let before = camera.eye;
+    camera.walk(0.0, 1.0, 0.5);
+    let moved = camera.eye - before;
+    assert!(moved.dot(&right_edge.direction) > 0.0);
+    assert!((moved.y - 0.5).abs() < 1e-9);
+  }
+
+  #[test]
+  fn fisheye_center_points_along_look() {
+    let mut rng = rand::thread_rng();
+    let mut camera = Camera::new(Vector::new(), 45.0, 16, 16);
+    camera.set_angle(1.0);
+    camera.projection = Projection::Fisheye { fov: 180.0 };
+
+    let ray = camera.get_ray_from_uv(&mut rng, 8.0, 8.0).unwrap();
+    assert!(ray.direction.approx_eq(&camera.look, 1e-9));
+  }
+
+  #[test]
+  fn fisheye_edge_is_at_half_fov() {
+    let mut rng = rand::thread_rng();
+    let mut camera = Camera::new(Vector::new(), 45.0, 16, 16);
+    camera.projection = Projection::Fisheye { fov: 180.0 };
+
+    // The right-hand edge of a 180 degree fisheye looks exactly sideways...
+    let ray = camera.get_ray_from_uv(&mut rng, 16.0, 8.0).unwrap();
+    assert!(ray.direction.dot(&camera.look).abs() < 1e-9);
+    // ...and the corners are outside the image circle entirely.
+    assert!(camera.get_ray_from_uv(&mut rng, 0.0, 0.0).is_none());
+  }
+
+  #[test]
+  fn frame_fits_scene_on_screen() {
+    use crate::material::MIRROR;
+    use crate::scene::Renderable;
+    use crate::sphere::Sphere;
+    use std::sync::Arc;
+
+    let spheres = [
+      Sphere::new(
+        Vector {
+          x: -3.0,
+          y: 1.0,
+          z: 10.0,
+        },
+        1.0,
+        Arc::new(MIRROR),
+      ),
+      Sphere::new(
+        Vector {
+          x: 4.0,
+          y: -2.0,
+          z: 20.0,
+        },
+        2.0,
+        Arc::new(MIRROR),
+      ),
+    ];
+    let bounds = spheres
+      .iter()
+      .filter_map(|sphere| sphere.bounds())
+      .fold(Aabb::EMPTY, |bounds, sphere_bounds| {
+        bounds.union(&sphere_bounds)
+      });
+
+    let mut camera = Camera::new(Vector::new(), 45.0, 32, 24);
+    camera.frame(
+      &bounds,
+      Vector {
+        x: 1.0,
+        y: 0.0,
+        z: 1.0,
+      },
+    );
+
+    // Project the extremes of each sphere back onto the screen by inverting
+    // `get_ray_from_uv`'s rectilinear mapping:
+    let project = |point: Vector| {
+      let to_point = point - camera.eye;
+      let forward = to_point.dot(&camera.look);
+      assert!(forward > 0.0);
+      let p = to_point / forward;
+      let u = (-p.dot(&camera.perp) - camera.xstart) / camera.xmult;
+      let v = (p.y - camera.ystart) / camera.ymult;
+      (u, v)
+    };
+    for sphere in spheres.iter() {
+      for axis in [
+        Vector {
+          x: 1.0,
+          y: 0.0,
+          z: 0.0,
+        },
+        Vector {
+          x: 0.0,
+          y: 1.0,
+          z: 0.0,
+        },
+        Vector {
+          x: 0.0,
+          y: 0.0,
+          z: 1.0,
+        },
+      ]
+      .iter()
+      {
+        for sign in [-1.0, 1.0].iter() {
+          let (u, v) = project(sphere.center + axis * (sphere.radius() * sign));
+          assert!((0.0..=32.0).contains(&u), "u = {} is off screen", u);
+          assert!((0.0..=24.0).contains(&v), "v = {} is off screen", v);
+        }
+      }
     }
   }
 }
@@ -0,0 +1,169 @@
+use crate::settings::{LightSampling, PixelSampling, RenderSettings};
+
+pub const USAGE: &str =
+  "usage: racy [--width N] [--height N] [--samples N] [--adaptive THRESHOLD] [--max-samples N] [--seed N] [--exposure X] [--bounces N] [--random-aa] [--stratified-shadows] [--headless] [--output FILE.png|FILE.exr] [--aovs] [--environment FILE]";
+
+/// Everything we can configure from the command line.
+#[derive(Debug, Clone)]
+pub struct Options {
+  pub settings: RenderSettings,
+  /// Render a single frame to `output` without opening a window (or even
+  /// initializing SDL's video subsystem).
+  pub headless: bool,
+  /// Where to save a headless render. Ending it in `.exr` saves the image in
+  /// full floating-point range instead of as a PNG (which can't be combined
+  /// with `aovs`).
+  pub output: String,
+  /// When rendering headless, also write albedo and normal AOVs next to
+  /// `output` (see `aov_path`).
+  pub aovs: bool,
+  /// An equirectangular image to light and surround the scene with, either a
+  /// Radiance `.hdr` file or anything SDL_image can load.
+  pub environment: Option<String>,
+}
+
+impl Default for Options {
+  fn default() -> Self {
+    Options {
+      settings: RenderSettings::default(),
+      headless: false,
+      output: String::from("racy.png"),
+      aovs: false,
+      environment: None,
+    }
+  }
+}
+
+/// Parse command line arguments (not including the program name).
+pub fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Options, String> {
+  let mut options = Options::default();
+  let mut args = args.into_iter();
+
+  while let Some(arg) = args.next() {
+    let mut value = |name: &str| {
+      args
+        .next()
+        .ok_or_else(|| format!("missing value for {}", name))
+    };
+    match arg.as_str() {
+      "--width" => options.settings.width = parse_number(&arg, &value(&arg)?)?,
+      "--height" => options.settings.height = parse_number(&arg, &value(&arg)?)?,
+      "--samples" => options.settings.samples_per_pixel = parse_number(&arg, &value(&arg)?)?,
+      "--adaptive" => {
+        options.settings.adaptive_threshold = Some(parse_number(&arg, &value(&arg)?)?)
+      }
+      "--max-samples" => {
+        options.settings.max_samples_per_pixel = parse_number(&arg, &value(&arg)?)?
+      }
+      "--seed" => options.settings.seed = Some(parse_number(&arg, &value(&arg)?)?),
+      "--bounces" => options.settings.diffuse_bounces = parse_number(&arg, &value(&arg)?)?,
+      "--exposure" => options.settings.exposure = parse_number(&arg, &value(&arg)?)?,
+      "--output" => options.output = value(&arg)?,
+      "--random-aa" => options.settings.pixel_sampling = PixelSampling::Random,
+      "--stratified-shadows" => options.settings.light_sampling = LightSampling::Stratified,
+      "--headless" => options.headless = true,
+      "--aovs" => options.aovs = true,
+      "--environment" => options.environment = Some(value(&arg)?),
+      _ => return Err(format!("unrecognized argument: {}", arg)),
+    }
+  }
+
+  if options.aovs && options.output.ends_with(".exr") {
+    return Err(String::from("--aovs only works with a .png output"));
+  }
+  Ok(options)
+}
+
+/// Where to save the AOV called `name` for an image saved at `output`:
+/// `racy.png` becomes `racy.albedo.png`.
+pub fn aov_path(output: &str, name: &str) -> String {
+  match output.rfind('.') {
+    Some(dot) if !output[dot..].contains('/') => {
+      format!("{}.{}{}", &output[..dot], name, &output[dot..])
+    }
+    _ => format!("{}.{}", output, name),
+  }
+}
+
+fn parse_number<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+  value
+    .parse()
+    .map_err(|_| format!("invalid value for {}: {}", name, value))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+  }
+
+  #[test]
+  fn no_args_is_interactive_with_defaults() {
+    let options = parse_args(args(&[])).unwrap();
+    assert!(!options.headless);
+    assert_eq!(options.settings.width, RenderSettings::default().width);
+  }
+
+  #[test]
+  fn parses_headless_render_options() {
+    let options = parse_args(args(&[
+      "--width",
+      "64",
+      "--height",
+      "48",
+      "--samples",
+      "4",
+      "--output",
+      "out.png",
+      "--headless",
+    ]))
+    .unwrap();
+    assert!(options.headless);
+    assert_eq!(options.settings.width, 64);
+    assert_eq!(options.settings.height, 48);
+    assert_eq!(options.settings.samples_per_pixel, 4);
+    assert_eq!(options.output, "out.png");
+  }
+
+  #[test]
+  fn parses_adaptive_sampling_options() {
+    let options = parse_args(args(&["--adaptive", "0.001", "--max-samples", "32"])).unwrap();
+    assert_eq!(options.settings.adaptive_threshold, Some(0.001));
+    assert_eq!(options.settings.max_samples_per_pixel, 32);
+  }
+
+  #[test]
+  fn parses_sampling_strategies() {
+    let options = parse_args(args(&[])).unwrap();
+    assert_eq!(options.settings.pixel_sampling, PixelSampling::Stratified);
+    let options = parse_args(args(&["--random-aa"])).unwrap();
+    assert_eq!(options.settings.pixel_sampling, PixelSampling::Random);
+  }
+
+  #[test]
+  fn parses_an_environment() {
+    assert_eq!(parse_args(args(&[])).unwrap().environment, None);
+    let options = parse_args(args(&["--environment", "sky.hdr"])).unwrap();
+    assert_eq!(options.environment.as_deref(), Some("sky.hdr"));
+  }
+
+  #[test]
+  fn aov_paths_go_next_to_output() {
+    assert_eq!(aov_path("racy.png", "albedo"), "racy.albedo.png");
+    assert_eq!(
+      aov_path("out/frame.1.png", "normal"),
+      "out/frame.1.normal.png"
+    );
+    assert_eq!(aov_path("./frame", "normal"), "./frame.normal");
+  }
+
+  #[test]
+  fn rejects_bad_args() {
+    assert!(parse_args(args(&["--width"])).is_err());
+    assert!(parse_args(args(&["--width", "wide"])).is_err());
+    assert!(parse_args(args(&["--fast"])).is_err());
+    assert!(parse_args(args(&["--aovs", "--output", "out.exr"])).is_err());
+  }
+}
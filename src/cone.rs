@@ -0,0 +1,293 @@
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::scene::{Hit, Renderable};
+use crate::vector::Vector;
+
+/// A solid, finite cone: its tip is at `apex`, and it widens at `half_angle`
+/// (in radians) from `axis` until it's closed off by a flat, round base
+/// `height` away.
+#[derive(Clone)]
+pub struct Cone {
+  pub apex: Vector,
+  axis: Vector,
+  pub half_angle: f64,
+  pub height: f64,
+  pub material: Arc<dyn Material>,
+}
+
+impl Cone {
+  pub fn new(
+    apex: Vector,
+    axis: Vector,
+    half_angle: f64,
+    height: f64,
+    material: Arc<dyn Material>,
+  ) -> Self {
+    Cone {
+      apex,
+      axis: axis.normalized(),
+      half_angle,
+      height,
+      material,
+    }
+  }
+
+  fn base_radius(&self) -> f64 {
+    self.height * self.half_angle.tan()
+  }
+
+  /// Every distance along the line through `ray` at which it crosses the
+  /// surface (nearest first), and the normal there.
+  fn crossings(&self, ray: &Ray) -> Vec<(f64, Vector)> {
+    // Relative to the apex, a point `v` is on the (infinite, double) cone when
+    //
+    //   (v·axis)² = |v|² cos²(half_angle)
+    //
+    // Substituting `v = o + t·d` gives a quadratic in `t`:
+    let o = ray.origin - self.apex;
+    let d = ray.direction;
+    let cos2 = self.half_angle.cos().powi(2);
+    let (oa, da) = (o.dot(&self.axis), d.dot(&self.axis));
+    let a = da * da - cos2 * d.dot(&d);
+    let b = 2.0 * (da * oa - cos2 * o.dot(&d));
+    let c = oa * oa - cos2 * o.dot(&o);
+
+    let mut side = vec![];
+    if a.abs() < 1e-12 {
+      // Parallel to the side of the cone, so we only cross it once:
+      if b.abs() > 1e-12 {
+        side.push(-c / b);
+      }
+    } else {
+      let discriminant = b * b - 4.0 * a * c;
+      // Rays through the apex are a double root, which rounding can nudge just
+      // below zero:
+      let discriminant = if discriminant < 0.0 && discriminant > -1e-9 * b * b {
+        0.0
+      } else {
+        discriminant
+      };
+      if discriminant >= 0.0 {
+        side.push((-b - discriminant.sqrt()) / (2.0 * a));
+        side.push((-b + discriminant.sqrt()) / (2.0 * a));
+      }
+    }
+
+    let mut crossings: Vec<(f64, Vector)> = side
+      .into_iter()
+      .filter_map(|t| {
+        // Only the half of the double cone in front of the apex, and only as
+        // far as the base:
+        let v = o + d * t;
+        let along = v.dot(&self.axis);
+        if !(0.0..=self.height).contains(&along) {
+          return None;
+        }
+        // The gradient of the equation above, which points inward, flipped.
+        // Right at the apex there isn't one, so just point away from the base:
+        let normal = v * cos2 - self.axis * along;
+        let normal = if normal.length_squared() < 1e-18 {
+          self.axis * -1.0
+        } else {
+          normal.normalized()
+        };
+        Some((t, normal))
+      })
+      .collect();
+
+    if da.abs() > 1e-12 {
+      let t = (self.height - oa) / da;
+      let from_center = o + d * t - self.axis * self.height;
+      let radius = self.base_radius();
+      if from_center.length_squared() <= radius * radius {
+        crossings.push((t, self.axis));
+      }
+    }
+
+    crossings.sort_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap());
+    crossings
+  }
+}
+
+impl Renderable for Cone {
+  fn intersects(&self, ray: &Ray) -> Option<Hit> {
+    // Like a sphere's, our normals always point outward, even from inside:
+    self
+      .crossings(ray)
+      .into_iter()
+      .find(|&(t, _)| t >= 0.0001)
+      .map(|(t, normal)| Hit {
+        t,
+        normal,
+        uv: None,
+      })
+  }
+
+  fn material(&self) -> &dyn Material {
+    &*self.material
+  }
+
+  fn set_material(&mut self, material: Arc<dyn Material>) {
+    self.material = material;
+  }
+
+  fn bounds(&self) -> Option<Aabb> {
+    // The base's circle reaches `r * sin(angle between axis and X)` along X,
+    // and so on; the apex might stick out past it.
+    let base = self.apex + self.axis * self.height;
+    let radius = self.base_radius();
+    let extent = |a: f64| radius * (1.0 - a * a).max(0.0).sqrt();
+    let extent = Vector {
+      x: extent(self.axis.x),
+      y: extent(self.axis.y),
+      z: extent(self.axis.z),
+    };
+    Some(Aabb::new(
+      (base - extent).min(&self.apex),
+      (base + extent).max(&self.apex),
+    ))
+  }
+
+  fn intersect_all(&self, ray: &Ray) -> Vec<(Hit, bool)> {
+    // A cone is convex, so a ray through it crosses in once and out once. (A
+    // ray that only touches an edge or the apex has nothing in between.)
+    let crossings = self.crossings(ray);
+    match (crossings.first(), crossings.last()) {
+      (Some(&(near, near_normal)), Some(&(far, far_normal))) if near < far => vec![
+        (
+          Hit {
+            t: near,
+            normal: near_normal,
+            uv: None,
+          },
+          true,
+        ),
+        (
+          Hit {
+            t: far,
+            normal: far_normal,
+            uv: None,
+          },
+          false,
+        ),
+      ],
+      _ => vec![],
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::material::MIRROR;
+  use crate::vector::approx_eq;
+  use std::f64::consts::FRAC_PI_4;
+
+  // Tip at `y = 2`, widening downward at 45° to a base of radius 2 on the
+  // ground.
+  fn party_hat() -> Cone {
+    Cone::new(
+      Vector {
+        x: 0.0,
+        y: 2.0,
+        z: 0.0,
+      },
+      Vector {
+        x: 0.0,
+        y: -1.0,
+        z: 0.0,
+      },
+      FRAC_PI_4,
+      2.0,
+      Arc::new(MIRROR),
+    )
+  }
+
+  fn along_x(y: f64) -> Ray {
+    Ray {
+      origin: Vector {
+        x: -10.0,
+        y,
+        z: 0.0,
+      },
+      direction: Vector {
+        x: 1.0,
+        y: 0.0,
+        z: 0.0,
+      },
+      time: 0.0,
+      channel: None,
+    }
+  }
+
+  #[test]
+  fn hits_the_side() {
+    // Halfway up, the cone is 1 unit wide:
+    let hit = party_hat().intersects(&along_x(1.0)).unwrap();
+    assert!(approx_eq(hit.t, 9.0, 1e-9));
+    let half = 0.5f64.sqrt();
+    assert!(hit.normal.approx_eq(
+      &Vector {
+        x: -half,
+        y: half,
+        z: 0.0,
+      },
+      1e-9
+    ));
+  }
+
+  #[test]
+  fn ray_through_the_apex() {
+    // Straight down the axis, we touch the apex and come out through the base:
+    let ray = Ray {
+      origin: Vector {
+        x: 0.0,
+        y: 10.0,
+        z: 0.0,
+      },
+      direction: Vector {
+        x: 0.0,
+        y: -1.0,
+        z: 0.0,
+      },
+      time: 0.0,
+      channel: None,
+    };
+    let cone = party_hat();
+    let hit = cone.intersects(&ray).unwrap();
+    assert!(approx_eq(hit.t, 8.0, 1e-6));
+    assert!(hit.normal.approx_eq(
+      &Vector {
+        x: 0.0,
+        y: 1.0,
+        z: 0.0,
+      },
+      1e-9
+    ));
+
+    let crossings = cone.intersect_all(&ray);
+    assert_eq!(crossings.len(), 2);
+    let (base, entering) = crossings[1];
+    assert!(!entering);
+    assert!(approx_eq(base.t, 10.0, 1e-9));
+    assert!(base.normal.approx_eq(
+      &Vector {
+        x: 0.0,
+        y: -1.0,
+        z: 0.0,
+      },
+      1e-9
+    ));
+  }
+
+  #[test]
+  fn misses_above_the_apex() {
+    let cone = party_hat();
+    assert_eq!(cone.intersects(&along_x(2.1)), None);
+    // The other half of the double cone, which we cut off at the apex:
+    assert_eq!(cone.intersects(&along_x(3.0)), None);
+  }
+}
@@ -0,0 +1,231 @@
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::scene::{Hit, Renderable};
+
+/// How `Csg` combines its two children.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CsgOp {
+  /// Inside either child.
+  Union,
+  /// Inside both children.
+  Intersection,
+  /// Inside `left` but not `right`.
+  Difference,
+}
+
+impl CsgOp {
+  fn apply(&self, in_left: bool, in_right: bool) -> bool {
+    match self {
+      CsgOp::Union => in_left || in_right,
+      CsgOp::Intersection => in_left && in_right,
+      CsgOp::Difference => in_left && !in_right,
+    }
+  }
+}
+
+/// Constructive solid geometry: a solid built by combining two others with a
+/// boolean operation, like a sphere with a bite (a box) taken out of it.
+///
+/// Both children must be able to report their `intersect_all` crossings. The
+/// whole thing is shaded with `left`'s material.
+pub struct Csg {
+  pub left: Box<dyn Renderable>,
+  pub right: Box<dyn Renderable>,
+  pub op: CsgOp,
+}
+
+impl Csg {
+  pub fn new(left: Box<dyn Renderable>, right: Box<dyn Renderable>, op: CsgOp) -> Self {
+    Csg { left, right, op }
+  }
+
+  /// The combined solid's crossings, in the same form as `intersect_all`.
+  fn crossings(&self, ray: &Ray) -> Vec<(Hit, bool)> {
+    let left = self.left.intersect_all(ray);
+    let right = self.right.intersect_all(ray);
+
+    // Every child's crossings start infinitely far behind the ray, so if the
+    // first one is an exit, that's where we started:
+    let mut in_left = left.first().is_some_and(|&(_, entering)| !entering);
+    let mut in_right = right.first().is_some_and(|&(_, entering)| !entering);
+    let mut inside = self.op.apply(in_left, in_right);
+
+    // Walk both lists in order, keeping only the crossings that change whether
+    // we're inside the combined solid:
+    let mut crossings = vec![];
+    let (mut l, mut r) = (left.iter().peekable(), right.iter().peekable());
+    loop {
+      let on_left = match (l.peek(), r.peek()) {
+        (Some((left_hit, _)), Some((right_hit, _))) => left_hit.t <= right_hit.t,
+        (Some(_), None) => true,
+        (None, Some(_)) => false,
+        (None, None) => break,
+      };
+      let &(hit, entering) = if on_left {
+        l.next().unwrap()
+      } else {
+        r.next().unwrap()
+      };
+      if on_left {
+        in_left = entering;
+      } else {
+        in_right = entering;
+      }
+
+      let now_inside = self.op.apply(in_left, in_right);
+      if now_inside != inside {
+        // Where we've carved `right` out of `left`, its surface faces the
+        // other way:
+        let normal = if !on_left && self.op == CsgOp::Difference {
+          hit.normal * -1.0
+        } else {
+          hit.normal
+        };
+        crossings.push((Hit { normal, ..hit }, now_inside));
+        inside = now_inside;
+      }
+    }
+    crossings
+  }
+}
+
+impl Renderable for Csg {
+  fn intersects(&self, ray: &Ray) -> Option<Hit> {
+    // Like a sphere's, our normals always point outward, whichever side we're
+    // looking from:
+    self
+      .crossings(ray)
+      .into_iter()
+      .map(|(hit, _)| hit)
+      .find(|hit| hit.t >= 0.0001)
+  }
+
+  fn material(&self) -> &dyn Material {
+    self.left.material()
+  }
+
+  fn set_material(&mut self, material: Arc<dyn Material>) {
+    self.left.set_material(material);
+  }
+
+  fn bounds(&self) -> Option<Aabb> {
+    match self.op {
+      CsgOp::Union => Some(self.left.bounds()?.union(&self.right.bounds()?)),
+      CsgOp::Intersection | CsgOp::Difference => self.left.bounds(),
+    }
+  }
+
+  fn intersect_all(&self, ray: &Ray) -> Vec<(Hit, bool)> {
+    self.crossings(ray)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::cuboid::Cuboid;
+  use crate::material::MIRROR;
+  use crate::sphere::Sphere;
+  use crate::vector::{approx_eq, Vector};
+  use std::sync::Arc;
+
+  // A unit sphere at the origin, and a box covering its top-right corner.
+  fn apple_and_bite(op: CsgOp) -> Csg {
+    Csg::new(
+      Box::new(Sphere::new(Vector::new(), 1.0, Arc::new(MIRROR))),
+      Box::new(Cuboid::new(
+        Aabb::new(
+          Vector {
+            x: 0.0,
+            y: 0.0,
+            z: -2.0,
+          },
+          Vector {
+            x: 2.0,
+            y: 2.0,
+            z: 2.0,
+          },
+        ),
+        Arc::new(MIRROR),
+      )),
+      op,
+    )
+  }
+
+  // Looking down the Z axis at `(x, y)`.
+  fn ray_at(x: f64, y: f64) -> Ray {
+    Ray {
+      origin: Vector { x, y, z: -5.0 },
+      direction: Vector {
+        x: 0.0,
+        y: 0.0,
+        z: 1.0,
+      },
+      time: 0.0,
+      channel: None,
+    }
+  }
+
+  #[test]
+  fn difference_takes_a_bite() {
+    let apple = apple_and_bite(CsgOp::Difference);
+
+    // Away from the bite, it's just the sphere:
+    let hit = apple.intersects(&ray_at(-0.5, -0.5)).unwrap();
+    assert!(approx_eq(hit.t, 5.0 - 0.5f64.sqrt(), 1e-9));
+
+    // Inside the bite there's nothing left...
+    assert_eq!(apple.intersects(&ray_at(0.5, 0.5)), None);
+
+    // ...and looking into the bite from the side, we see the box's wall at
+    // `x = 0`, facing back out of the bite:
+    let ray = Ray {
+      origin: Vector {
+        x: 5.0,
+        y: 0.5,
+        z: 0.5,
+      },
+      direction: Vector {
+        x: -1.0,
+        y: 0.0,
+        z: 0.0,
+      },
+      time: 0.0,
+      channel: None,
+    };
+    let hit = apple.intersects(&ray).unwrap();
+    assert!(approx_eq(hit.t, 5.0, 1e-9));
+    assert_eq!(
+      hit.normal,
+      Vector {
+        x: 1.0,
+        y: 0.0,
+        z: 0.0,
+      }
+    );
+  }
+
+  #[test]
+  fn intersection_keeps_only_the_overlap() {
+    let overlap = apple_and_bite(CsgOp::Intersection);
+
+    assert_eq!(overlap.intersects(&ray_at(-0.5, -0.5)), None);
+    let hit = overlap.intersects(&ray_at(0.5, 0.5)).unwrap();
+    assert!(approx_eq(hit.t, 5.0 - 0.5f64.sqrt(), 1e-9));
+  }
+
+  #[test]
+  fn union_covers_both() {
+    let both = apple_and_bite(CsgOp::Union);
+
+    assert!(both.intersects(&ray_at(-0.5, -0.5)).is_some());
+    // The box sticks out past the sphere, and its front face is at z = -2:
+    let hit = both.intersects(&ray_at(1.5, 1.5)).unwrap();
+    assert!(approx_eq(hit.t, 3.0, 1e-9));
+    // Crossings inside the union (where the two overlap) are hidden:
+    assert_eq!(both.intersect_all(&ray_at(0.5, 0.5)).len(), 2);
+  }
+}
@@ -0,0 +1,218 @@
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::scene::{Hit, Renderable};
+use crate::vector::Vector;
+
+/// A solid, axis-aligned box.
+#[derive(Clone)]
+pub struct Cuboid {
+  pub bounds: Aabb,
+  pub material: Arc<dyn Material>,
+}
+
+impl Cuboid {
+  pub fn new(bounds: Aabb, material: Arc<dyn Material>) -> Self {
+    Cuboid { bounds, material }
+  }
+
+  /// Where the line through `ray` enters and leaves the box, if it touches it
+  /// at all.
+  fn slabs(&self, ray: &Ray) -> Option<(f64, f64)> {
+    // A box is the overlap of three "slabs", one per axis, each bounded by a
+    // pair of parallel planes. The line is inside the box for the span of `t`
+    // where it's inside all three at once.
+    let mut near = f64::NEG_INFINITY;
+    let mut far = f64::INFINITY;
+    for &(origin, direction, min, max) in [
+      (
+        ray.origin.x,
+        ray.direction.x,
+        self.bounds.min.x,
+        self.bounds.max.x,
+      ),
+      (
+        ray.origin.y,
+        ray.direction.y,
+        self.bounds.min.y,
+        self.bounds.max.y,
+      ),
+      (
+        ray.origin.z,
+        ray.direction.z,
+        self.bounds.min.z,
+        self.bounds.max.z,
+      ),
+    ]
+    .iter()
+    {
+      if direction == 0.0 {
+        // Parallel to this slab; we're either always in it or never are:
+        if origin < min || origin > max {
+          return None;
+        }
+        continue;
+      }
+      let t0 = (min - origin) / direction;
+      let t1 = (max - origin) / direction;
+      near = near.max(t0.min(t1));
+      far = far.min(t0.max(t1));
+    }
+
+    if near > far {
+      None
+    } else {
+      Some((near, far))
+    }
+  }
+
+  fn hit_at(&self, ray: &Ray, t: f64) -> Hit {
+    Hit {
+      t,
+      normal: self.normal_at(&(ray.origin + ray.direction * t)),
+      uv: None,
+    }
+  }
+
+  fn normal_at(&self, point: &Vector) -> Vector {
+    // Whichever face `point` is closest to, measured relative to the box's size
+    // so that flat boxes don't favor their big faces.
+    let half_size = self.bounds.diagonal() / 2.0;
+    let p = point - self.bounds.center();
+    let (x, y, z) = (p.x / half_size.x, p.y / half_size.y, p.z / half_size.z);
+    let (ax, ay, az) = (x.abs(), y.abs(), z.abs());
+    if ax >= ay && ax >= az {
+      Vector {
+        x: x.signum(),
+        y: 0.0,
+        z: 0.0,
+      }
+    } else if ay >= az {
+      Vector {
+        x: 0.0,
+        y: y.signum(),
+        z: 0.0,
+      }
+    } else {
+      Vector {
+        x: 0.0,
+        y: 0.0,
+        z: z.signum(),
+      }
+    }
+  }
+}
+
+impl Renderable for Cuboid {
+  fn intersects(&self, ray: &Ray) -> Option<Hit> {
+    let (near, far) = self.slabs(ray)?;
+    if near >= 0.0001 {
+      Some(self.hit_at(ray, near))
+    } else if far >= 0.0001 {
+      // We're inside the box:
+      Some(self.hit_at(ray, far))
+    } else {
+      None
+    }
+  }
+
+  fn material(&self) -> &dyn Material {
+    &*self.material
+  }
+
+  fn set_material(&mut self, material: Arc<dyn Material>) {
+    self.material = material;
+  }
+
+  fn bounds(&self) -> Option<Aabb> {
+    Some(self.bounds)
+  }
+
+  fn intersect_all(&self, ray: &Ray) -> Vec<(Hit, bool)> {
+    match self.slabs(ray) {
+      Some((near, far)) if near < far => {
+        vec![
+          (self.hit_at(ray, near), true),
+          (self.hit_at(ray, far), false),
+        ]
+      }
+      _ => vec![],
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::material::MIRROR;
+  use crate::vector::{approx_eq, EPSILON};
+
+  fn unit_cube() -> Cuboid {
+    Cuboid::new(
+      Aabb::new(
+        Vector {
+          x: -1.0,
+          y: -1.0,
+          z: -1.0,
+        },
+        Vector {
+          x: 1.0,
+          y: 1.0,
+          z: 1.0,
+        },
+      ),
+      Arc::new(MIRROR),
+    )
+  }
+
+  #[test]
+  fn hits_the_near_face() {
+    let cube = unit_cube();
+    let ray = Ray {
+      origin: Vector {
+        x: 0.5,
+        y: 0.5,
+        z: -5.0,
+      },
+      direction: Vector {
+        x: 0.0,
+        y: 0.0,
+        z: 1.0,
+      },
+      time: 0.0,
+      channel: None,
+    };
+
+    let hit = cube.intersects(&ray).unwrap();
+    assert!(approx_eq(hit.t, 4.0, EPSILON));
+    assert_eq!(
+      hit.normal,
+      Vector {
+        x: 0.0,
+        y: 0.0,
+        z: -1.0,
+      }
+    );
+  }
+
+  #[test]
+  fn misses_beside_the_box() {
+    let ray = Ray {
+      origin: Vector {
+        x: 1.5,
+        y: 0.0,
+        z: -5.0,
+      },
+      direction: Vector {
+        x: 0.0,
+        y: 0.0,
+        z: 1.0,
+      },
+      time: 0.0,
+      channel: None,
+    };
+    assert_eq!(unit_cube().intersects(&ray), None);
+  }
+}
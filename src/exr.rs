@@ -0,0 +1,167 @@
+use std::io::{self, Write};
+
+use crate::material::HDRColor;
+
+/// Write `pixels` (`width` by `height`, one row after another from the top) as
+/// an OpenEXR image, keeping full 32-bit float precision in linear space so
+/// nothing brighter than white gets clamped.
+///
+/// This is the simplest flavor of EXR there is: a single part of uncompressed
+/// scanlines, with `R`, `G` and `B` float channels.
+pub fn write_exr<W: Write>(
+  out: &mut W,
+  width: usize,
+  height: usize,
+  pixels: &[HDRColor],
+) -> io::Result<()> {
+  assert_eq!(pixels.len(), width * height);
+
+  let mut header = vec![];
+  // Magic number, then version 2 with none of the flags set (so: scanlines,
+  // short names, single part):
+  header.extend_from_slice(&20000630i32.to_le_bytes());
+  header.extend_from_slice(&2i32.to_le_bytes());
+
+  // Channels have to be listed in alphabetical order:
+  let mut channels = vec![];
+  for name in &["B", "G", "R"] {
+    channels.extend_from_slice(name.as_bytes());
+    channels.push(0);
+    // FLOAT, not perceptually linear, three reserved bytes, and sampled at
+    // every pixel in both directions:
+    channels.extend_from_slice(&2i32.to_le_bytes());
+    channels.extend_from_slice(&[0, 0, 0, 0]);
+    channels.extend_from_slice(&1i32.to_le_bytes());
+    channels.extend_from_slice(&1i32.to_le_bytes());
+  }
+  channels.push(0);
+  attribute(&mut header, "channels", "chlist", &channels);
+
+  attribute(&mut header, "compression", "compression", &[0]);
+  let mut window = vec![];
+  for &coordinate in &[0, 0, width as i32 - 1, height as i32 - 1] {
+    window.extend_from_slice(&coordinate.to_le_bytes());
+  }
+  attribute(&mut header, "dataWindow", "box2i", &window);
+  attribute(&mut header, "displayWindow", "box2i", &window);
+  // Top row first:
+  attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+  attribute(
+    &mut header,
+    "pixelAspectRatio",
+    "float",
+    &1.0f32.to_le_bytes(),
+  );
+  attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+  attribute(
+    &mut header,
+    "screenWindowWidth",
+    "float",
+    &1.0f32.to_le_bytes(),
+  );
+  header.push(0);
+
+  // The header is followed by where to find each scanline, which are all the
+  // same size and come straight after this table:
+  let line_size = 8 + width * 3 * 4;
+  let first_line = header.len() + height * 8;
+  for y in 0..height {
+    header.extend_from_slice(&((first_line + y * line_size) as u64).to_le_bytes());
+  }
+  out.write_all(&header)?;
+
+  let mut line = Vec::with_capacity(line_size);
+  for (y, row) in pixels.chunks(width.max(1)).enumerate() {
+    line.clear();
+    line.extend_from_slice(&(y as i32).to_le_bytes());
+    line.extend_from_slice(&((width * 3 * 4) as i32).to_le_bytes());
+    for channel in &[|c: &HDRColor| c.b, |c: &HDRColor| c.g, |c: &HDRColor| c.r] {
+      for pixel in row {
+        line.extend_from_slice(&channel(pixel).to_le_bytes());
+      }
+    }
+    out.write_all(&line)?;
+  }
+  Ok(())
+}
+
+fn attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+  header.extend_from_slice(name.as_bytes());
+  header.push(0);
+  header.extend_from_slice(kind.as_bytes());
+  header.push(0);
+  header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+  header.extend_from_slice(value);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // Just enough of a reader to get back what `write_exr` wrote.
+  fn read_exr(bytes: &[u8]) -> (usize, usize, Vec<HDRColor>) {
+    let i32_at =
+      |at: usize| i32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]]);
+    let f32_at = |at: usize| f32::from_bits(i32_at(at) as u32);
+    let string_at = |at: usize| {
+      let end = at + bytes[at..].iter().position(|&b| b == 0).unwrap();
+      (std::str::from_utf8(&bytes[at..end]).unwrap(), end + 1)
+    };
+
+    assert_eq!(i32_at(0), 20000630);
+    let mut at = 8;
+    let (mut width, mut height) = (0, 0);
+    loop {
+      let (name, after_name) = string_at(at);
+      if name.is_empty() {
+        at = after_name;
+        break;
+      }
+      let (_, after_kind) = string_at(after_name);
+      let size = i32_at(after_kind) as usize;
+      let value = after_kind + 4;
+      if name == "dataWindow" {
+        width = (i32_at(value + 8) + 1) as usize;
+        height = (i32_at(value + 12) + 1) as usize;
+      }
+      at = value + size;
+    }
+
+    let mut pixels = vec![];
+    for y in 0..height {
+      let offset_at = at + y * 8;
+      let line = i32_at(offset_at) as usize;
+      assert_eq!(i32_at(line), y as i32);
+      let data = line + 8;
+      for x in 0..width {
+        pixels.push(HDRColor {
+          b: f32_at(data + x * 4),
+          g: f32_at(data + (width + x) * 4),
+          r: f32_at(data + (2 * width + x) * 4),
+        });
+      }
+    }
+    (width, height, pixels)
+  }
+
+  #[test]
+  fn bright_pixels_round_trip_without_clamping() {
+    let pixels: Vec<HDRColor> = (0..6)
+      .map(|i| HDRColor {
+        r: 4.0,
+        g: i as f32 * 0.5,
+        b: 1e-3,
+      })
+      .collect();
+    let mut bytes = vec![];
+    write_exr(&mut bytes, 3, 2, &pixels).unwrap();
+
+    let (width, height, read) = read_exr(&bytes);
+    assert_eq!((width, height), (3, 2));
+    for (read, written) in read.iter().zip(pixels.iter()) {
+      assert_eq!(read.r, written.r);
+      assert_eq!(read.g, written.g);
+      assert_eq!(read.b, written.b);
+    }
+  }
+}
@@ -0,0 +1,30 @@
+#[macro_use]
+extern crate impl_ops;
+extern crate rayon;
+extern crate sdl2;
+
+pub mod aabb;
+pub mod animation;
+pub mod camera;
+pub mod cli;
+pub mod cone;
+pub mod csg;
+pub mod cuboid;
+pub mod exr;
+pub mod material;
+pub mod mesh;
+pub mod noise;
+pub mod obj;
+pub mod plane;
+pub mod postprocess;
+pub mod ray;
+pub mod render;
+pub mod sampling;
+pub mod scene;
+pub mod settings;
+pub mod shard;
+pub mod sphere;
+pub mod texture;
+pub mod torus;
+pub mod triangle;
+pub mod vector;
@@ -1,64 +1,54 @@
-#[macro_use]
-extern crate impl_ops;
-extern crate rayon;
+extern crate racy;
 extern crate sdl2;
 
 use core::f64::consts::PI;
-use rand::prelude::thread_rng;
-use rand::seq::SliceRandom;
-use rayon::prelude::*;
 use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
+use sdl2::image::SaveSurface;
+use sdl2::keyboard::{Keycode, Scancode};
+use sdl2::mouse::MouseButton;
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::surface::Surface;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-pub mod camera;
-pub mod material;
-pub mod plane;
-pub mod ray;
-pub mod scene;
-pub mod sphere;
-pub mod vector;
-
-use crate::camera::*;
-use crate::material::*;
-use crate::plane::*;
-use crate::ray::*;
-use crate::scene::*;
-use crate::sphere::*;
-use crate::vector::*;
-
-const SCREEN_WIDTH: u32 = 320;
-const SCREEN_HEIGHT: u32 = 320;
+use racy::animation::*;
+use racy::camera::*;
+use racy::cli::*;
+use racy::material::*;
+use racy::plane::*;
+use racy::render::*;
+use racy::scene::*;
+use racy::settings::*;
+use racy::sphere::*;
+use racy::vector::*;
 
 const SCREEN_SCALE: u32 = 3;
 
-const WHITE: DiffuseColor = DiffuseColor {
-    color: HDRColor {
-        r: 1.0,
-        g: 1.0,
-        b: 1.0,
-    },
-};
+// Keyboard camera controls, in meters and radians per second:
+const MOVE_SPEED: f64 = 3.0;
+const TURN_SPEED: f64 = 1.5;
 
-const RED: DiffuseColor = DiffuseColor {
-    color: HDRColor {
-        r: 0.92,
-        g: 0.2,
-        b: 0.1,
-    },
-};
+// While the camera moves (and for a moment after), only one pixel in every
+// `PREVIEW_SCALE` by `PREVIEW_SCALE` block is traced, so it keeps up:
+const PREVIEW_SCALE: u32 = 4;
+const SETTLE_TIME: Duration = Duration::from_millis(300);
 
-const GREEN: DiffuseColor = DiffuseColor {
-    color: HDRColor {
-        r: 0.2,
-        g: 0.92,
-        b: 0.1,
-    },
-};
+const WHITE: DiffuseColor = DiffuseColor::new(1.0, 1.0, 1.0);
+const RED: DiffuseColor = DiffuseColor::new(0.92, 0.2, 0.1);
+const GREEN: DiffuseColor = DiffuseColor::new(0.2, 0.92, 0.1);
 
-fn basic_scene() -> Scene {
-    let mut lights: Vec<Light> = vec![];
+// What `M` cycles the clicked object's material through:
+fn material_palette() -> Vec<Arc<dyn Material>> {
+    vec![
+        Arc::new(WHITE),
+        Arc::new(MIRROR),
+        Arc::new(GLASS),
+        Arc::new(DEBUG_NORMALS),
+    ]
+}
 
-    lights.push(Light {
+fn basic_scene(settings: RenderSettings) -> Scene {
+    let lights: Vec<Light> = vec![Light {
         color: HDRColor {
             r: 3.0,
             g: 3.0,
@@ -70,152 +60,188 @@ fn basic_scene() -> Scene {
             z: 8.0,
         },
         radius: 0.0,
-    });
+    }];
 
-    Scene {
-        bg_color: HDRColor {
-            // r: (98.0 / 255.0),
-            // g: (192.0 / 255.0),
-            // b: (255.0 / 255.0),
-            r: 0.0,
-            g: 0.0,
-            b: 0.0,
+    let cam = *Camera::new(
+        Vector {
+            x: 0.0,
+            y: 0.0, // meters
+            z: 0.0,
         },
-        lights,
-        cam: Camera::new(
+        45.0,
+        settings.width,
+        settings.height,
+    )
+    .set_angle(PI);
+    let mut scene = Scene::new(cam, settings);
+    scene.background = Background::Solid(HDRColor {
+        // r: (98.0 / 255.0),
+        // g: (192.0 / 255.0),
+        // b: (255.0 / 255.0),
+        r: 0.0,
+        g: 0.0,
+        b: 0.0,
+    });
+    scene.lights = lights;
+    scene.renderables = vec![
+        Box::new(Sphere::new(
+            Vector {
+                x: -2.0,
+                y: 1.0,
+                z: 12.0,
+            },
+            1.0,
+            Arc::new(WHITE),
+        )),
+        Box::new(Sphere::new(
             Vector {
                 x: 0.0,
-                y: 0.0, // meters
+                y: 0.0,
+                z: 8.0,
+            },
+            1.0,
+            Arc::new(MIRROR),
+        )),
+        Box::new(Sphere::new(
+            Vector {
+                x: 2.0,
+                y: 1.0,
+                z: 8.0,
+            },
+            1.0,
+            Arc::new(GLASS),
+        )),
+        // "Floor"
+        Box::new(Plane::new(
+            Vector {
+                x: 0.0,
+                y: -1.0,
+                z: 0.0,
+            },
+            Vector {
+                x: 0.0,
+                y: 1.0,
                 z: 0.0,
             },
-            45.0,
-            SCREEN_WIDTH,
-            SCREEN_HEIGHT,
-        ),
-        renderables: vec![
-            Box::new(Sphere::new(
-                Vector {
-                    x: -2.0,
-                    y: 1.0,
-                    z: 12.0,
-                },
-                1.0,
-                &WHITE,
-            )),
-            Box::new(Sphere::new(
-                Vector {
-                    x: 0.0,
-                    y: 0.0,
-                    z: 8.0,
-                },
-                1.0,
-                &MIRROR,
-            )),
-            Box::new(Sphere::new(
-                Vector {
-                    x: 2.0,
-                    y: 1.0,
-                    z: 8.0,
-                },
-                1.0,
-                &GLASS,
-            )),
-            // "Floor"
-            Box::new(Plane::new(
-                Vector {
-                    x: 0.0,
-                    y: -1.0,
-                    z: 0.0,
-                },
-                Vector {
-                    x: 0.0,
-                    y: 1.0,
-                    z: 0.0,
-                },
-                &WHITE,
-            )),
-            // "Back wall"
-            Box::new(Plane::new(
-                Vector {
-                    x: 0.0,
-                    y: 0.0,
-                    z: 14.0,
-                },
-                Vector {
-                    x: 0.0,
-                    y: 0.0,
-                    z: -1.0,
-                },
-                &MIRROR,
-            )),
-            // "Left wall"
-            Box::new(Plane::new(
-                Vector {
-                    x: 4.0,
-                    y: 0.0,
-                    z: 0.0,
-                },
-                Vector {
-                    x: -1.0,
-                    y: 0.0,
-                    z: 0.0,
-                },
-                &RED,
-            )),
-            // "Right wall"
-            Box::new(Plane::new(
-                Vector {
-                    x: -4.0,
-                    y: 0.0,
-                    z: 0.0,
-                },
-                Vector {
-                    x: 1.0,
-                    y: 0.0,
-                    z: 0.0,
-                },
-                &GREEN,
-            )),
-            // "Front wall"
-            Box::new(Plane::new(
-                Vector {
-                    x: 0.0,
-                    y: 0.0,
-                    z: -4.0,
-                },
-                Vector {
-                    x: 0.0,
-                    y: 0.0,
-                    z: 1.0,
-                },
-                &WHITE,
-            )),
-            // // "Ceiling"
-            Box::new(Plane::new(
-                Vector {
-                    x: 0.0,
-                    y: 8.0,
-                    z: 0.0,
-                },
-                Vector {
-                    x: 0.0,
-                    y: -1.0,
-                    z: 0.0,
-                },
-                &WHITE,
-            )),
-        ],
+            Arc::new(WHITE),
+        )),
+        // "Back wall"
+        Box::new(Plane::new(
+            Vector {
+                x: 0.0,
+                y: 0.0,
+                z: 14.0,
+            },
+            Vector {
+                x: 0.0,
+                y: 0.0,
+                z: -1.0,
+            },
+            Arc::new(MIRROR),
+        )),
+        // "Left wall"
+        Box::new(Plane::new(
+            Vector {
+                x: 4.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            Vector {
+                x: -1.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            Arc::new(RED),
+        )),
+        // "Right wall"
+        Box::new(Plane::new(
+            Vector {
+                x: -4.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            Vector {
+                x: 1.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            Arc::new(GREEN),
+        )),
+        // "Front wall"
+        Box::new(Plane::new(
+            Vector {
+                x: 0.0,
+                y: 0.0,
+                z: -4.0,
+            },
+            Vector {
+                x: 0.0,
+                y: 0.0,
+                z: 1.0,
+            },
+            Arc::new(WHITE),
+        )),
+        // // "Ceiling"
+        Box::new(Plane::new(
+            Vector {
+                x: 0.0,
+                y: 8.0,
+                z: 0.0,
+            },
+            Vector {
+                x: 0.0,
+                y: -1.0,
+                z: 0.0,
+            },
+            Arc::new(WHITE),
+        )),
+    ];
+    scene
+}
+
+// The scene to render, surrounded by the `--environment` image if there is one.
+fn load_scene(options: &Options) -> Scene {
+    let mut scene = basic_scene(options.settings);
+    if let Some(path) = &options.environment {
+        let environment = if path.ends_with(".hdr") {
+            Background::load_hdr(path)
+        } else {
+            Background::load_equirect(path)
+        };
+        match environment {
+            Ok(environment) => scene.background = environment,
+            Err(message) => {
+                eprintln!("couldn't load environment: {}", message);
+                std::process::exit(1);
+            }
+        }
     }
+    scene
 }
 
 pub fn main() {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\n{}", message, USAGE);
+            std::process::exit(1);
+        }
+    };
+
+    if options.headless {
+        render_headless(&options);
+        return;
+    }
+
+    let settings = options.settings;
+
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
     let window = video_subsystem
         .window(
             "racy",
-            SCREEN_WIDTH * SCREEN_SCALE,
-            SCREEN_HEIGHT * SCREEN_SCALE,
+            settings.width * SCREEN_SCALE,
+            settings.height * SCREEN_SCALE,
         )
         .position_centered()
         .build()
@@ -228,22 +254,47 @@ pub fn main() {
     let mut screen_texture = texture_creator
         .create_texture_streaming(
             texture_creator.default_pixel_format(),
-            SCREEN_WIDTH,
-            SCREEN_HEIGHT,
+            settings.width,
+            settings.height,
         )
         .unwrap();
     screen_texture.set_blend_mode(sdl2::render::BlendMode::Blend);
     let mut event_pump = sdl_context.event_pump().unwrap();
     let mut tick: f64 = 0.0;
+    let mut last_frame = Instant::now();
+    let mut last_moved: Option<Instant> = None;
+    let mut history = History::new(0.8);
 
-    let mut scene = basic_scene();
+    let mut scene = load_scene(&options);
+    let animations: Vec<Box<dyn Animation>> = vec![
+        Box::new(OrbitLight {
+            light: 0,
+            center: Vector {
+                x: 0.0,
+                y: 0.0,
+                z: 7.0,
+            },
+            radius: 3.2,
+            speed: 0.03,
+        }),
+        Box::new(BobLight {
+            light: 0,
+            height: 3.2,
+            amplitude: 2.0,
+            speed: 0.02,
+        }),
+    ];
 
     // scene.lights.clear(); // Turn off all lights
 
-    canvas.set_draw_color::<HDRColor>(scene.bg_color.into());
+    // The last object clicked on, if any, and where it is in the palette:
+    let palette = material_palette();
+    let mut selected: Option<usize> = None;
+    let mut palette_idx = 0;
+
+    canvas.set_draw_color(BLACK);
     canvas.clear();
     canvas.present();
-    scene.cam.set_angle(PI);
 
     'running: loop {
         for event in event_pump.poll_iter() {
@@ -253,15 +304,99 @@ pub fn main() {
                     keycode: Some(Keycode::Escape),
                     ..
                 } => break 'running,
+                // Brighten or darken by a stop:
+                Event::KeyDown {
+                    keycode: Some(Keycode::Equals),
+                    ..
+                }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::KpPlus),
+                    ..
+                } => scene.settings.exposure *= 2.0,
+                Event::KeyDown {
+                    keycode: Some(Keycode::Minus),
+                    ..
+                }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::KpMinus),
+                    ..
+                } => scene.settings.exposure /= 2.0,
+                // Select (and report) what's under the cursor:
+                Event::MouseButtonDown {
+                    mouse_btn: MouseButton::Left,
+                    x,
+                    y,
+                    ..
+                } => {
+                    let (x, y) = (x as u32 / SCREEN_SCALE, y as u32 / SCREEN_SCALE);
+                    selected = scene.pick(x, y);
+                    match selected {
+                        Some(idx) => println!(
+                            "object {} ({})",
+                            idx,
+                            scene.renderables[idx].material().name()
+                        ),
+                        None => println!("nothing there"),
+                    }
+                }
+                // Change what the selected object is made of:
+                Event::KeyDown {
+                    keycode: Some(Keycode::M),
+                    ..
+                } => {
+                    if let Some(idx) = selected {
+                        palette_idx = (palette_idx + 1) % palette.len();
+                        let material = Arc::clone(&palette[palette_idx]);
+                        println!("object {} is now {}", idx, material.name());
+                        scene.renderable_mut(idx).set_material(material);
+                        history.reset();
+                    }
+                }
                 _ => {}
             }
         }
 
+        // Held keys move the camera by however long the last frame took, so
+        // it moves at the same speed whatever the frame rate:
+        let now = Instant::now();
+        let dt = now.duration_since(last_frame).as_secs_f64();
+        last_frame = now;
+        let keys = event_pump.keyboard_state();
+        let held = |scancode| {
+            if keys.is_scancode_pressed(scancode) {
+                1.0
+            } else {
+                0.0
+            }
+        };
+        let forward = held(Scancode::W) - held(Scancode::S);
+        let right = held(Scancode::D) - held(Scancode::A);
+        // The camera can't pitch, so up and down raise and lower it instead:
+        let up = held(Scancode::Up) - held(Scancode::Down);
+        let turn = held(Scancode::Left) - held(Scancode::Right);
+        if forward != 0.0 || right != 0.0 || up != 0.0 {
+            scene.cam.walk(
+                forward * MOVE_SPEED * dt,
+                right * MOVE_SPEED * dt,
+                up * MOVE_SPEED * dt,
+            );
+            last_moved = Some(now);
+            history.reset();
+        }
+        if turn != 0.0 {
+            let angle = scene.cam.angle + turn * TURN_SPEED * dt;
+            scene.cam.set_angle(angle);
+            last_moved = Some(now);
+            history.reset();
+        }
+        let settling = last_moved.is_some_and(|moved| now.duration_since(moved) < SETTLE_TIME);
+        scene.settings.preview_scale = if settling { PREVIEW_SCALE } else { 1 };
+
         canvas.clear();
 
         screen_texture
-            .with_lock(None, |mut screen, _size| {
-                render(&scene, &mut screen);
+            .with_lock(None, |screen, _size| {
+                render_with_history(&scene, screen, &mut history);
             })
             .unwrap();
         canvas
@@ -272,42 +407,59 @@ pub fn main() {
         // scene.cam.eye.x = 3.2 * (tick * 0.03).sin();
         // scene.cam.eye.z = -2.0 + 1.0 * (tick * 0.03).cos();
         // scene.cam.eye.y = 0.2 + 1.0 * (tick * 0.01).sin();
-        scene.lights[0].center.x = 3.2 * (tick * 0.03).sin();
-        scene.lights[0].center.z = 7.0 + 3.2 * (tick * 0.03).cos();
-        scene.lights[0].center.y = 3.2 + 2.0 * (tick * 0.02).cos();
+        for animation in &animations {
+            animation.update(&mut scene, tick);
+        }
         tick += 1.0;
     }
 }
 
-const EXPOSURE: f32 = 1.0;
-const GAMMA: f32 = 1.0;
+// Render a single frame straight to a PNG (or, given a `.exr` output, a
+// floating-point OpenEXR image), without ever touching SDL's video subsystem
+// (so this works on machines without a display).
+fn render_headless(options: &Options) {
+    let settings = options.settings;
+    let mut scene = load_scene(options);
+    scene.settings.progress = true;
 
-fn render(scene: &Scene, screen: &mut [u8]) {
-    let cam = scene.cam;
-    let screen_width = cam.screen_width as usize;
-    screen.par_chunks_mut(4).enumerate().for_each(|(i, pixel)| {
-        let x = i % screen_width;
-        let y = i / screen_width;
+    if options.output.ends_with(".exr") {
+        if let Err(error) = render_to_exr(&scene, &options.output) {
+            exit_unsaved(&options.output, &error.to_string());
+        }
+        return;
+    }
 
-        let pixel_ray = cam.get_ray_from_uv(x as f32, y as f32);
+    let mut pixels = vec![0; settings.width as usize * settings.height as usize * 4];
+    if options.aovs {
+        let mut aovs = render_aovs(&scene, &mut pixels);
+        let (width, height) = (settings.width, settings.height);
+        let path = aov_path(&options.output, "albedo");
+        save_png(&path, &mut aovs.albedo, width, height)
+            .unwrap_or_else(|message| exit_unsaved(&path, &message));
+        let path = aov_path(&options.output, "normal");
+        save_png(&path, &mut aovs.normal, width, height)
+            .unwrap_or_else(|message| exit_unsaved(&path, &message));
+    } else {
+        render(&scene, &mut pixels);
+    }
+    save_png(
+        &options.output,
+        &mut pixels,
+        settings.width,
+        settings.height,
+    )
+    .unwrap_or_else(|message| exit_unsaved(&options.output, &message));
+}
 
-        let mut rng = thread_rng();
+// Give up on a headless render because `path` couldn't be written.
+fn exit_unsaved(path: &str, message: &str) -> ! {
+    eprintln!("couldn't save {}: {}", path, message);
+    std::process::exit(1);
+}
 
-        match scene.cast(&pixel_ray, 0) {
-            None => (),
-            Some(intersection) => {
-                let point = pixel_ray.origin + pixel_ray.direction * intersection.t;
-                let object = &scene.renderables[intersection.renderable_idx];
-                let normal = object.normal(&point);
-                let color = object
-                    .material()
-                    .color_at(&mut rng, &point, &normal, &pixel_ray, &scene, 0);
-                let display_rgb = color.into_display_rgb(EXPOSURE, GAMMA);
-                pixel[0] = display_rgb.b;
-                pixel[1] = display_rgb.g;
-                pixel[2] = display_rgb.r;
-                pixel[3] = display_rgb.a;
-            }
-        }
-    });
+// `pixels` are laid out the way `render` writes them: B, G, R, A bytes, which
+// is ARGB8888 on little-endian machines.
+fn save_png(path: &str, pixels: &mut [u8], width: u32, height: u32) -> Result<(), String> {
+    let surface = Surface::from_data(pixels, width, height, width * 4, PixelFormatEnum::ARGB8888)?;
+    surface.save(path)
 }
@@ -1,46 +1,193 @@
-use rand::prelude::ThreadRng;
-use rand::seq::SliceRandom;
+use rand::{Rng, RngCore};
 use sdl2::pixels::Color;
 use std::ops;
+use std::sync::Arc;
 
+use crate::noise::Noise;
 use crate::ray::Ray;
+use crate::sampling::jittered_grid;
 use crate::scene::Scene;
+use crate::settings::LightSampling;
+use crate::sphere::Sphere;
+use crate::texture::Texture;
 use crate::vector::Vector;
 
-pub trait Material: Sync {
+pub trait Material: Send + Sync {
+  // `rng` is a trait object (rather than a generic parameter) so that
+  // `Material`s can still be used as `dyn Material`; this lets callers pass
+  // either a thread-local or a seeded generator.
   fn color_at(
     &self,
-    rng: &mut ThreadRng,
+    rng: &mut dyn RngCore,
     point: &Vector,
     normal: &Vector,
     ray: &Ray,
     scene: &Scene,
     depth: u8,
   ) -> HDRColor;
+
+  /// The surface's own color at `point`, regardless of lighting: how much of
+  /// each channel it passes on. Surfaces that don't tint light (like glass)
+  /// are white.
+  fn albedo(&self, _point: &Vector) -> HDRColor {
+    HDRColor {
+      r: 1.0,
+      g: 1.0,
+      b: 1.0,
+    }
+  }
+
+  /// Light given off by the surface itself, for materials that glow. Objects
+  /// made of these light the rest of the scene like `Light`s do (see
+  /// `Scene::emitters`).
+  fn emission(&self) -> Option<HDRColor> {
+    None
+  }
+
+  /// A short, human-readable name for the kind of material this is, like
+  /// `"Mirror"`.
+  fn name(&self) -> &'static str {
+    let path = std::any::type_name::<Self>();
+    path.rsplit("::").next().unwrap_or(path)
+  }
 }
 
-#[derive(Copy, Clone)]
+/// A linear RGB color. The default is black.
+#[derive(Debug, PartialEq, Copy, Clone, Default)]
 pub struct HDRColor {
   pub r: f32,
   pub g: f32,
   pub b: f32,
 }
 
+/// Encode a linear intensity with the sRGB transfer function, which is what
+/// displays expect; storing linear values directly makes midtones too dark.
+pub fn linear_to_srgb(x: f32) -> f32 {
+  let x = x.clamp(0.0, 1.0);
+  if x <= 0.0031308 {
+    x * 12.92
+  } else {
+    1.055 * x.powf(1.0 / 2.4) - 0.055
+  }
+}
+
+/// Operator used to squeeze unbounded HDR intensities into the displayable
+/// `[0, 1]` range.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ToneMap {
+  /// Hard-clip anything brighter than 1.0.
+  Clamp,
+  /// `x / (1 + x)` per channel; never quite reaches white.
+  Reinhard,
+  /// Narkowicz's fit of the ACES filmic curve.
+  Aces,
+}
+
+impl ToneMap {
+  pub fn apply(&self, x: f32) -> f32 {
+    match self {
+      ToneMap::Clamp => x.clamp(0.0, 1.0),
+      ToneMap::Reinhard => {
+        let x = x.max(0.0);
+        x / (1.0 + x)
+      }
+      ToneMap::Aces => {
+        let x = x.max(0.0);
+        ((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)).clamp(0.0, 1.0)
+      }
+    }
+  }
+}
+
+/// Inverse of `linear_to_srgb`; turns an sRGB-encoded value (as stored in
+/// regular image files) back into linear intensity.
+pub fn srgb_to_linear(x: f32) -> f32 {
+  let x = x.clamp(0.0, 1.0);
+  if x <= 0.04045 {
+    x / 12.92
+  } else {
+    ((x + 0.055) / 1.055).powf(2.4)
+  }
+}
+
 impl HDRColor {
-  pub fn into_display_rgb(&self, exposure: f32, gamma: f32) -> Color {
+  pub const fn new(r: f32, g: f32, b: f32) -> Self {
+    HDRColor { r, g, b }
+  }
+
+  /// A color as picked in an image editor or color picker, whose channels are
+  /// sRGB-encoded in `[0, 1]`.
+  pub fn from_srgb_f32(r: f32, g: f32, b: f32) -> Self {
+    HDRColor::new(srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b))
+  }
+
+  /// Like `from_srgb_f32`, with channels in `[0, 255]` (as in `#rrggbb`).
+  pub fn from_srgb8(r: u8, g: u8, b: u8) -> Self {
+    HDRColor::from_srgb_f32(r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0)
+  }
+
+  pub fn into_display_rgb(&self, exposure: f32, tone_map: ToneMap) -> Color {
     Color {
-      r: (255.0 * (self.r * exposure).powf(gamma).min(1.0).max(0.0)).round() as u8,
-      g: (255.0 * (self.g * exposure).powf(gamma).min(1.0).max(0.0)).round() as u8,
-      b: (255.0 * (self.b * exposure).powf(gamma).min(1.0).max(0.0)).round() as u8,
+      r: (255.0 * linear_to_srgb(tone_map.apply(self.r * exposure))).round() as u8,
+      g: (255.0 * linear_to_srgb(tone_map.apply(self.g * exposure))).round() as u8,
+      b: (255.0 * linear_to_srgb(tone_map.apply(self.b * exposure))).round() as u8,
       a: 255,
     }
   }
+
+  /// The color `t` of the way from `self` to `other`. Like `Vector::lerp`,
+  /// `t` isn't clamped, so values outside `[0, 1]` extrapolate (and can go
+  /// negative).
+  pub fn lerp(&self, other: &HDRColor, t: f32) -> HDRColor {
+    self * (1.0 - t) + other * t
+  }
+
+  /// How bright this color looks, weighting each channel by how sensitive our
+  /// eyes are to it (Rec. 709). White is 1.
+  pub fn luminance(&self) -> f32 {
+    0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+  }
+
+  /// Channel-wise minimum of two colors.
+  pub fn min(&self, other: &HDRColor) -> HDRColor {
+    HDRColor {
+      r: self.r.min(other.r),
+      g: self.g.min(other.g),
+      b: self.b.min(other.b),
+    }
+  }
+
+  /// Channel-wise maximum of two colors.
+  pub fn max(&self, other: &HDRColor) -> HDRColor {
+    HDRColor {
+      r: self.r.max(other.r),
+      g: self.g.max(other.g),
+      b: self.b.max(other.b),
+    }
+  }
+
+  /// Every channel clamped to `[lo, hi]`.
+  pub fn clamp(&self, lo: f32, hi: f32) -> HDRColor {
+    HDRColor {
+      r: self.r.clamp(lo, hi),
+      g: self.g.clamp(lo, hi),
+      b: self.b.clamp(lo, hi),
+    }
+  }
 }
 
 pub struct DiffuseColor {
   pub color: HDRColor,
 }
 
+impl DiffuseColor {
+  pub const fn new(r: f32, g: f32, b: f32) -> Self {
+    DiffuseColor {
+      color: HDRColor::new(r, g, b),
+    }
+  }
+}
+
 impl_op_ex!(*|a: &HDRColor, b: f32| -> HDRColor {
   HDRColor {
     r: a.r * b,
@@ -106,23 +253,31 @@ impl_op_ex!(-|a: &HDRColor, b: &HDRColor| -> HDRColor {
   }
 });
 
-const BLACK: HDRColor = HDRColor {
-  r: 0.0,
-  g: 0.0,
-  b: 0.0,
-};
+pub const BLACK: HDRColor = HDRColor::new(0.0, 0.0, 0.0);
+
+impl std::iter::Sum for HDRColor {
+  fn sum<I: Iterator<Item = HDRColor>>(colors: I) -> HDRColor {
+    colors.fold(BLACK, |sum, color| sum + color)
+  }
+}
+
+impl<'a> std::iter::Sum<&'a HDRColor> for HDRColor {
+  fn sum<I: Iterator<Item = &'a HDRColor>>(colors: I) -> HDRColor {
+    colors.fold(BLACK, |sum, color| sum + color)
+  }
+}
 
 impl Material for DiffuseColor {
   fn color_at(
     &self,
-    rng: &mut ThreadRng,
+    rng: &mut dyn RngCore,
     point: &Vector,
     normal: &Vector,
-    _: &Ray,
+    ray: &Ray,
     scene: &Scene,
     depth: u8,
   ) -> HDRColor {
-    if depth > MAX_DEPTH {
+    if depth > scene.settings.max_depth {
       return BLACK;
     }
 
@@ -143,46 +298,264 @@ impl Material for DiffuseColor {
     //
     // To do this, all we need is the angle between the light source and our
     // normal.
-    let mut color = HDRColor {
-      r: 0.0,
-      g: 0.0,
-      b: 0.0,
+    //
+    // We start with the scene's ambient light, which reaches every surface
+    // equally:
+    let mut color = scene.ambient;
+    let shadow_ray_origin = Ray::offset_origin(point, normal, scene.settings.epsilon);
+    // Glowing objects are lights too, except that they block their own shadow
+    // rays, so we have to look past them. Without next event estimation, we
+    // only find lights by bouncing into them (see below).
+    let (lights, emitters) = if scene.settings.next_event_estimation {
+      (&scene.lights[..], scene.emitters())
+    } else {
+      (&[][..], &[][..])
     };
-    let shadow_ray_origin = point + normal * 0.0001;
-    for light in &scene.lights {
+    let lights = lights.iter().map(|&light| (light, None)).chain(
+      emitters
+        .iter()
+        .map(|&(renderable_idx, light)| (light, Some(renderable_idx))),
+    );
+    for (light, emitter_idx) in lights {
+      // Where in the cone of directions the light fills (see
+      // `Light::sample_from`) to send shadow rays:
       let light_samples: usize = 1 + (light.radius * 5.0).round() as usize;
+      let cone_samples: Vec<(f32, f32)> = match scene.settings.light_sampling {
+        LightSampling::Random => (0..light_samples).map(|_| (rng.gen(), rng.gen())).collect(),
+        LightSampling::Stratified => {
+          let strata = (light_samples as f64).sqrt().ceil() as usize;
+          jittered_grid(rng, strata)
+        }
+      };
+      let light_samples = cone_samples.len();
+      let center_distance = light.center.distance(point);
 
-      for _ in 0..light_samples {
-        // 1. Draw a vector from our intersection point to the light source:
-        let to_light = (light.center + (Vector::random_norm() * light.radius as f64)) - point;
-        let dist_to_light = to_light.length();
-        match scene.cast(
-          &Ray {
-            origin: shadow_ray_origin,
-            direction: to_light.normalized(),
+      for (u, v) in cone_samples {
+        // 1. Pick a direction toward some point on the light's surface:
+        let (direction, distance, weight) = light.sample_from(point, u as f64, v as f64);
+        // 2. Use the dot product to calculate theta.cos(); light from behind the
+        //    surface doesn't reach it at all:
+        let theta_cos = direction.dot(normal);
+        if theta_cos <= 0.0 {
+          continue;
+        }
+        let shadow_ray = Ray {
+          origin: shadow_ray_origin,
+          direction,
+          time: ray.time,
+          channel: ray.channel,
+        };
+        let shadowed = match emitter_idx {
+          None => scene.cast_any(&shadow_ray, distance),
+          Some(emitter_idx) => match scene.cast(&shadow_ray, depth + 1) {
+            Some(intersection) => intersection.renderable_idx != emitter_idx,
+            None => false,
           },
-          depth + 1,
-        ) {
-          None => (),
-          Some(intersection) => {
-            if intersection.t < dist_to_light {
-              continue;
-            }
-          }
+        };
+        if shadowed {
+          continue;
+        }
+        // 3. The sample's weight already includes the inverse-square law:
+        let mut intensity = weight / light_samples as f64;
+        // (`Light`s have always fallen off linearly, though, and their colors
+        // are tuned for that. Glowing objects have to match what bouncing into
+        // them adds, so they keep the real inverse-square falloff.)
+        if emitter_idx.is_none() {
+          intensity *= center_distance;
         }
-        // 2. Use the dot product to calculate theta.cos()
-        let theta_cos = to_light.dot(&normal);
-        // 3. We employ the inverse-square law to determine how intense the light
-        //    should be:
-        let intensity = 1.0 / ((to_light.length_squared()) * light_samples as f64);
         // 4. Finally, we just multiply our lighting intensity by the cosine of the
         //    angle between our normal and the incoming light:
         color += light.color * (intensity as f32) * (theta_cos as f32);
       }
     }
 
+    // Indirect light: whatever we see in one random direction (favoring those
+    // near the normal, as light from there counts for more). With next event
+    // estimation we've already added light coming straight from glowing
+    // objects, so we mustn't count it again if we happen to bounce into one.
+    if depth < scene.settings.diffuse_bounces {
+      let bounce_ray = Ray {
+        origin: shadow_ray_origin,
+        direction: Vector::random_cosine_hemisphere(rng, normal),
+        time: ray.time,
+        channel: ray.channel,
+      };
+      color += match scene.cast(&bounce_ray, depth + 1) {
+        Some(intersection) => {
+          let object = &scene.renderables[intersection.renderable_idx];
+          if scene.settings.next_event_estimation && object.material().emission().is_some() {
+            BLACK
+          } else {
+            let point = bounce_ray.origin + bounce_ray.direction * intersection.hit.t;
+            object.material().color_at(
+              rng,
+              &point,
+              &intersection.hit.normal,
+              &bounce_ray,
+              scene,
+              depth + 1,
+            )
+          }
+        }
+        None => scene.background(&bounce_ray),
+      };
+    }
+
     self.color * color
   }
+
+  fn albedo(&self, _point: &Vector) -> HDRColor {
+    self.color
+  }
+}
+
+/// A diffuse surface alternating between two colors in squares of side
+/// `scale`, laid out across the X and Z axes (so it reads as a checkerboard on
+/// floors and ceilings).
+pub struct Checkerboard {
+  pub a: HDRColor,
+  pub b: HDRColor,
+  pub scale: f64,
+}
+
+impl Checkerboard {
+  /// The color of the square `point` falls in.
+  pub fn pattern(&self, point: &Vector) -> HDRColor {
+    let cell_x = (point.x / self.scale).floor() as i64;
+    let cell_z = (point.z / self.scale).floor() as i64;
+    if (cell_x + cell_z) % 2 == 0 {
+      self.a
+    } else {
+      self.b
+    }
+  }
+}
+
+impl Material for Checkerboard {
+  fn color_at(
+    &self,
+    rng: &mut dyn RngCore,
+    point: &Vector,
+    normal: &Vector,
+    ray: &Ray,
+    scene: &Scene,
+    depth: u8,
+  ) -> HDRColor {
+    DiffuseColor {
+      color: self.pattern(point),
+    }
+    .color_at(rng, point, normal, ray, scene, depth)
+  }
+
+  fn albedo(&self, point: &Vector) -> HDRColor {
+    self.pattern(point)
+  }
+}
+
+/// A diffuse surface blotched with `noise`, blending from `color_low` where
+/// it's lowest to `color_high` where it's highest. `scale` is roughly how big
+/// the blotches are.
+pub struct NoiseMaterial {
+  pub noise: Noise,
+  pub scale: f64,
+  pub color_low: HDRColor,
+  pub color_high: HDRColor,
+}
+
+impl NoiseMaterial {
+  /// The color at `point`.
+  pub fn pattern(&self, point: &Vector) -> HDRColor {
+    let t = ((self.noise.at(&(point / self.scale)) + 1.0) / 2.0) as f32;
+    self.color_low.lerp(&self.color_high, t)
+  }
+}
+
+impl Material for NoiseMaterial {
+  fn color_at(
+    &self,
+    rng: &mut dyn RngCore,
+    point: &Vector,
+    normal: &Vector,
+    ray: &Ray,
+    scene: &Scene,
+    depth: u8,
+  ) -> HDRColor {
+    DiffuseColor {
+      color: self.pattern(point),
+    }
+    .color_at(rng, point, normal, ray, scene, depth)
+  }
+
+  fn albedo(&self, point: &Vector) -> HDRColor {
+    self.pattern(point)
+  }
+}
+
+/// Veined marble: stripes of `vein` across `color`, running perpendicular to
+/// the X axis `frequency` times per unit and warped by `octaves` of turbulence.
+pub struct Marble {
+  pub noise: Noise,
+  pub octaves: u32,
+  pub frequency: f64,
+  pub color: HDRColor,
+  pub vein: HDRColor,
+}
+
+impl Marble {
+  /// The color at `point`.
+  pub fn pattern(&self, point: &Vector) -> HDRColor {
+    let phase = self.frequency * point.x + 4.0 * self.noise.turbulence(point, self.octaves);
+    let t = ((1.0 + (phase * std::f64::consts::PI).sin()) / 2.0) as f32;
+    self.vein.lerp(&self.color, t)
+  }
+}
+
+impl Material for Marble {
+  fn color_at(
+    &self,
+    rng: &mut dyn RngCore,
+    point: &Vector,
+    normal: &Vector,
+    ray: &Ray,
+    scene: &Scene,
+    depth: u8,
+  ) -> HDRColor {
+    DiffuseColor {
+      color: self.pattern(point),
+    }
+    .color_at(rng, point, normal, ray, scene, depth)
+  }
+
+  fn albedo(&self, point: &Vector) -> HDRColor {
+    self.pattern(point)
+  }
+}
+
+/// A surface that glows with `color`, and lights up its surroundings.
+pub struct Emissive {
+  pub color: HDRColor,
+}
+
+impl Material for Emissive {
+  fn color_at(
+    &self,
+    _: &mut dyn RngCore,
+    _: &Vector,
+    _: &Vector,
+    _: &Ray,
+    _: &Scene,
+    _depth: u8,
+  ) -> HDRColor {
+    self.color
+  }
+
+  fn albedo(&self, _point: &Vector) -> HDRColor {
+    self.color
+  }
+
+  fn emission(&self) -> Option<HDRColor> {
+    Some(self.color)
+  }
 }
 
 pub struct DebugNormals;
@@ -190,18 +563,18 @@ pub struct DebugNormals;
 impl Material for DebugNormals {
   fn color_at(
     &self,
-    _: &mut ThreadRng,
+    _: &mut dyn RngCore,
     _: &Vector,
     normal: &Vector,
     _: &Ray,
     _: &Scene,
     _depth: u8,
   ) -> HDRColor {
-    return HDRColor {
+    HDRColor {
       r: ((1.0 + normal.x) / 2.0) as f32,
       g: ((1.0 + normal.y) / 2.0) as f32,
       b: (0.5 - normal.z) as f32,
-    };
+    }
   }
 }
 
@@ -211,64 +584,92 @@ pub struct Mirror {
   reflectivity: f32,
 }
 
-const MAX_DEPTH: u8 = 15;
 impl Material for Mirror {
   fn color_at(
     &self,
-    rng: &mut ThreadRng,
+    rng: &mut dyn RngCore,
     point: &Vector,
     normal: &Vector,
     ray: &Ray,
     scene: &Scene,
     depth: u8,
   ) -> HDRColor {
-    if depth > MAX_DEPTH {
+    if depth > scene.settings.max_depth {
       return BLACK;
     }
-    let neg_norm = normal * -1.0;
-    let mirror_direction = ray.direction - neg_norm * 2.0 * (ray.direction.dot(&neg_norm));
     let ray_reflection = Ray {
-      origin: point + normal * 0.001,
-      direction: mirror_direction,
+      origin: Ray::offset_origin(point, normal, scene.settings.epsilon),
+      direction: ray.direction.reflect(normal),
+      time: ray.time,
+      channel: ray.channel,
     };
     (match scene.cast(&ray_reflection, depth + 1) {
       Some(intersection) => {
-        let point = ray_reflection.origin + ray_reflection.direction * intersection.t;
+        let point = ray_reflection.origin + ray_reflection.direction * intersection.hit.t;
         let object = &scene.renderables[intersection.renderable_idx];
-        let normal = object.normal(&point);
         let color = object.material().color_at(
           rng,
           &point,
-          &normal,
+          &intersection.hit.normal,
           &ray_reflection,
-          &scene,
+          scene,
           intersection.depth + 1,
         );
         color
       }
-      None => scene.bg_color,
+      None => scene.background(&ray_reflection),
     }) * self.reflectivity
   }
+
+  fn albedo(&self, _point: &Vector) -> HDRColor {
+    HDRColor {
+      r: self.reflectivity,
+      g: self.reflectivity,
+      b: self.reflectivity,
+    }
+  }
 }
 pub const MIRROR: Mirror = Mirror { reflectivity: 0.8 };
 
 pub struct Refractor {
   refractive_index: f64,
+  /// How much more strongly shorter wavelengths bend: the `B` in Cauchy's
+  /// equation `n(λ) = A + B/λ²` (λ in micrometers). `refractive_index` is the
+  /// index for green light. With any dispersion, each sample follows just one
+  /// of the red, green or blue channels, so white light fans out into a
+  /// rainbow; `0.0` bends every channel the same.
+  dispersion: f64,
 }
-impl Material for Refractor {
-  fn color_at(
+
+/// Representative wavelengths for the red, green and blue channels, in
+/// micrometers.
+const WAVELENGTHS: [f64; 3] = [0.65, 0.55, 0.45];
+
+impl Refractor {
+  pub const fn new(refractive_index: f64, dispersion: f64) -> Self {
+    Refractor {
+      refractive_index,
+      dispersion,
+    }
+  }
+
+  /// The refractive index for light of the given wavelength (in micrometers).
+  fn index_at(&self, wavelength: f64) -> f64 {
+    let green = WAVELENGTHS[1];
+    self.refractive_index
+      + self.dispersion * (1.0 / (wavelength * wavelength) - 1.0 / (green * green))
+  }
+
+  /// Where `ray` goes after crossing the surface at `point`, for a material
+  /// with the given `refractive_index`, starting `epsilon` off the surface.
+  fn refracted_ray(
     &self,
-    rng: &mut ThreadRng,
+    refractive_index: f64,
     point: &Vector,
     normal_: &Vector,
     ray: &Ray,
-    scene: &Scene,
-    depth: u8,
-  ) -> HDRColor {
-    if depth > MAX_DEPTH {
-      return BLACK;
-    }
-
+    epsilon: f64,
+  ) -> Ray {
     let mut ray_dot_n = ray.direction.dot(normal_);
     let mut normal = *normal_;
     let (n_in, n_out) = if ray_dot_n > 0.0 {
@@ -276,11 +677,11 @@ impl Material for Refractor {
       // If `ray_dot_n` is positive, then our ray is going in roughly the same
       // direction as the normal, which means we are _exiting_ our material into
       // air:
-      (self.refractive_index, AIR.refractive_index)
+      (refractive_index, AIR.refractive_index)
     } else {
       ray_dot_n = -ray_dot_n;
       // ...otherwise we are _entering_ our material into air:
-      (AIR.refractive_index, self.refractive_index)
+      (AIR.refractive_index, refractive_index)
     };
 
     // To constrain our refraction ray to the plane of incidence, we need a
@@ -291,50 +692,1128 @@ impl Material for Refractor {
     // https://www.scratchapixel.com/lessons/3d-basic-rendering/introduction-to-shading/reflection-refraction-fresnel
     let mu = n_in / n_out;
     let k = 1.0 - (mu * mu) * (1.0 - (ray_dot_n * ray_dot_n));
-    let mut refraction_direction =
-      (ray.direction * (if k < 0.0 { 0.0 } else { mu })) + (normal * (mu * ray_dot_n - k.sqrt()));
-    refraction_direction.normalize();
+    if k < 0.0 {
+      // Past the critical angle nothing gets through; it's all reflected back
+      // (total internal reflection), just like a mirror:
+      Ray {
+        origin: Ray::offset_origin(point, &normal, epsilon),
+        direction: ray.direction.reflect(&normal),
+        time: ray.time,
+        channel: ray.channel,
+      }
+    } else {
+      let mut refraction_direction = (ray.direction * mu) + (normal * (mu * ray_dot_n - k.sqrt()));
+      refraction_direction.normalize();
+      Ray {
+        origin: Ray::offset_origin(point, &(normal * -1.0), epsilon),
+        direction: refraction_direction,
+        time: ray.time,
+        channel: ray.channel,
+      }
+    }
+  }
+}
+
+impl Material for Refractor {
+  fn color_at(
+    &self,
+    rng: &mut dyn RngCore,
+    point: &Vector,
+    normal: &Vector,
+    ray: &Ray,
+    scene: &Scene,
+    depth: u8,
+  ) -> HDRColor {
+    if depth > scene.settings.max_depth {
+      return BLACK;
+    }
 
+    // With dispersion, follow a single channel's wavelength from here on, and
+    // count it three times over to make up for the two we skipped. A ray that
+    // has already been split up keeps its channel (and whoever split it does
+    // the counting):
+    let split = if self.dispersion != 0.0 && ray.channel.is_none() {
+      Some(rng.gen_range(0, 3))
+    } else {
+      None
+    };
+    let channel = split.or(ray.channel);
+    let refractive_index = match channel {
+      Some(channel) if self.dispersion != 0.0 => self.index_at(WAVELENGTHS[channel]),
+      _ => self.refractive_index,
+    };
     let ray_refraction = Ray {
-      origin: point - normal * 0.0001,
-      direction: refraction_direction,
+      channel,
+      ..self.refracted_ray(refractive_index, point, normal, ray, scene.settings.epsilon)
     };
 
-    match scene.cast(&ray_refraction, depth + 1) {
+    let color = match scene.cast(&ray_refraction, depth + 1) {
       Some(intersection) => {
-        let point = ray_refraction.origin + ray_refraction.direction * intersection.t;
+        let point = ray_refraction.origin + ray_refraction.direction * intersection.hit.t;
         let object = &scene.renderables[intersection.renderable_idx];
-        let normal = object.normal(&point);
         let color = object.material().color_at(
           rng,
           &point,
-          &normal,
+          &intersection.hit.normal,
           &ray_refraction,
-          &scene,
+          scene,
           intersection.depth + 1,
         );
         color
       }
-      None => scene.bg_color,
+      None => scene.background(&ray_refraction),
+    };
+    match split {
+      None => color,
+      Some(0) => HDRColor {
+        r: color.r * 3.0,
+        g: 0.0,
+        b: 0.0,
+      },
+      Some(1) => HDRColor {
+        r: 0.0,
+        g: color.g * 3.0,
+        b: 0.0,
+      },
+      Some(_) => HDRColor {
+        r: 0.0,
+        g: 0.0,
+        b: color.b * 3.0,
+      },
+    }
+  }
+}
+pub const GLASS: Refractor = Refractor::new(1.52, 0.0);
+pub const WATER: Refractor = Refractor::new(1.33, 0.0);
+pub const AIR: Refractor = Refractor::new(1.0, 0.0);
+
+/// Layers two materials: each time it's shaded, a surface looks like `a` with
+/// probability `factor` and like `b` otherwise, so averaged over many samples it
+/// comes out as a blend of the two.
+pub struct Mix {
+  pub a: Arc<dyn Material>,
+  pub b: Arc<dyn Material>,
+  pub factor: f32,
+}
+
+impl Material for Mix {
+  fn color_at(
+    &self,
+    rng: &mut dyn RngCore,
+    point: &Vector,
+    normal: &Vector,
+    ray: &Ray,
+    scene: &Scene,
+    depth: u8,
+  ) -> HDRColor {
+    let material = if rng.gen::<f32>() < self.factor {
+      &self.a
+    } else {
+      &self.b
+    };
+    material.color_at(rng, point, normal, ray, scene, depth)
+  }
+
+  fn albedo(&self, point: &Vector) -> HDRColor {
+    self
+      .b
+      .albedo(point)
+      .lerp(&self.a.albedo(point), self.factor)
+  }
+}
+
+/// Shades like `base`, but with the surface's normal bent by a tangent-space
+/// normal map, for bumps and grooves without any extra geometry.
+///
+/// Each of `map`'s pixels holds a normal, with its X (red) pointing the way `u`
+/// increases, Y (green) toward the top of the image, and Z (blue) straight out
+/// of the surface, each mapped from `[-1, 1]` to `[0, 1]`; load them with
+/// `Texture::load_data`.
+///
+/// For now the UVs and tangents come from the surface normal, mapped the way
+/// `Sphere::uv` maps it, so this only lines up on spheres.
+pub struct NormalMapped {
+  pub base: Arc<dyn Material>,
+  pub map: Texture,
+}
+
+impl NormalMapped {
+  /// The normal to shade with in place of the surface's own `normal`.
+  fn shading_normal(&self, normal: &Vector) -> Vector {
+    let (u, v) = Sphere::uv(normal);
+    let (tangent, bitangent) = Sphere::tangent_frame(normal);
+    let texel = self.map.sample(u, v);
+    let (x, y, z) = (
+      texel.r as f64 * 2.0 - 1.0,
+      texel.g as f64 * 2.0 - 1.0,
+      texel.b as f64 * 2.0 - 1.0,
+    );
+    // The image's "up" is toward decreasing `v`, against the bitangent:
+    (tangent * x - bitangent * y + normal * z).normalized()
+  }
+}
+
+impl Material for NormalMapped {
+  fn color_at(
+    &self,
+    rng: &mut dyn RngCore,
+    point: &Vector,
+    normal: &Vector,
+    ray: &Ray,
+    scene: &Scene,
+    depth: u8,
+  ) -> HDRColor {
+    let normal = self.shading_normal(normal);
+    self.base.color_at(rng, point, &normal, ray, scene, depth)
+  }
+
+  fn albedo(&self, point: &Vector) -> HDRColor {
+    self.base.albedo(point)
+  }
+
+  fn emission(&self) -> Option<HDRColor> {
+    self.base.emission()
+  }
+}
+
+/// Shades surfaces by how exposed they are rather than by the scene's lights:
+/// white out in the open, darkening in creases and where objects touch. Good
+/// for judging shapes at a glance.
+pub struct AmbientOcclusion {
+  /// How many rays to cast from each hit; more means less noise.
+  pub samples: usize,
+  /// How far away something can be and still count as blocking a ray.
+  pub radius: f64,
+}
+
+impl Material for AmbientOcclusion {
+  fn color_at(
+    &self,
+    rng: &mut dyn RngCore,
+    point: &Vector,
+    normal: &Vector,
+    ray: &Ray,
+    scene: &Scene,
+    _depth: u8,
+  ) -> HDRColor {
+    let origin = Ray::offset_origin(point, normal, scene.settings.epsilon);
+    let escaped = (0..self.samples)
+      .filter(|_| {
+        let occlusion_ray = Ray {
+          origin,
+          direction: Vector::random_cosine_hemisphere(rng, normal),
+          time: ray.time,
+          channel: ray.channel,
+        };
+        !scene.cast_any(&occlusion_ray, self.radius)
+      })
+      .count();
+
+    let exposure = escaped as f32 / self.samples.max(1) as f32;
+    HDRColor {
+      r: exposure,
+      g: exposure,
+      b: exposure,
     }
   }
 }
-pub const GLASS: Refractor = Refractor {
-  refractive_index: 1.52,
-};
-pub const WATER: Refractor = Refractor {
-  refractive_index: 1.33,
-};
-pub const AIR: Refractor = Refractor {
-  refractive_index: 1.0,
-};
 
-impl Into<Color> for HDRColor {
-  fn into(self) -> Color {
+impl From<HDRColor> for Color {
+  fn from(color: HDRColor) -> Color {
     Color::RGB(
-      (self.r * 255.0).floor().min(255.0).max(0.0) as u8,
-      (self.g * 255.0).floor().min(255.0).max(0.0) as u8,
-      (self.b * 255.0).floor().min(255.0).max(0.0) as u8,
+      (color.r * 255.0).floor().clamp(0.0, 255.0) as u8,
+      (color.g * 255.0).floor().clamp(0.0, 255.0) as u8,
+      (color.b * 255.0).floor().clamp(0.0, 255.0) as u8,
     )
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::vector::approx_eq;
+
+  #[test]
+  fn srgb_encodes_midtones() {
+    let gray = HDRColor {
+      r: 0.5,
+      g: 0.5,
+      b: 0.5,
+    };
+    let display = gray.into_display_rgb(1.0, ToneMap::Clamp);
+    assert_eq!(display.r, 188);
+    assert_eq!(display.g, 188);
+    assert_eq!(display.b, 188);
+  }
+
+  #[test]
+  fn srgb_input_is_decoded_to_linear() {
+    let gray = HDRColor::from_srgb8(188, 188, 188);
+    assert!((gray.r - 0.5).abs() < 0.005);
+    assert_eq!((gray.r, gray.g), (gray.g, gray.b));
+
+    // ...which encodes right back to what we started with:
+    let display = HDRColor::from_srgb8(12, 128, 250).into_display_rgb(1.0, ToneMap::Clamp);
+    assert_eq!((display.r, display.g, display.b), (12, 128, 250));
+
+    let white = HDRColor::from_srgb_f32(1.0, 1.0, 1.0);
+    assert_eq!((white.r, white.g, white.b), (1.0, 1.0, 1.0));
+  }
+
+  #[test]
+  fn color_min_max_and_clamp_work_per_channel() {
+    let a = HDRColor::new(-1.0, 0.5, 4.0);
+    let b = HDRColor::new(0.0, 0.25, 8.0);
+    let channels = |c: HDRColor| (c.r, c.g, c.b);
+    assert_eq!(channels(a.min(&b)), (-1.0, 0.25, 4.0));
+    assert_eq!(channels(a.max(&b)), (0.0, 0.5, 8.0));
+    assert_eq!(channels(a.clamp(0.0, 1.0)), (0.0, 0.5, 1.0));
+    // Colors already in range are left alone:
+    assert_eq!(channels(b.clamp(0.0, 8.0)), channels(b));
+  }
+
+  #[test]
+  fn colors_sum_channel_by_channel() {
+    let colors = [
+      HDRColor::new(1.0, 0.0, 0.5),
+      HDRColor::new(2.0, 0.25, 0.5),
+      HDRColor::new(0.5, 0.25, 3.0),
+    ];
+    let channels = |c: HDRColor| (c.r, c.g, c.b);
+    assert_eq!(channels(colors.iter().sum()), (3.5, 0.5, 4.0));
+    assert_eq!(channels(colors.iter().copied().sum()), (3.5, 0.5, 4.0));
+    // Nothing at all adds up to black, which is also the default:
+    assert_eq!(channels(colors[..0].iter().sum()), channels(BLACK));
+    assert_eq!(channels(HDRColor::default()), channels(BLACK));
+  }
+
+  #[test]
+  fn white_has_unit_luminance() {
+    assert!((HDRColor::new(1.0, 1.0, 1.0).luminance() - 1.0).abs() < 1e-6);
+    assert_eq!(BLACK.luminance(), 0.0);
+  }
+
+  #[test]
+  fn color_lerp_runs_from_self_to_other() {
+    let a = HDRColor {
+      r: 0.0,
+      g: 1.0,
+      b: 4.0,
+    };
+    let b = HDRColor {
+      r: 1.0,
+      g: 1.0,
+      b: 2.0,
+    };
+    let channels = |c: HDRColor| (c.r, c.g, c.b);
+    assert_eq!(channels(a.lerp(&b, 0.0)), channels(a));
+    assert_eq!(channels(a.lerp(&b, 1.0)), channels(b));
+    assert_eq!(channels(a.lerp(&b, 0.5)), (0.5, 1.0, 3.0));
+  }
+
+  #[test]
+  fn doubling_exposure_doubles_linear_output() {
+    let gray = HDRColor {
+      r: 0.2,
+      g: 0.2,
+      b: 0.2,
+    };
+    let linear =
+      |exposure| srgb_to_linear(gray.into_display_rgb(exposure, ToneMap::Clamp).r as f32 / 255.0);
+    let ratio = linear(2.0) / linear(1.0);
+    // Within the error of quantizing to 8 bits:
+    assert!((ratio - 2.0).abs() < 0.05, "{}", ratio);
+  }
+
+  #[test]
+  fn srgb_preserves_black_and_white() {
+    assert_eq!(BLACK.into_display_rgb(1.0, ToneMap::Clamp).r, 0);
+    let white = HDRColor {
+      r: 1.0,
+      g: 1.0,
+      b: 1.0,
+    };
+    assert_eq!(white.into_display_rgb(1.0, ToneMap::Clamp).r, 255);
+  }
+
+  #[test]
+  fn tone_maps_bright_values() {
+    let bright = HDRColor {
+      r: 2.0,
+      g: 2.0,
+      b: 2.0,
+    };
+    let clamped = bright.into_display_rgb(1.0, ToneMap::Clamp).r;
+    let reinhard = bright.into_display_rgb(1.0, ToneMap::Reinhard).r;
+    let aces = bright.into_display_rgb(1.0, ToneMap::Aces).r;
+
+    // Clamping throws away everything above 1.0...
+    assert_eq!(clamped, 255);
+    // ...while the curves keep some headroom, Reinhard more so than ACES:
+    assert!(reinhard < aces);
+    assert!(aces < 255);
+  }
+
+  #[test]
+  fn diffuse_shading_is_deterministic_with_a_seeded_rng() {
+    use crate::camera::Camera;
+    use crate::scene::{Background, Light};
+    use crate::settings::RenderSettings;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    let white = DiffuseColor {
+      color: HDRColor {
+        r: 1.0,
+        g: 1.0,
+        b: 1.0,
+      },
+    };
+    // An area light, so shading actually draws random numbers:
+    let scene = Scene {
+      cam: Camera::new(Vector::new(), 45.0, 1, 1),
+      renderables: vec![],
+      background: Background::Solid(BLACK),
+      lights: vec![Light {
+        center: Vector {
+          x: 0.0,
+          y: 2.0,
+          z: 0.0,
+        },
+        color: HDRColor {
+          r: 1.0,
+          g: 1.0,
+          b: 1.0,
+        },
+        radius: 0.5,
+      }],
+      ambient: BLACK,
+      settings: RenderSettings::default(),
+      emitters: Default::default(),
+    };
+    let up = Vector {
+      x: 0.0,
+      y: 1.0,
+      z: 0.0,
+    };
+    let ray = Ray {
+      origin: up,
+      direction: up * -1.0,
+      time: 0.0,
+      channel: None,
+    };
+    let shade = || {
+      let mut rng = StdRng::seed_from_u64(42);
+      white.color_at(&mut rng, &Vector::new(), &up, &ray, &scene, 0)
+    };
+
+    let (a, b) = (shade(), shade());
+    assert_eq!((a.r, a.g, a.b), (b.r, b.g, b.b));
+  }
+
+  #[test]
+  fn mix_at_full_factor_is_first_material() {
+    use crate::camera::Camera;
+    use crate::scene::Background;
+    use crate::settings::RenderSettings;
+
+    let scene = Scene {
+      cam: Camera::new(Vector::new(), 45.0, 1, 1),
+      renderables: vec![],
+      background: Background::Solid(BLACK),
+      lights: vec![],
+      ambient: BLACK,
+      settings: RenderSettings::default(),
+      emitters: Default::default(),
+    };
+    let mix = Mix {
+      a: Arc::new(DEBUG_NORMALS),
+      b: Arc::new(DiffuseColor { color: BLACK }),
+      factor: 1.0,
+    };
+    let normal = Vector {
+      x: 0.0,
+      y: 1.0,
+      z: 0.0,
+    };
+    let ray = Ray {
+      origin: normal,
+      direction: normal * -1.0,
+      time: 0.0,
+      channel: None,
+    };
+
+    let mut rng = rand::thread_rng();
+    let expected = DEBUG_NORMALS.color_at(&mut rng, &Vector::new(), &normal, &ray, &scene, 0);
+    for _ in 0..100 {
+      let color = mix.color_at(&mut rng, &Vector::new(), &normal, &ray, &scene, 0);
+      assert_eq!(
+        (color.r, color.g, color.b),
+        (expected.r, expected.g, expected.b)
+      );
+    }
+  }
+
+  #[test]
+  fn ambient_occlusion_in_the_open_is_white() {
+    use crate::camera::Camera;
+    use crate::plane::Plane;
+    use crate::scene::Background;
+    use crate::settings::RenderSettings;
+
+    let up = Vector {
+      x: 0.0,
+      y: 1.0,
+      z: 0.0,
+    };
+    // Just a floor, with nothing above it to block the sky:
+    let scene = Scene {
+      cam: Camera::new(Vector::new(), 45.0, 1, 1),
+      renderables: vec![Box::new(Plane::new(
+        Vector::new(),
+        up,
+        Arc::new(DiffuseColor { color: BLACK }),
+      ))],
+      background: Background::Solid(BLACK),
+      lights: vec![],
+      ambient: BLACK,
+      settings: RenderSettings::default(),
+      emitters: Default::default(),
+    };
+    let ao = AmbientOcclusion {
+      samples: 64,
+      radius: 1.0,
+    };
+    let ray = Ray {
+      origin: up,
+      direction: up * -1.0,
+      time: 0.0,
+      channel: None,
+    };
+
+    let color = ao.color_at(
+      &mut rand::thread_rng(),
+      &Vector::new(),
+      &up,
+      &ray,
+      &scene,
+      0,
+    );
+    assert!(approx_eq(color.r as f64, 1.0, 1e-6));
+  }
+
+  #[test]
+  fn stratified_light_sampling_softens_shadow_noise() {
+    use crate::aabb::Aabb;
+    use crate::camera::Camera;
+    use crate::cuboid::Cuboid;
+    use crate::scene::{Background, Light};
+    use crate::settings::{LightSampling, RenderSettings};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    let white = DiffuseColor {
+      color: HDRColor {
+        r: 1.0,
+        g: 1.0,
+        b: 1.0,
+      },
+    };
+    let up = Vector {
+      x: 0.0,
+      y: 1.0,
+      z: 0.0,
+    };
+    let ray = Ray {
+      origin: up,
+      direction: up * -1.0,
+      time: 0.0,
+      channel: None,
+    };
+    // A slab hiding the `x > 0` half of an area light from the origin, which
+    // puts the origin right in the middle of the penumbra:
+    let scene = |light_sampling| Scene {
+      cam: Camera::new(Vector::new(), 45.0, 1, 1),
+      renderables: vec![Box::new(Cuboid::new(
+        Aabb::new(
+          Vector {
+            x: 0.0,
+            y: 1.9,
+            z: -10.0,
+          },
+          Vector {
+            x: 10.0,
+            y: 2.1,
+            z: 10.0,
+          },
+        ),
+        Arc::new(MIRROR),
+      ))],
+      background: Background::Solid(BLACK),
+      lights: vec![Light {
+        center: Vector {
+          x: 0.0,
+          y: 4.0,
+          z: 0.0,
+        },
+        color: HDRColor {
+          r: 1.0,
+          g: 1.0,
+          b: 1.0,
+        },
+        // 4 samples, a perfect square, so both strategies cast as many rays:
+        radius: 0.6,
+      }],
+      ambient: BLACK,
+      settings: RenderSettings {
+        light_sampling,
+        ..RenderSettings::default()
+      },
+      emitters: Default::default(),
+    };
+    let variance = |light_sampling| {
+      let scene = scene(light_sampling);
+      let mut rng = StdRng::seed_from_u64(7);
+      let shades: Vec<f64> = (0..500)
+        .map(|_| {
+          white
+            .color_at(&mut rng, &Vector::new(), &up, &ray, &scene, 0)
+            .r as f64
+        })
+        .collect();
+      let mean = shades.iter().sum::<f64>() / shades.len() as f64;
+      shades.iter().map(|s| (s - mean) * (s - mean)).sum::<f64>() / shades.len() as f64
+    };
+
+    let random = variance(LightSampling::Random);
+    let stratified = variance(LightSampling::Stratified);
+    assert!(random > 0.0);
+    assert!(stratified < random / 2.0, "{} vs {}", stratified, random);
+  }
+
+  #[test]
+  fn sphere_lights_overhead_shade_like_point_lights() {
+    use crate::camera::Camera;
+    use crate::settings::RenderSettings;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    // A ball of light straight over a surface lights it exactly as much as a
+    // point at its center would, however big the ball is:
+    let up = Vector {
+      x: 0.0,
+      y: 1.0,
+      z: 0.0,
+    };
+    let ray = Ray {
+      origin: up,
+      direction: up * -1.0,
+      time: 0.0,
+      channel: None,
+    };
+    let shade = |radius| {
+      let mut scene = Scene::new(
+        Camera::new(Vector::new(), 45.0, 1, 1),
+        RenderSettings::default(),
+      );
+      scene.add_light(up * 3.0, HDRColor::new(1.0, 1.0, 1.0), radius);
+      let mut rng = StdRng::seed_from_u64(5);
+      let total: f32 = (0..2000)
+        .map(|_| {
+          DiffuseColor::new(1.0, 1.0, 1.0)
+            .color_at(&mut rng, &Vector::new(), &up, &ray, &scene, 0)
+            .r
+        })
+        .sum();
+      total / 2000.0
+    };
+
+    let point = shade(0.0);
+    assert!((point - 1.0 / 3.0).abs() < 1e-5, "{}", point);
+    for &radius in &[0.01, 0.5, 2.0] {
+      let sphere = shade(radius);
+      assert!((sphere - point).abs() < 0.01, "{} vs {}", sphere, point);
+    }
+  }
+
+  #[test]
+  fn glass_reflects_everything_past_the_critical_angle() {
+    use crate::camera::Camera;
+    use crate::scene::Background;
+    use crate::settings::RenderSettings;
+
+    // Dark below the horizon and bright above, so we can tell which way the
+    // ray went:
+    let scene = Scene {
+      background: Background::Gradient {
+        top: HDRColor {
+          r: 1.0,
+          g: 1.0,
+          b: 1.0,
+        },
+        bottom: BLACK,
+      },
+      ..Scene::new(
+        Camera::new(Vector::new(), 45.0, 1, 1),
+        RenderSettings::default(),
+      )
+    };
+    // From inside the glass, glancing up off its flat top surface:
+    let up = Vector {
+      x: 0.0,
+      y: 1.0,
+      z: 0.0,
+    };
+    let ray = Ray {
+      origin: Vector {
+        x: -1.0,
+        y: -0.3,
+        z: 0.0,
+      },
+      direction: Vector {
+        x: 1.0,
+        y: 0.3,
+        z: 0.0,
+      }
+      .normalized(),
+      time: 0.0,
+      channel: None,
+    };
+
+    let color = GLASS.color_at(
+      &mut rand::thread_rng(),
+      &Vector::new(),
+      &up,
+      &ray,
+      &scene,
+      0,
+    );
+    let reflected = scene.background.color(&ray.direction.reflect(&up));
+    assert!(approx_eq(color.r as f64, reflected.r as f64, 1e-6));
+    assert!(color.r < 0.5);
+  }
+
+  #[test]
+  fn emissive_objects_light_diffuse_surfaces() {
+    use crate::camera::Camera;
+    use crate::settings::RenderSettings;
+
+    let up = Vector {
+      x: 0.0,
+      y: 1.0,
+      z: 0.0,
+    };
+    // No lights at all, just a glowing ball hanging above the origin:
+    let mut scene = Scene::new(
+      Camera::new(Vector::new(), 45.0, 1, 1),
+      RenderSettings::default(),
+    );
+    scene.add_sphere(
+      up * 3.0,
+      0.5,
+      Arc::new(Emissive {
+        color: HDRColor {
+          r: 4.0,
+          g: 4.0,
+          b: 4.0,
+        },
+      }),
+    );
+    assert!(scene.lights.is_empty());
+    assert_eq!(scene.emitters().len(), 1);
+
+    let white = DiffuseColor {
+      color: HDRColor {
+        r: 1.0,
+        g: 1.0,
+        b: 1.0,
+      },
+    };
+    let ray = Ray {
+      origin: up,
+      direction: up * -1.0,
+      time: 0.0,
+      channel: None,
+    };
+    let color = white.color_at(
+      &mut rand::thread_rng(),
+      &Vector::new(),
+      &up,
+      &ray,
+      &scene,
+      0,
+    );
+    assert!(color.r > 0.0);
+  }
+
+  #[test]
+  fn next_event_estimation_is_less_noisy_than_bouncing() {
+    use crate::camera::Camera;
+    use crate::settings::RenderSettings;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    let up = Vector {
+      x: 0.0,
+      y: 1.0,
+      z: 0.0,
+    };
+    let ray = Ray {
+      origin: up,
+      direction: up * -1.0,
+      time: 0.0,
+      channel: None,
+    };
+    let white = DiffuseColor {
+      color: HDRColor {
+        r: 1.0,
+        g: 1.0,
+        b: 1.0,
+      },
+    };
+    // A small glowing ball over a spot on the floor, and nothing else:
+    let stats = |next_event_estimation| {
+      let mut scene = Scene::new(
+        Camera::new(Vector::new(), 45.0, 1, 1),
+        RenderSettings {
+          diffuse_bounces: 1,
+          next_event_estimation,
+          ..RenderSettings::default()
+        },
+      );
+      scene.add_sphere(
+        up * 2.0,
+        0.5,
+        Arc::new(Emissive {
+          color: HDRColor {
+            r: 1.0,
+            g: 1.0,
+            b: 1.0,
+          },
+        }),
+      );
+
+      let mut rng = StdRng::seed_from_u64(3);
+      let shades: Vec<f64> = (0..4000)
+        .map(|_| {
+          white
+            .color_at(&mut rng, &Vector::new(), &up, &ray, &scene, 0)
+            .r as f64
+        })
+        .collect();
+      let mean = shades.iter().sum::<f64>() / shades.len() as f64;
+      let variance =
+        shades.iter().map(|s| (s - mean) * (s - mean)).sum::<f64>() / shades.len() as f64;
+      (mean, variance)
+    };
+
+    let (nee_mean, nee_variance) = stats(true);
+    let (naive_mean, naive_variance) = stats(false);
+    // Both converge on the same answer (which for a ball this size and this
+    // far away is about 1/16)...
+    assert!((nee_mean - 0.0625).abs() < 0.005, "{}", nee_mean);
+    assert!((naive_mean - 0.0625).abs() < 0.01, "{}", naive_mean);
+    // ...but only one of them gets there quickly:
+    assert!(
+      nee_variance < naive_variance / 10.0,
+      "{} vs {}",
+      nee_variance,
+      naive_variance
+    );
+  }
+
+  #[test]
+  fn dispersion_bends_blue_more_than_red() {
+    use crate::settings::RenderSettings;
+
+    let prism = Refractor::new(1.52, 0.01);
+    let normal = Vector {
+      x: 0.0,
+      y: 1.0,
+      z: 0.0,
+    };
+    // Entering the glass from above, at an angle:
+    let ray = Ray::new(
+      Vector {
+        x: -1.0,
+        y: 1.0,
+        z: 0.0,
+      },
+      Vector {
+        x: 1.0,
+        y: -1.0,
+        z: 0.0,
+      },
+      0.0,
+    );
+    let refract = |wavelength| {
+      prism
+        .refracted_ray(
+          prism.index_at(wavelength),
+          &Vector::new(),
+          &normal,
+          &ray,
+          RenderSettings::default().epsilon,
+        )
+        .direction
+    };
+
+    let (red, green, blue) = (
+      refract(WAVELENGTHS[0]),
+      refract(WAVELENGTHS[1]),
+      refract(WAVELENGTHS[2]),
+    );
+    assert!(!red.approx_eq(&blue, 1e-6));
+    // Bending more means heading more steeply down, away from the surface:
+    assert!(blue.x < green.x && green.x < red.x);
+
+    // Without dispersion, every channel goes the same way:
+    assert_eq!(
+      GLASS.index_at(WAVELENGTHS[0]),
+      GLASS.index_at(WAVELENGTHS[2])
+    );
+  }
+
+  #[test]
+  fn dispersed_light_keeps_its_color_through_a_slab() {
+    use crate::aabb::Aabb;
+    use crate::camera::Camera;
+    use crate::cuboid::Cuboid;
+    use crate::plane::Plane;
+    use crate::settings::RenderSettings;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    // Shows which way light reaches it (in red and green), and where (in
+    // blue):
+    struct Backdrop;
+    impl Material for Backdrop {
+      fn color_at(
+        &self,
+        _rng: &mut dyn RngCore,
+        point: &Vector,
+        _normal: &Vector,
+        ray: &Ray,
+        _scene: &Scene,
+        _depth: u8,
+      ) -> HDRColor {
+        HDRColor::new(
+          ray.direction.x as f32,
+          ray.direction.y as f32,
+          point.x as f32,
+        )
+      }
+    }
+
+    let prism = Arc::new(Refractor::new(1.52, 0.01));
+    let up = Vector {
+      x: 0.0,
+      y: 1.0,
+      z: 0.0,
+    };
+    // A slab one unit thick with its top at `y = 0`, over a backdrop:
+    let scene = Scene::new(
+      Camera::new(Vector::new(), 45.0, 1, 1),
+      RenderSettings::default(),
+    )
+    .with(Cuboid::new(
+      Aabb::new(
+        Vector {
+          x: -10.0,
+          y: -1.0,
+          z: -10.0,
+        },
+        Vector {
+          x: 10.0,
+          y: 0.0,
+          z: 10.0,
+        },
+      ),
+      prism.clone(),
+    ))
+    .with(Plane::new(up * -3.0, up, Arc::new(Backdrop)));
+
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut through = |channel| {
+      let ray = Ray {
+        channel: Some(channel),
+        ..Ray::new(
+          Vector {
+            x: -1.0,
+            y: 1.0,
+            z: 0.0,
+          },
+          Vector {
+            x: 1.0,
+            y: -1.0,
+            z: 0.0,
+          },
+          0.0,
+        )
+      };
+      let seen = prism.color_at(&mut rng, &Vector::new(), &up, &ray, &scene, 0);
+      // Bending the same way going in and coming out leaves every color
+      // heading the way it came in:
+      let half = 0.5f32.sqrt();
+      assert!((seen.r - half).abs() < 1e-6 && (seen.g + half).abs() < 1e-6);
+      seen.b
+    };
+    // ...but blue, bent more steeply, drifts less far sideways on the way
+    // through:
+    assert!(through(2) < through(0) - 0.01);
+  }
+
+  #[test]
+  fn marble_varies_smoothly() {
+    let marble = Marble {
+      noise: Noise::new(42, 64),
+      octaves: 5,
+      frequency: 2.0,
+      color: HDRColor {
+        r: 0.9,
+        g: 0.9,
+        b: 0.85,
+      },
+      vein: HDRColor {
+        r: 0.2,
+        g: 0.2,
+        b: 0.25,
+      },
+    };
+
+    // Creeping along in tiny steps, the color never jumps...
+    let step = Vector {
+      x: 1e-4,
+      y: 0.7e-4,
+      z: 0.3e-4,
+    };
+    let mut point = Vector::new();
+    let (mut lightest, mut darkest) = (0.0f32, 1.0f32);
+    for _ in 0..100_000 {
+      let (here, next) = (marble.pattern(&point), marble.pattern(&(point + step)));
+      assert!((here.r - next.r).abs() < 0.01, "{:?} -> {:?}", here, next);
+      lightest = lightest.max(here.r);
+      darkest = darkest.min(here.r);
+      point += step;
+    }
+    // ...but over a longer stretch we cross a few veins:
+    assert!(lightest > 0.8 && darkest < 0.3);
+  }
+
+  fn normal_mapped(texel: HDRColor) -> NormalMapped {
+    NormalMapped {
+      base: Arc::new(DiffuseColor::new(1.0, 1.0, 1.0)),
+      map: Texture::new(1, 1, vec![texel]),
+    }
+  }
+
+  #[test]
+  fn flat_normal_map_changes_nothing() {
+    let material = normal_mapped(HDRColor::new(0.5, 0.5, 1.0));
+    let mut rng = rand::thread_rng();
+    for _ in 0..100 {
+      let normal = Vector::random_norm(&mut rng);
+      assert!(material.shading_normal(&normal).approx_eq(&normal, 1e-6));
+    }
+  }
+
+  #[test]
+  fn normal_map_tilts_along_the_tangents() {
+    // Facing +Z, east is +X and the top of the image is +Y:
+    let normal = Vector {
+      x: 0.0,
+      y: 0.0,
+      z: 1.0,
+    };
+    let half = 0.5f64.sqrt();
+
+    let east = normal_mapped(HDRColor::new(
+      0.5 + half as f32 / 2.0,
+      0.5,
+      0.5 + half as f32 / 2.0,
+    ));
+    let expected = Vector {
+      x: half,
+      y: 0.0,
+      z: half,
+    };
+    assert!(east.shading_normal(&normal).approx_eq(&expected, 1e-6));
+
+    let up = normal_mapped(HDRColor::new(
+      0.5,
+      0.5 + half as f32 / 2.0,
+      0.5 + half as f32 / 2.0,
+    ));
+    let expected = Vector {
+      x: 0.0,
+      y: half,
+      z: half,
+    };
+    assert!(up.shading_normal(&normal).approx_eq(&expected, 1e-6));
+  }
+
+  #[test]
+  fn scaled_epsilon_avoids_shadow_acne_in_huge_scenes() {
+    use crate::camera::Camera;
+    use crate::settings::RenderSettings;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    // A planet-sized ball, lit from straight above, seen at coordinates big
+    // enough that rounding error dwarfs the default epsilon:
+    let radius = 1e13;
+    let lit_fraction = |epsilon: f64| {
+      let settings = RenderSettings {
+        epsilon,
+        ..RenderSettings::default()
+      };
+      let mut scene = Scene::new(Camera::new(Vector::new(), 45.0, 16, 16), settings);
+      scene
+        .add_sphere(
+          Vector::new(),
+          radius,
+          Arc::new(DiffuseColor::new(1.0, 1.0, 1.0)),
+        )
+        .add_light(
+          Vector {
+            x: 0.0,
+            y: radius * 3.0,
+            z: 0.0,
+          },
+          HDRColor::new(1.0, 1.0, 1.0),
+          0.0,
+        );
+
+      let mut rng = StdRng::seed_from_u64(5);
+      let samples = 1000;
+      let lit = (0..samples)
+        .filter(|_| {
+          // Somewhere on the top of the ball, facing the light:
+          let normal = (Vector::random_norm(&mut rng)
+            + Vector {
+              x: 0.0,
+              y: 2.0,
+              z: 0.0,
+            })
+          .normalized();
+          let point = normal * radius;
+          let ray = Ray {
+            origin: point + normal * radius,
+            direction: normal * -1.0,
+            time: 0.0,
+            channel: None,
+          };
+          scene.renderables[0]
+            .material()
+            .color_at(&mut rng, &point, &normal, &ray, &scene, 0)
+            .r
+            > 0.0
+        })
+        .count();
+      lit as f64 / samples as f64
+    };
+
+    assert!(lit_fraction(RenderSettings::default().epsilon) < 0.9);
+    assert_eq!(lit_fraction(radius * 1e-9), 1.0);
+  }
+}
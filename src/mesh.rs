@@ -0,0 +1,211 @@
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::scene::{Hit, Renderable};
+use crate::triangle::Triangle;
+
+/// Leaves of a mesh's hierarchy hold at most this many triangles.
+const LEAF_SIZE: usize = 4;
+
+/// Lots of triangles (like a model from `load_mesh`) that act as one object,
+/// all made of the same material.
+///
+/// Testing a ray against each triangle in turn gets slow quickly, so the mesh
+/// keeps them in a bounding volume hierarchy: a tree of boxes, each holding
+/// either a few triangles or two smaller boxes. A ray only has to look inside
+/// the boxes it passes through, which takes it from thousands of tests to
+/// dozens.
+#[derive(Clone)]
+pub struct Mesh {
+  /// In tree order, so each leaf's triangles are next to each other.
+  triangles: Vec<Triangle>,
+  /// The root is the first node.
+  nodes: Vec<Node>,
+  material: Arc<dyn Material>,
+}
+
+#[derive(Clone)]
+struct Node {
+  bounds: Aabb,
+  contents: Contents,
+}
+
+#[derive(Clone)]
+enum Contents {
+  Triangles(Range<usize>),
+  /// Indexes of the two child nodes.
+  Children(usize, usize),
+}
+
+impl Mesh {
+  /// A mesh of `triangles`, all shaded with `material` (whatever they were
+  /// made of before).
+  pub fn new(mut triangles: Vec<Triangle>, material: Arc<dyn Material>) -> Self {
+    let mut nodes = vec![];
+    build(&mut triangles, 0, &mut nodes);
+    Mesh {
+      triangles,
+      nodes,
+      material,
+    }
+  }
+
+  pub fn triangles(&self) -> &[Triangle] {
+    &self.triangles
+  }
+}
+
+// Add a node for `triangles` (which start at `offset` in the whole mesh), and
+// everything under it, to `nodes`, reordering `triangles` as we go. Returns
+// the new node's index.
+fn build(triangles: &mut [Triangle], offset: usize, nodes: &mut Vec<Node>) -> usize {
+  let bounds = triangles
+    .iter()
+    .filter_map(|triangle| triangle.bounds())
+    .fold(Aabb::EMPTY, |bounds, triangle| bounds.union(&triangle));
+  let idx = nodes.len();
+  nodes.push(Node {
+    bounds,
+    contents: Contents::Triangles(offset..offset + triangles.len()),
+  });
+  if triangles.len() <= LEAF_SIZE {
+    return idx;
+  }
+
+  // Split down the middle of whichever way the triangles are most spread out:
+  let centroid = |triangle: &Triangle| {
+    let [a, b, c] = triangle.vertices;
+    (a + b + c) / 3.0
+  };
+  let spread = triangles
+    .iter()
+    .map(|triangle| {
+      let centroid = centroid(triangle);
+      Aabb::new(centroid, centroid)
+    })
+    .fold(Aabb::EMPTY, |bounds, centroid| bounds.union(&centroid))
+    .diagonal();
+  let axis = |triangle: &Triangle| {
+    let centroid = centroid(triangle);
+    if spread.x >= spread.y && spread.x >= spread.z {
+      centroid.x
+    } else if spread.y >= spread.z {
+      centroid.y
+    } else {
+      centroid.z
+    }
+  };
+  triangles.sort_by(|a, b| axis(a).total_cmp(&axis(b)));
+
+  let middle = triangles.len() / 2;
+  let (left, right) = triangles.split_at_mut(middle);
+  let left = build(left, offset, nodes);
+  let right = build(right, offset + middle, nodes);
+  nodes[idx].contents = Contents::Children(left, right);
+  idx
+}
+
+impl Renderable for Mesh {
+  fn intersects(&self, ray: &Ray) -> Option<Hit> {
+    let mut closest: Option<Hit> = None;
+    let mut stack = vec![0];
+    while let Some(node) = stack.pop() {
+      let node = &self.nodes[node];
+      // Skip boxes that only start beyond what we've already hit:
+      match (node.bounds.entry(ray), &closest) {
+        (None, _) => continue,
+        (Some(entry), Some(hit)) if entry > hit.t => continue,
+        _ => {}
+      }
+      match &node.contents {
+        Contents::Triangles(range) => {
+          for triangle in &self.triangles[range.clone()] {
+            if let Some(hit) = triangle.intersects(ray) {
+              if closest.is_none_or(|closest| hit.t < closest.t) {
+                closest = Some(hit);
+              }
+            }
+          }
+        }
+        Contents::Children(left, right) => {
+          stack.push(*left);
+          stack.push(*right);
+        }
+      }
+    }
+    closest
+  }
+
+  fn material(&self) -> &dyn Material {
+    &*self.material
+  }
+
+  fn set_material(&mut self, material: Arc<dyn Material>) {
+    self.material = material;
+  }
+
+  fn bounds(&self) -> Option<Aabb> {
+    Some(self.nodes[0].bounds)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::material::MIRROR;
+  use crate::vector::Vector;
+  use rand::Rng;
+
+  #[test]
+  fn nearest_hit_matches_testing_every_triangle() {
+    let mut rng = rand::thread_rng();
+    let mut point = |size: f64| Vector {
+      x: rng.gen_range(-size, size),
+      y: rng.gen_range(-size, size),
+      z: rng.gen_range(-size, size),
+    };
+    let triangles: Vec<Triangle> = (0..200)
+      .map(|_| {
+        let a = point(5.0);
+        Triangle::new(a, a + point(1.0), a + point(1.0), Arc::new(MIRROR))
+      })
+      .collect();
+    let mesh = Mesh::new(triangles.clone(), Arc::new(MIRROR));
+
+    let mut hits = 0;
+    for _ in 0..500 {
+      // From all around, toward the middle of the cloud of triangles:
+      let origin = point(8.0);
+      let ray = Ray::new(origin, point(3.0) - origin, 0.0);
+      let expected = triangles
+        .iter()
+        .filter_map(|triangle| triangle.intersects(&ray))
+        .min_by(|a, b| a.t.total_cmp(&b.t));
+      assert_eq!(
+        mesh.intersects(&ray).map(|hit| hit.t),
+        expected.map(|hit| hit.t)
+      );
+      hits += expected.is_some() as usize;
+    }
+    // Make sure we actually tested something:
+    assert!(hits > 100);
+  }
+
+  #[test]
+  fn empty_mesh_is_never_hit() {
+    let mesh = Mesh::new(vec![], Arc::new(MIRROR));
+    let ray = Ray::new(
+      Vector::new(),
+      Vector {
+        x: 0.0,
+        y: 0.0,
+        z: 1.0,
+      },
+      0.0,
+    );
+    assert_eq!(mesh.intersects(&ray), None);
+  }
+}
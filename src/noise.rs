@@ -0,0 +1,129 @@
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+
+use crate::vector::Vector;
+
+/// Smooth 3D "value noise": random values at the corners of a unit lattice,
+/// blended smoothly in between. Handy for clouds, marble and terrain.
+///
+/// The lattice repeats every `period` units along each axis, so textures built
+/// from it tile seamlessly.
+#[derive(Debug, Clone)]
+pub struct Noise {
+  period: usize,
+  /// A shuffle of `0..period`, used to hash lattice coordinates.
+  permutation: Vec<usize>,
+  /// The value at each hashed lattice point, in `[-1, 1]`.
+  values: Vec<f64>,
+}
+
+impl Noise {
+  /// The same `seed` and `period` always produce the same noise.
+  pub fn new(seed: u64, period: usize) -> Self {
+    let period = period.max(1);
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut permutation: Vec<usize> = (0..period).collect();
+    permutation.shuffle(&mut rng);
+    let values = (0..period).map(|_| rng.gen_range(-1.0, 1.0)).collect();
+    Noise {
+      period,
+      permutation,
+      values,
+    }
+  }
+
+  /// The noise at `point`, in `[-1, 1]`.
+  pub fn at(&self, point: &Vector) -> f64 {
+    let (x0, y0, z0) = (point.x.floor(), point.y.floor(), point.z.floor());
+    // Ease in and out of each cell, so there are no visible creases along the
+    // lattice lines:
+    let smooth = |t: f64| t * t * (3.0 - 2.0 * t);
+    let (fx, fy, fz) = (
+      smooth(point.x - x0),
+      smooth(point.y - y0),
+      smooth(point.z - z0),
+    );
+    let (x0, y0, z0) = (x0 as i64, y0 as i64, z0 as i64);
+
+    let lerp = |a: f64, b: f64, t: f64| a + (b - a) * t;
+    let corner = |dx, dy, dz| self.lattice(x0 + dx, y0 + dy, z0 + dz);
+    let y_near = lerp(
+      lerp(corner(0, 0, 0), corner(1, 0, 0), fx),
+      lerp(corner(0, 1, 0), corner(1, 1, 0), fx),
+      fy,
+    );
+    let y_far = lerp(
+      lerp(corner(0, 0, 1), corner(1, 0, 1), fx),
+      lerp(corner(0, 1, 1), corner(1, 1, 1), fx),
+      fy,
+    );
+    lerp(y_near, y_far, fz)
+  }
+
+  /// Several octaves of noise layered together, each twice the frequency and
+  /// half the strength of the last. Taking the absolute value of each gives
+  /// sharp creases where the noise crosses zero, which is what makes it look
+  /// turbulent rather than cloudy.
+  ///
+  /// Always non-negative, and less than 2.
+  pub fn turbulence(&self, point: &Vector, octaves: u32) -> f64 {
+    (0..octaves)
+      .map(|octave| {
+        let frequency = (1 << octave) as f64;
+        self.at(&(point * frequency)).abs() / frequency
+      })
+      .sum()
+  }
+
+  fn lattice(&self, x: i64, y: i64, z: i64) -> f64 {
+    let wrap = |i: i64| i.rem_euclid(self.period as i64) as usize;
+    let hash = self.permutation[wrap(x)];
+    let hash = self.permutation[(hash + wrap(y)) % self.period];
+    let hash = self.permutation[(hash + wrap(z)) % self.period];
+    self.values[hash]
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn point(x: f64, y: f64, z: f64) -> Vector {
+    Vector { x, y, z }
+  }
+
+  #[test]
+  fn seeded_noise_is_deterministic_and_bounded() {
+    let (a, b) = (Noise::new(7, 64), Noise::new(7, 64));
+    let other = Noise::new(8, 64);
+
+    let mut differs = false;
+    for i in 0..1000 {
+      let p = point(i as f64 * 0.37, i as f64 * 0.11 - 20.0, i as f64 * 0.73);
+      let value = a.at(&p);
+      assert_eq!(value, b.at(&p));
+      assert!((-1.0..=1.0).contains(&value));
+      differs |= value != other.at(&p);
+    }
+    assert!(differs);
+  }
+
+  #[test]
+  fn noise_tiles_every_period() {
+    let noise = Noise::new(1, 16);
+    let p = point(3.3, -7.8, 12.1);
+    let shifted = p + point(16.0, -32.0, 48.0);
+    assert!((noise.at(&p) - noise.at(&shifted)).abs() < 1e-9);
+  }
+
+  #[test]
+  fn turbulence_is_bounded() {
+    let noise = Noise::new(3, 32);
+    for i in 0..1000 {
+      let p = point(i as f64 * 0.29, i as f64 * 0.53, i as f64 * -0.17);
+      let turbulence = noise.turbulence(&p, 6);
+      assert!((0.0..2.0).contains(&turbulence));
+    }
+  }
+}
@@ -0,0 +1,236 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::sync::Arc;
+
+use crate::material::Material;
+use crate::mesh::Mesh;
+use crate::scene::Renderable;
+use crate::triangle::Triangle;
+use crate::vector::Vector;
+
+/// Why a `.obj` file couldn't be loaded.
+#[derive(Debug)]
+pub enum ObjError {
+  /// The file couldn't be read at all.
+  Io(io::Error),
+  /// Line number `line` (counting from 1) didn't make sense.
+  Parse { line: usize, message: String },
+}
+
+impl fmt::Display for ObjError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ObjError::Io(error) => write!(f, "{}", error),
+      ObjError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+    }
+  }
+}
+
+impl From<io::Error> for ObjError {
+  fn from(error: io::Error) -> Self {
+    ObjError::Io(error)
+  }
+}
+
+/// Load the Wavefront `.obj` model at `path` as triangles, all made of
+/// `material`. See `parse_obj`.
+pub fn load_obj(
+  path: &str,
+  material: Arc<dyn Material>,
+) -> Result<Vec<Box<dyn Renderable>>, ObjError> {
+  parse_obj(&fs::read_to_string(path)?, material)
+}
+
+/// Like `load_obj`, but gather the triangles into a single `Mesh`, which is
+/// much quicker to render once there are more than a handful.
+pub fn load_mesh(path: &str, material: Arc<dyn Material>) -> Result<Mesh, ObjError> {
+  let triangles = parse_triangles(&fs::read_to_string(path)?, &material)?;
+  Ok(Mesh::new(triangles, material))
+}
+
+/// Turn the text of a `.obj` file into triangles, all made of `material`.
+///
+/// Only geometry is read: vertices (`v`), vertex normals (`vn`) and faces
+/// (`f`). Faces with more than three corners are split into a fan of
+/// triangles, which is right for the convex polygons modelling tools export.
+/// Faces that give a normal for every corner shade smoothly; the rest are
+/// flat. Everything else (texture coordinates, groups, materials) is ignored.
+pub fn parse_obj(
+  text: &str,
+  material: Arc<dyn Material>,
+) -> Result<Vec<Box<dyn Renderable>>, ObjError> {
+  Ok(
+    parse_triangles(text, &material)?
+      .into_iter()
+      .map(|triangle| Box::new(triangle) as Box<dyn Renderable>)
+      .collect(),
+  )
+}
+
+fn parse_triangles(text: &str, material: &Arc<dyn Material>) -> Result<Vec<Triangle>, ObjError> {
+  let mut vertices = vec![];
+  let mut normals = vec![];
+  let mut triangles = vec![];
+
+  for (i, line) in text.lines().enumerate() {
+    let error = |message: String| ObjError::Parse {
+      line: i + 1,
+      message,
+    };
+    let mut words = line.split_whitespace();
+    match words.next() {
+      Some("v") => vertices.push(parse_vector(words).map_err(error)?),
+      Some("vn") => normals.push(parse_vector(words).map_err(error)?),
+      Some("f") => {
+        let corners = words
+          .map(|corner| parse_corner(corner, &vertices, &normals))
+          .collect::<Result<Vec<_>, _>>()
+          .map_err(error)?;
+        if corners.len() < 3 {
+          return Err(error(format!(
+            "a face needs at least 3 corners, not {}",
+            corners.len()
+          )));
+        }
+        for pair in corners[1..].windows(2) {
+          let (a, b, c) = (corners[0], pair[0], pair[1]);
+          let triangle = match (a.1, b.1, c.1) {
+            (Some(na), Some(nb), Some(nc)) => {
+              Triangle::smooth(a.0, b.0, c.0, [na, nb, nc], Arc::clone(material))
+            }
+            _ => Triangle::new(a.0, b.0, c.0, Arc::clone(material)),
+          };
+          triangles.push(triangle);
+        }
+      }
+      _ => {}
+    }
+  }
+  Ok(triangles)
+}
+
+// The `x y z` following a `v` or `vn`. (Vertices may have a fourth, `w`
+// coordinate, which we don't need.)
+fn parse_vector<'a, I: Iterator<Item = &'a str>>(mut words: I) -> Result<Vector, String> {
+  let mut next = || -> Result<f64, String> {
+    let word = words.next().ok_or("expected 3 coordinates")?;
+    word
+      .parse()
+      .map_err(|_| format!("{:?} isn't a number", word))
+  };
+  Ok(Vector {
+    x: next()?,
+    y: next()?,
+    z: next()?,
+  })
+}
+
+// One corner of a face: `v`, `v/vt`, `v//vn` or `v/vt/vn`, where each is a
+// 1-based index into what's been read so far, or a negative one counting back
+// from the latest.
+fn parse_corner(
+  corner: &str,
+  vertices: &[Vector],
+  normals: &[Vector],
+) -> Result<(Vector, Option<Vector>), String> {
+  let lookup = |index: &str, list: &[Vector]| -> Result<Vector, String> {
+    let index: isize = index
+      .parse()
+      .map_err(|_| format!("{:?} isn't an index", index))?;
+    let at = if index < 0 {
+      list.len() as isize + index
+    } else {
+      index - 1
+    };
+    if (0..list.len() as isize).contains(&at) {
+      Ok(list[at as usize])
+    } else {
+      Err(format!("there's no number {} to refer to", index))
+    }
+  };
+
+  let mut indices = corner.split('/');
+  let vertex = lookup(indices.next().unwrap_or(""), vertices)?;
+  let normal = match indices.nth(1) {
+    Some(index) if !index.is_empty() => Some(lookup(index, normals)?),
+    _ => None,
+  };
+  Ok((vertex, normal))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::material::MIRROR;
+  use crate::ray::Ray;
+
+  // A unit square in the `z = 0` plane facing +Z, as one quad, with normals
+  // tilted outward at its corners.
+  const SQUARE: &str = "
+# corners
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vn -1 -1 1
+vn 1 -1 1
+vn 1 1 1
+vn -1 1 1
+vt 0 0
+f 1/1/1 2/1/2 3/1/3 -1//-1
+";
+
+  fn from_above(x: f64, y: f64) -> Ray {
+    Ray {
+      origin: Vector { x, y, z: 1.0 },
+      direction: Vector {
+        x: 0.0,
+        y: 0.0,
+        z: -1.0,
+      },
+      time: 0.0,
+      channel: None,
+    }
+  }
+
+  #[test]
+  fn quads_are_split_into_triangles() {
+    let triangles = parse_obj(SQUARE, Arc::new(MIRROR)).unwrap();
+    assert_eq!(triangles.len(), 2);
+
+    // Every part of the square is covered by one of them:
+    for &(x, y) in &[(0.25, 0.75), (0.75, 0.25), (0.5, 0.5)] {
+      assert!(triangles
+        .iter()
+        .any(|triangle| triangle.intersects(&from_above(x, y)).is_some()));
+    }
+    // The normals came along, so the middle is shaded straight on and the
+    // corners aren't:
+    let normal_at = |x, y| {
+      triangles
+        .iter()
+        .find_map(|triangle| triangle.intersects(&from_above(x, y)))
+        .unwrap()
+        .normal
+    };
+    assert!(normal_at(0.5, 0.5).z > 0.99);
+    assert!(normal_at(0.01, 0.01).z < 0.6);
+  }
+
+  #[test]
+  fn bad_faces_are_errors() {
+    let parse_error_line = |text| match parse_obj(text, Arc::new(MIRROR)) {
+      Err(ObjError::Parse { line, .. }) => line,
+      _ => panic!("{:?} should have failed to parse", text),
+    };
+    assert_eq!(parse_error_line("v 0 0 0\nv 1 0 0\nf 1 2"), 3);
+    assert_eq!(parse_error_line("v 0 0 0\nf 1 2 3"), 2);
+    assert_eq!(parse_error_line("v 0 0 zero"), 1);
+
+    assert!(matches!(
+      load_obj("no/such/model.obj", Arc::new(MIRROR)),
+      Err(ObjError::Io(_))
+    ));
+  }
+}
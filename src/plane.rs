@@ -1,46 +1,335 @@
-use crate::material::Material;
+use std::sync::Arc;
+
+use crate::material::{Checkerboard, HDRColor, Material};
 use crate::ray::Ray;
-use crate::scene::Renderable;
-use crate::Vector;
+use crate::scene::{Hit, Renderable};
+use crate::vector::Vector;
 
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 pub struct Plane {
-  pub center: Vector,
+  center: Vector,
   normal: Vector,
-  material: &'static dyn Material,
+  /// `-normal·center`, so a point `p` is on the plane when `normal·p + d = 0`.
+  d: f64,
+  material: Arc<dyn Material>,
+  /// Only visible from the side its normal faces; rays arriving from behind
+  /// pass straight through. Planes are double-sided by default.
+  pub single_sided: bool,
 }
 
 impl Plane {
-  pub fn new(center: Vector, normal: Vector, material: &'static dyn Material) -> Self {
+  pub fn new(center: Vector, normal: Vector, material: Arc<dyn Material>) -> Self {
+    let normal = normal.normalized();
     Plane {
       center,
-      normal: normal.normalized(),
+      normal,
+      d: -normal.dot(&center),
       material,
+      single_sided: false,
     }
   }
+
+  /// An endless floor at height `y`, checkered with squares of colors `a` and
+  /// `b`, `scale` units across.
+  pub fn checkered(y: f64, a: HDRColor, b: HDRColor, scale: f64) -> Self {
+    Plane::new(
+      Vector { x: 0.0, y, z: 0.0 },
+      Vector {
+        x: 0.0,
+        y: 1.0,
+        z: 0.0,
+      },
+      Arc::new(Checkerboard { a, b, scale }),
+    )
+  }
 }
 
 impl Renderable for Plane {
-  fn intersects(&self, ray: &Ray) -> Option<f64> {
-    let dir = ray.direction.normalized();
-    let denominator = self.normal.normalized().dot(&dir);
-    if denominator.abs() < 0.0001 {
+  fn intersects(&self, ray: &Ray) -> Option<Hit> {
+    if ray.is_degenerate() {
       return None;
     }
-    let d = -self.normal.normalized().dot(&self.center);
-    let t = -(self.normal.normalized().dot(&ray.origin) + d) / denominator;
-    if t < 0.0001 {
+    let denominator = self.normal.dot(&ray.direction);
+    if denominator.abs() < 0.0001 || (self.single_sided && denominator > 0.0) {
+      return None;
+    }
+    let t = -(self.normal.dot(&ray.origin) + self.d) / denominator;
+    if t < 0.0001 || !t.is_finite() {
       return None;
     }
 
-    Some(t)
+    // A plane has no inside, so light it from whichever side we're looking at
+    // it from; otherwise the back of a wall shades like its front.
+    let normal = if denominator > 0.0 {
+      self.normal * -1.0
+    } else {
+      self.normal
+    };
+    Some(Hit {
+      t,
+      normal,
+      uv: None,
+    })
   }
 
-  fn normal(&self, _: &Vector) -> Vector {
-    self.normal
+  fn material(&self) -> &dyn Material {
+    &*self.material
   }
 
-  fn material(&self) -> &dyn Material {
-    self.material
+  fn set_material(&mut self, material: Arc<dyn Material>) {
+    self.material = material;
+  }
+
+  // For CSG, a plane is a half-space: everything on the side opposite its
+  // normal is "inside".
+  fn intersect_all(&self, ray: &Ray) -> Vec<(Hit, bool)> {
+    let denominator = self.normal.dot(&ray.direction);
+    if denominator.abs() < 0.0001 {
+      return vec![];
+    }
+    let t = (self.center - ray.origin).dot(&self.normal) / denominator;
+    let hit = Hit {
+      t,
+      normal: self.normal,
+      uv: None,
+    };
+    vec![(hit, denominator < 0.0)]
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::material::MIRROR;
+
+  fn floor() -> Plane {
+    Plane::new(
+      Vector::new(),
+      Vector {
+        x: 0.0,
+        y: 1.0,
+        z: 0.0,
+      },
+      Arc::new(MIRROR),
+    )
+  }
+
+  #[test]
+  fn normal_faces_ray_from_front() {
+    let ray = Ray {
+      origin: Vector {
+        x: 0.0,
+        y: 1.0,
+        z: 0.0,
+      },
+      direction: Vector {
+        x: 0.0,
+        y: -1.0,
+        z: 0.0,
+      },
+      time: 0.0,
+      channel: None,
+    };
+    let normal = floor().intersects(&ray).unwrap().normal;
+    assert!(normal.dot(&(ray.origin - Vector::new())) > 0.0);
+  }
+
+  #[test]
+  fn oblique_ray_hits_at_its_distance() {
+    let plane = Plane::new(
+      Vector {
+        x: 5.0,
+        y: 1.0,
+        z: -3.0,
+      },
+      Vector {
+        x: 0.0,
+        y: 2.0,
+        z: 0.0,
+      },
+      Arc::new(MIRROR),
+    );
+    let ray = Ray {
+      origin: Vector {
+        x: 0.0,
+        y: 3.0,
+        z: 0.0,
+      },
+      direction: Vector {
+        x: 1.0,
+        y: -1.0,
+        z: 0.0,
+      }
+      .normalized(),
+      time: 0.0,
+      channel: None,
+    };
+    let t = plane.intersects(&ray).unwrap().t;
+    assert!((t - 2.0 * 2f64.sqrt()).abs() < 1e-9);
+  }
+
+  #[test]
+  fn degenerate_rays_miss() {
+    let origin = Vector {
+      x: 0.0,
+      y: 1.0,
+      z: 0.0,
+    };
+    for direction in [
+      Vector {
+        x: 0.0,
+        y: f64::NAN,
+        z: 0.0,
+      },
+      Vector::new(),
+    ]
+    .iter()
+    {
+      let ray = Ray {
+        origin,
+        direction: *direction,
+        time: 0.0,
+        channel: None,
+      };
+      assert_eq!(floor().intersects(&ray), None);
+    }
+  }
+
+  #[test]
+  fn normal_flips_to_face_ray_from_behind() {
+    let ray = Ray {
+      origin: Vector {
+        x: 1.0,
+        y: -1.0,
+        z: 0.0,
+      },
+      direction: Vector {
+        x: -1.0,
+        y: 1.0,
+        z: 0.0,
+      }
+      .normalized(),
+      time: 0.0,
+      channel: None,
+    };
+    let plane = floor();
+    let hit = plane.intersects(&ray).unwrap();
+    let point = ray.origin + ray.direction * hit.t;
+    let normal = hit.normal;
+    assert!(normal.dot(&(ray.origin - point)) > 0.0);
+    assert_eq!(
+      normal,
+      Vector {
+        x: 0.0,
+        y: -1.0,
+        z: 0.0,
+      }
+    );
+  }
+
+  #[test]
+  fn single_sided_plane_is_invisible_from_behind() {
+    let mut plane = floor();
+    plane.single_sided = true;
+
+    let from_below = Ray {
+      origin: Vector {
+        x: 0.0,
+        y: -1.0,
+        z: 0.0,
+      },
+      direction: Vector {
+        x: 0.0,
+        y: 1.0,
+        z: 0.0,
+      },
+      time: 0.0,
+      channel: None,
+    };
+    let from_above = Ray {
+      origin: Vector {
+        x: 0.0,
+        y: 1.0,
+        z: 0.0,
+      },
+      direction: Vector {
+        x: 0.0,
+        y: -1.0,
+        z: 0.0,
+      },
+      time: 0.0,
+      channel: None,
+    };
+
+    assert_eq!(plane.intersects(&from_below), None);
+    assert_eq!(plane.intersects(&from_above).map(|hit| hit.t), Some(1.0));
+    assert_eq!(floor().intersects(&from_below).map(|hit| hit.t), Some(1.0));
+  }
+
+  #[test]
+  fn checkered_floor_faces_up_and_alternates() {
+    use crate::camera::Camera;
+    use crate::material::BLACK;
+    use crate::scene::{Background, Scene};
+    use crate::settings::RenderSettings;
+
+    let white = HDRColor {
+      r: 1.0,
+      g: 1.0,
+      b: 1.0,
+    };
+    let floor = Plane::checkered(-1.0, white, BLACK, 2.0);
+
+    let ray_down_at = |x: f64, z: f64| Ray {
+      origin: Vector { x, y: 5.0, z },
+      direction: Vector {
+        x: 0.0,
+        y: -1.0,
+        z: 0.0,
+      },
+      time: 0.0,
+      channel: None,
+    };
+    let hit = floor.intersects(&ray_down_at(0.0, 0.0)).unwrap();
+    assert_eq!(hit.t, 6.0);
+    assert_eq!(
+      hit.normal,
+      Vector {
+        x: 0.0,
+        y: 1.0,
+        z: 0.0,
+      }
+    );
+
+    // With only (white) ambient light, shading shows the pattern's color as-is:
+    let scene = Scene {
+      cam: Camera::new(Vector::new(), 45.0, 1, 1),
+      renderables: vec![],
+      background: Background::Solid(BLACK),
+      lights: vec![],
+      ambient: white,
+      settings: RenderSettings::default(),
+      emitters: Default::default(),
+    };
+    let brightness_at = |x: f64, z: f64| {
+      let ray = ray_down_at(x, z);
+      let hit = floor.intersects(&ray).unwrap();
+      let point = ray.origin + ray.direction * hit.t;
+      floor
+        .material()
+        .color_at(
+          &mut rand::thread_rng(),
+          &point,
+          &hit.normal,
+          &ray,
+          &scene,
+          0,
+        )
+        .r
+    };
+    assert_eq!(brightness_at(0.5, 0.5), 1.0);
+    assert_eq!(brightness_at(2.5, 0.5), 0.0);
+    assert_eq!(brightness_at(2.5, 2.5), 1.0);
+    assert_eq!(brightness_at(-0.5, 0.5), 0.0);
   }
 }
@@ -0,0 +1,304 @@
+use crate::material::{HDRColor, BLACK};
+use crate::vector::Vector;
+
+/// Per-pixel geometry recorded at the primary hit, used to guide filters that
+/// shouldn't smear across object boundaries.
+#[derive(Debug, Clone, Copy)]
+pub struct GBufferSample {
+  pub normal: Vector,
+  /// Distance along the primary ray; `f64::INFINITY` when the ray missed.
+  pub depth: f64,
+  /// The material's own color, before lighting.
+  pub albedo: HDRColor,
+}
+
+impl GBufferSample {
+  pub const MISS: GBufferSample = GBufferSample {
+    normal: Vector {
+      x: 0.0,
+      y: 0.0,
+      z: 0.0,
+    },
+    depth: f64::INFINITY,
+    albedo: BLACK,
+  };
+}
+
+// How quickly the filter weight falls off as neighboring normals diverge, and
+// as neighboring depths differ (relative to the center pixel's depth).
+const NORMAL_SIGMA: f64 = 0.1;
+const DEPTH_SIGMA: f64 = 0.05;
+
+/// Edge-aware ("cross bilateral") denoise filter.
+///
+/// Each output pixel is a weighted average of its neighbors within `radius`,
+/// where the weights fall off with screen distance _and_ with differences in
+/// the G-buffer normal and depth. Flat regions get smoothed out, while object
+/// silhouettes and creases stay sharp.
+pub fn denoise(
+  buffer: &[HDRColor],
+  gbuffer: &[GBufferSample],
+  width: usize,
+  height: usize,
+  radius: usize,
+) -> Vec<HDRColor> {
+  let spatial_sigma = (radius as f64 / 2.0).max(0.5);
+  let r = radius as isize;
+
+  (0..width * height)
+    .map(|i| {
+      let x = (i % width) as isize;
+      let y = (i / width) as isize;
+      let center = gbuffer[i];
+
+      let mut sum = BLACK;
+      let mut total_weight = 0.0;
+      for dy in -r..=r {
+        for dx in -r..=r {
+          let nx = x + dx;
+          let ny = y + dy;
+          if nx < 0 || ny < 0 || nx >= width as isize || ny >= height as isize {
+            continue;
+          }
+          let j = ny as usize * width + nx as usize;
+          let weight = similarity(&center, &gbuffer[j])
+            * (-((dx * dx + dy * dy) as f64) / (2.0 * spatial_sigma * spatial_sigma)).exp();
+
+          sum += buffer[j] * weight as f32;
+          total_weight += weight;
+        }
+      }
+
+      if total_weight > 0.0 {
+        sum / total_weight as f32
+      } else {
+        buffer[i]
+      }
+    })
+    .collect()
+}
+
+/// How likely two G-buffer samples are to be of the same surface, from `1`
+/// (identical) falling toward `0` as their normals and depths diverge.
+fn similarity(center: &GBufferSample, sample: &GBufferSample) -> f64 {
+  if center.depth.is_infinite() || sample.depth.is_infinite() {
+    // Background pixels only match other background pixels:
+    if center.depth.is_infinite() && sample.depth.is_infinite() {
+      1.0
+    } else {
+      0.0
+    }
+  } else {
+    let normal_distance = 1.0 - center.normal.dot(&sample.normal);
+    let depth_distance = (center.depth - sample.depth).abs() / center.depth.max(0.0001);
+    (-normal_distance / NORMAL_SIGMA).exp() * (-depth_distance / DEPTH_SIGMA).exp()
+  }
+}
+
+/// Blend each pixel of the `current` frame with the same pixel of the
+/// `previous` one (an exponential moving average), keeping `weight` of the
+/// previous frame. Over a run of frames this averages away Monte Carlo noise
+/// and flicker.
+///
+/// There's no motion compensation, so this only suits frames rendered from the
+/// same viewpoint. Where something has moved (or something behind it has come
+/// into view), the G-buffer tells us the pixel now sees a different surface
+/// than last frame, and the previous frame counts for less or not at all,
+/// rather than leaving a ghost behind.
+pub fn temporal_blend(
+  previous: &[HDRColor],
+  previous_gbuffer: &[GBufferSample],
+  current: &[HDRColor],
+  gbuffer: &[GBufferSample],
+  weight: f32,
+) -> Vec<HDRColor> {
+  (0..current.len())
+    .map(|i| {
+      let keep = weight * similarity(&gbuffer[i], &previous_gbuffer[i]) as f32;
+      current[i].lerp(&previous[i], keep)
+    })
+    .collect()
+}
+
+/// Add a glow around bright parts of the image.
+///
+/// Whatever exceeds `threshold` in each channel is blurred with a Gaussian of
+/// the given `radius` and added back on top of the original image. This should
+/// run on the HDR buffer, before tone mapping.
+pub fn bloom(
+  buffer: &[HDRColor],
+  width: usize,
+  height: usize,
+  threshold: f32,
+  radius: usize,
+) -> Vec<HDRColor> {
+  let bright: Vec<HDRColor> = buffer
+    .iter()
+    .map(|color| (color - HDRColor::new(threshold, threshold, threshold)).max(&BLACK))
+    .collect();
+
+  // A 2D Gaussian is separable, so we blur horizontally and then vertically,
+  // rather than visiting every pixel in the full (2r+1)x(2r+1) window.
+  let sigma = (radius as f32 / 2.0).max(0.5);
+  let kernel: Vec<f32> = (-(radius as isize)..=radius as isize)
+    .map(|d| (-((d * d) as f32) / (2.0 * sigma * sigma)).exp())
+    .collect();
+  let kernel_total: f32 = kernel.iter().sum();
+  let kernel: Vec<f32> = kernel.iter().map(|w| w / kernel_total).collect();
+
+  let blur = |source: &[HDRColor], step_x: isize, step_y: isize| -> Vec<HDRColor> {
+    (0..width * height)
+      .map(|i| {
+        let x = (i % width) as isize;
+        let y = (i / width) as isize;
+        kernel
+          .iter()
+          .enumerate()
+          .filter_map(|(k, weight)| {
+            let d = k as isize - radius as isize;
+            let nx = x + d * step_x;
+            let ny = y + d * step_y;
+            if nx < 0 || ny < 0 || nx >= width as isize || ny >= height as isize {
+              return None;
+            }
+            Some(source[ny as usize * width + nx as usize] * *weight)
+          })
+          .sum()
+      })
+      .collect()
+  };
+  let blurred = blur(&blur(&bright, 1, 0), 0, 1);
+
+  buffer
+    .iter()
+    .zip(blurred.iter())
+    .map(|(color, glow)| color + glow)
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rand::{Rng, SeedableRng};
+
+  const UP: Vector = Vector {
+    x: 0.0,
+    y: 1.0,
+    z: 0.0,
+  };
+
+  #[test]
+  fn noisy_flat_region_converges_to_mean() {
+    let (width, height) = (16, 16);
+    // Seeded, since now and then an unlucky draw leaves some pixel more than
+    // a quarter off:
+    let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+    let buffer: Vec<HDRColor> = (0..width * height)
+      .map(|_| {
+        let v = rng.gen_range(0.0, 1.0);
+        HDRColor { r: v, g: v, b: v }
+      })
+      .collect();
+    let gbuffer = vec![
+      GBufferSample {
+        normal: UP,
+        depth: 5.0,
+        albedo: BLACK,
+      };
+      width * height
+    ];
+
+    let denoised = denoise(&buffer, &gbuffer, width, height, 3);
+
+    let variance = |pixels: &[HDRColor]| {
+      let mean = pixels.iter().map(|p| p.r).sum::<f32>() / pixels.len() as f32;
+      pixels.iter().map(|p| (p.r - mean).powi(2)).sum::<f32>() / pixels.len() as f32
+    };
+    assert!(variance(&denoised) < variance(&buffer) / 4.0);
+    for pixel in &denoised {
+      assert!((pixel.r - 0.5).abs() < 0.25);
+    }
+  }
+
+  #[test]
+  fn preserves_normal_discontinuity() {
+    let (width, height) = (8, 8);
+    let left = HDRColor {
+      r: 1.0,
+      g: 1.0,
+      b: 1.0,
+    };
+    let right = BLACK;
+    let facing = Vector {
+      x: 1.0,
+      y: 0.0,
+      z: 0.0,
+    };
+    let buffer: Vec<HDRColor> = (0..width * height)
+      .map(|i| if i % width < width / 2 { left } else { right })
+      .collect();
+    let gbuffer: Vec<GBufferSample> = (0..width * height)
+      .map(|i| GBufferSample {
+        normal: if i % width < width / 2 { UP } else { facing },
+        depth: 5.0,
+        albedo: BLACK,
+      })
+      .collect();
+
+    let denoised = denoise(&buffer, &gbuffer, width, height, 3);
+
+    for (i, pixel) in denoised.iter().enumerate() {
+      let expected = if i % width < width / 2 { 1.0 } else { 0.0 };
+      assert!((pixel.r - expected).abs() < 0.001);
+    }
+  }
+
+  #[test]
+  fn bloom_spreads_bright_pixels_only() {
+    let (width, height) = (9, 9);
+    let mut buffer = vec![BLACK; width * height];
+    let center = 4 * width + 4;
+    let dim = HDRColor {
+      r: 0.5,
+      g: 0.5,
+      b: 0.5,
+    };
+    buffer[center] = HDRColor {
+      r: 10.0,
+      g: 10.0,
+      b: 10.0,
+    };
+    buffer[0] = dim;
+
+    let bloomed = bloom(&buffer, width, height, 1.0, 2);
+
+    // The bright pixel bleeds into its neighbors...
+    assert!(bloomed[center - 1].r > 0.0);
+    assert!(bloomed[center + width].r > 0.0);
+    assert!(bloomed[center].r > 10.0);
+    // ...but the dim pixel, and pixels outside the radius, are left alone:
+    assert_eq!(bloomed[0].r, dim.r);
+    assert_eq!(bloomed[width - 1].r, 0.0);
+  }
+
+  #[test]
+  fn blends_with_the_previous_frame_only_where_nothing_moved() {
+    let white = HDRColor::new(1.0, 1.0, 1.0);
+    let surface = |depth| GBufferSample {
+      normal: UP,
+      depth,
+      albedo: white,
+    };
+    let previous = vec![white; 3];
+    let previous_gbuffer = vec![surface(5.0), surface(5.0), GBufferSample::MISS];
+    // The same wall, something new in front of it, and something where there
+    // was just background:
+    let gbuffer = vec![surface(5.0), surface(2.0), surface(5.0)];
+
+    let blended = temporal_blend(&previous, &previous_gbuffer, &[BLACK; 3], &gbuffer, 0.75);
+
+    assert_eq!(blended[0].r, 0.75);
+    assert!(blended[1].r < 0.001);
+    assert_eq!(blended[2].r, 0.0);
+  }
+}
@@ -3,5 +3,75 @@ use crate::vector::Vector;
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub struct Ray {
   pub origin: Vector,
+  /// Always unit length, so that distances along the ray (the `t` in every
+  /// `Hit`) are in world units. Use `Ray::new` when that isn't already
+  /// guaranteed; `Scene::cast` checks it in debug builds.
   pub direction: Vector,
+  /// When, within a frame, this ray was cast; `0.0` is the start of the frame
+  /// and `1.0` the end. Moving objects use this to produce motion blur.
+  pub time: f64,
+  /// Which color channel (`0` red, `1` green or `2` blue) this ray carries,
+  /// once a dispersive `Refractor` has split the light up by wavelength;
+  /// `None` carries all three. Every ray traced on from this one keeps it, so
+  /// the light bends the same way at every surface it goes on to cross.
+  pub channel: Option<usize>,
+}
+
+impl Ray {
+  /// A ray from `origin` toward `direction`, which doesn't need to be
+  /// normalized.
+  pub fn new(origin: Vector, direction: Vector, time: f64) -> Self {
+    Ray {
+      origin,
+      direction: direction.normalized(),
+      time,
+      channel: None,
+    }
+  }
+
+  /// Where to start a ray leaving the surface at `point`: `epsilon` off it, on
+  /// the side `normal` faces (see `RenderSettings::epsilon`).
+  pub fn offset_origin(point: &Vector, normal: &Vector, epsilon: f64) -> Vector {
+    point + normal * epsilon
+  }
+
+  /// Whether `direction` is unit length (give or take some rounding error).
+  pub fn is_normalized(&self) -> bool {
+    (self.direction.length_squared() - 1.0).abs() < 1e-6
+  }
+
+  /// Whether this ray can't actually be traced: its direction has no length,
+  /// or it has NaNs or infinities anywhere. Intersecting one would only produce
+  /// garbage distances.
+  pub fn is_degenerate(&self) -> bool {
+    let length_squared = self.direction.length_squared();
+    !(length_squared > 0.0 && self.direction.is_finite() && self.origin.is_finite())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn new_normalizes_direction() {
+    let ray = Ray::new(
+      Vector::new(),
+      Vector {
+        x: 3.0,
+        y: 0.0,
+        z: 4.0,
+      },
+      0.0,
+    );
+    assert!(ray.is_normalized());
+    assert_eq!(
+      ray.direction,
+      Vector {
+        x: 0.6,
+        y: 0.0,
+        z: 0.8,
+      }
+    );
+  }
 }
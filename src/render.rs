@@ -0,0 +1,985 @@
+use rand::prelude::thread_rng;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use rand::{Rng, RngCore};
+use rayon::prelude::*;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::exr::write_exr;
+
+use crate::material::{linear_to_srgb, HDRColor, BLACK};
+use crate::postprocess::*;
+use crate::ray::Ray;
+use crate::sampling::{pixel_offsets, Welford};
+use crate::scene::{Hit, Light, Scene};
+use crate::settings::{RenderMode, RenderSettings};
+use crate::texture::{Texture, Wrap};
+
+/// Render `scene` into a freshly allocated buffer of B, G, R, A bytes, one row
+/// after another.
+pub fn render_to_buffer(scene: &Scene) -> Vec<u8> {
+  let mut pixels = vec![0; scene.cam.screen_width as usize * scene.cam.screen_height as usize * 4];
+  render(scene, &mut pixels);
+  pixels
+}
+
+/// Render `scene` and save it to `path` as an OpenEXR image, in linear color
+/// before any exposure or tone mapping, so bright highlights keep their full
+/// range for compositing.
+pub fn render_to_exr(scene: &Scene, path: &str) -> io::Result<()> {
+  let (colors, _, _) = render_hdr(scene);
+  let mut file = BufWriter::new(File::create(path)?);
+  write_exr(
+    &mut file,
+    scene.cam.screen_width as usize,
+    scene.cam.screen_height as usize,
+    &colors,
+  )
+}
+
+/// Render `scene` into `screen`, which must hold 4 bytes (B, G, R, A) for every
+/// pixel. Writing into a caller-provided buffer lets the interactive viewer
+/// render straight into a locked SDL texture.
+///
+/// Returns how many camera rays were traced in total.
+pub fn render(scene: &Scene, screen: &mut [u8]) -> usize {
+  render_with_gbuffer(scene, screen).0
+}
+
+/// The last frame `render_with_history` drew, for it to blend the next one
+/// with.
+pub struct History {
+  /// How much of the previous frames to keep in each new one, from `0` (none:
+  /// every frame stands alone) toward `1` (smoother, but slower to catch up
+  /// with changes).
+  pub weight: f32,
+  colors: Vec<HDRColor>,
+  gbuffer: Vec<GBufferSample>,
+  /// The lights the last frame was lit by.
+  lights: Vec<Light>,
+}
+
+impl History {
+  pub fn new(weight: f32) -> Self {
+    History {
+      weight,
+      colors: vec![],
+      gbuffer: vec![],
+      lights: vec![],
+    }
+  }
+
+  /// Forget the previous frames, say because the camera moved and they no
+  /// longer line up with the next one, or an object's material changed.
+  pub fn reset(&mut self) {
+    self.colors.clear();
+    self.gbuffer.clear();
+    self.lights.clear();
+  }
+}
+
+/// Like `render`, but blend the new frame with the ones before it (see
+/// `temporal_blend`), so the noise in an animation settles down over a few
+/// frames instead of flickering.
+///
+/// The G-buffer only catches geometry changing, so frames where any light has
+/// moved or changed start afresh rather than leaving old shadows and
+/// highlights behind. Remember to `reset` the history yourself whenever the
+/// camera moves or a material changes.
+///
+/// Returns how many camera rays were traced for this frame.
+pub fn render_with_history(scene: &Scene, screen: &mut [u8], history: &mut History) -> usize {
+  let settings = &scene.settings;
+  let (mut colors, rays, gbuffer) = render_hdr(scene);
+
+  if history.colors.len() == colors.len() && history.lights == scene.lights {
+    colors = temporal_blend(
+      &history.colors,
+      &history.gbuffer,
+      &colors,
+      &gbuffer,
+      history.weight,
+    );
+  }
+
+  screen
+    .par_chunks_mut(4)
+    .zip(colors.par_iter())
+    .for_each(|(pixel, color)| encode_pixel(pixel, color, settings));
+
+  history.colors = colors;
+  history.gbuffer = gbuffer;
+  history.lights.clone_from(&scene.lights);
+  rays
+}
+
+/// Auxiliary images ("AOVs") describing the first surface each pixel sees, laid
+/// out like the rendered image. External denoisers use these to tell noise
+/// apart from real detail.
+pub struct Aovs {
+  /// Each surface's material color, without any lighting.
+  pub albedo: Vec<u8>,
+  /// Surface normals, with each component mapped from `[-1, 1]` to `[0, 1]`.
+  pub normal: Vec<u8>,
+}
+
+/// Like `render`, but also produce albedo and normal AOVs. Pixels that don't
+/// hit anything are black in both.
+pub fn render_aovs(scene: &Scene, screen: &mut [u8]) -> Aovs {
+  let (_, gbuffer) = render_with_gbuffer(scene, screen);
+
+  Aovs {
+    // Stored sRGB-encoded, like the image itself:
+    albedo: encode_aov(&gbuffer, |sample| HDRColor {
+      r: linear_to_srgb(sample.albedo.r),
+      g: linear_to_srgb(sample.albedo.g),
+      b: linear_to_srgb(sample.albedo.b),
+    }),
+    normal: encode_aov(&gbuffer, |sample| HDRColor {
+      r: ((sample.normal.x + 1.0) / 2.0) as f32,
+      g: ((sample.normal.y + 1.0) / 2.0) as f32,
+      b: ((sample.normal.z + 1.0) / 2.0) as f32,
+    }),
+  }
+}
+
+// Turn one G-buffer channel into B, G, R, A bytes (without any tone mapping).
+fn encode_aov<F>(gbuffer: &[GBufferSample], color: F) -> Vec<u8>
+where
+  F: Fn(&GBufferSample) -> HDRColor + Sync,
+{
+  let mut pixels = vec![0; gbuffer.len() * 4];
+  pixels
+    .par_chunks_mut(4)
+    .zip(gbuffer.par_iter())
+    .for_each(|(pixel, sample)| {
+      let color = if sample.depth.is_finite() {
+        color(sample).clamp(0.0, 1.0)
+      } else {
+        BLACK
+      };
+      pixel[0] = (255.0 * color.b).round() as u8;
+      pixel[1] = (255.0 * color.g).round() as u8;
+      pixel[2] = (255.0 * color.r).round() as u8;
+      pixel[3] = 255;
+    });
+  pixels
+}
+
+fn render_with_gbuffer(scene: &Scene, screen: &mut [u8]) -> (usize, Vec<GBufferSample>) {
+  let settings = &scene.settings;
+  let (colors, rays, gbuffer) = render_hdr(scene);
+
+  screen
+    .par_chunks_mut(4)
+    .zip(colors.par_iter())
+    .for_each(|(pixel, color)| encode_pixel(pixel, color, settings));
+
+  (rays, gbuffer)
+}
+
+/// Like `render`, but only compute the pixels with `x0 <= x < x1` and
+/// `y0 <= y < y1`, leaving the rest of `screen` as it was. Handy for refining
+/// one part of the image, or for splitting a render up between processes.
+///
+/// With a `seed`, the pixels come out exactly as they would from `render`,
+/// except that denoising and bloom, which need the whole image, are skipped.
+///
+/// Returns how many camera rays were traced.
+pub fn render_region(
+  scene: &Scene,
+  screen: &mut [u8],
+  x0: u32,
+  y0: u32,
+  x1: u32,
+  y1: u32,
+) -> usize {
+  let settings = &scene.settings;
+  let screen_width = scene.cam.screen_width as usize;
+  let (x0, x1) = (x0 as usize, (x1 as usize).min(screen_width));
+  let (y0, y1) = (
+    y0 as usize,
+    (y1 as usize).min(scene.cam.screen_height as usize),
+  );
+
+  screen
+    .par_chunks_mut(4)
+    .enumerate()
+    .filter(|(i, _)| {
+      (x0..x1).contains(&(i % screen_width)) && (y0..y1).contains(&(i / screen_width))
+    })
+    .map(|(i, pixel)| {
+      let (x, y) = (i % screen_width, i / screen_width);
+      let (color, rays) = if settings.mode == RenderMode::Edges {
+        (outline_at(scene, x, y), 1)
+      } else {
+        let mut ignored = GBufferSample::MISS;
+        render_pixel(scene, x, y, &mut ignored)
+      };
+      encode_pixel(pixel, &color, settings);
+      rays
+    })
+    .sum()
+}
+
+// Tone map `color` into one pixel's B, G, R, A bytes.
+fn encode_pixel(pixel: &mut [u8], color: &HDRColor, settings: &RenderSettings) {
+  let display_rgb = color.into_display_rgb(settings.exposure, settings.tone_map);
+  pixel[0] = display_rgb.b;
+  pixel[1] = display_rgb.g;
+  pixel[2] = display_rgb.r;
+  pixel[3] = display_rgb.a;
+}
+
+// Every pixel's linear color (post-processed, but before exposure and tone
+// mapping), how many camera rays it took, and the G-buffer.
+fn render_hdr(scene: &Scene) -> (Vec<HDRColor>, usize, Vec<GBufferSample>) {
+  let settings = &scene.settings;
+  let cam = scene.cam;
+  let screen_width = cam.screen_width as usize;
+  let screen_height = cam.screen_height as usize;
+
+  if settings.mode == RenderMode::Edges {
+    return (
+      object_outlines(scene),
+      screen_width * screen_height,
+      vec![GBufferSample::MISS; screen_width * screen_height],
+    );
+  }
+
+  let (mut colors, gbuffer, rays) = if settings.preview_scale > 1 {
+    render_blocks(scene, settings.preview_scale as usize)
+  } else {
+    render_pixels(scene)
+  };
+
+  if let Some(radius) = settings.denoise_radius {
+    colors = denoise(&colors, &gbuffer, screen_width, screen_height, radius);
+  }
+
+  if let Some(threshold) = settings.bloom_threshold {
+    colors = bloom(
+      &colors,
+      screen_width,
+      screen_height,
+      threshold,
+      settings.bloom_radius,
+    );
+  }
+
+  (colors, rays, gbuffer)
+}
+
+// Every pixel's color (before post-processing), the G-buffer, and how many
+// camera rays they took.
+fn render_pixels(scene: &Scene) -> (Vec<HDRColor>, Vec<GBufferSample>, usize) {
+  let settings = &scene.settings;
+  let screen_width = scene.cam.screen_width as usize;
+  let screen_height = scene.cam.screen_height as usize;
+
+  let mut colors = vec![BLACK; screen_width * screen_height];
+  let mut gbuffer = vec![GBufferSample::MISS; screen_width * screen_height];
+  let mut sample_counts = vec![0; screen_width * screen_height];
+  let progress = Progress::new(screen_width * screen_height);
+
+  colors
+    .par_iter_mut()
+    .zip(gbuffer.par_iter_mut())
+    .zip(sample_counts.par_iter_mut())
+    .enumerate()
+    .for_each(|(i, ((color, gbuffer_sample), sample_count))| {
+      let (pixel_color, samples) =
+        render_pixel(scene, i % screen_width, i / screen_width, gbuffer_sample);
+      *color = pixel_color;
+      *sample_count = samples;
+      if settings.progress {
+        if let Some(percent) = progress.tick() {
+          eprint!("\rrendering: {}%", percent);
+        }
+      }
+    });
+  if settings.progress {
+    eprintln!();
+  }
+
+  (colors, gbuffer, sample_counts.iter().sum())
+}
+
+// Like `render_pixels`, but only render the middle pixel of each `scale` by
+// `scale` block, and blend smoothly between those for the rest. (The G-buffer
+// just repeats each block's middle, since blending normals and depths across
+// edges would make up surfaces that aren't there.)
+fn render_blocks(scene: &Scene, scale: usize) -> (Vec<HDRColor>, Vec<GBufferSample>, usize) {
+  let screen_width = scene.cam.screen_width as usize;
+  let screen_height = scene.cam.screen_height as usize;
+  let columns = screen_width.div_ceil(scale);
+  let rows = screen_height.div_ceil(scale);
+
+  let blocks: Vec<(HDRColor, usize, GBufferSample)> = (0..columns * rows)
+    .into_par_iter()
+    .map(|block| {
+      let x = ((block % columns) * scale + scale / 2).min(screen_width - 1);
+      let y = ((block / columns) * scale + scale / 2).min(screen_height - 1);
+      let mut gbuffer_sample = GBufferSample::MISS;
+      let (color, samples) = render_pixel(scene, x, y, &mut gbuffer_sample);
+      (color, samples, gbuffer_sample)
+    })
+    .collect();
+
+  // Each block's middle lands right on the center of its pixel here:
+  let mut preview = Texture::new(
+    columns,
+    rows,
+    blocks.iter().map(|&(color, _, _)| color).collect(),
+  );
+  preview.wrap_u = Wrap::Clamp;
+  preview.wrap_v = Wrap::Clamp;
+  let to_preview =
+    |i: usize, size: usize| ((i as f64 - (scale / 2) as f64) / scale as f64 + 0.5) / size as f64;
+  let colors = (0..screen_width * screen_height)
+    .into_par_iter()
+    .map(|i| {
+      preview.sample(
+        to_preview(i % screen_width, columns),
+        to_preview(i / screen_width, rows),
+      )
+    })
+    .collect();
+
+  let block_of =
+    |i: usize| &blocks[(i / screen_width / scale) * columns + (i % screen_width) / scale];
+  (
+    colors,
+    (0..screen_width * screen_height)
+      .map(|i| block_of(i).2)
+      .collect(),
+    blocks.iter().map(|&(_, samples, _)| samples).sum(),
+  )
+}
+
+/// Counts finished pixels from any number of threads at once.
+struct Progress {
+  done: AtomicUsize,
+  total: usize,
+}
+
+impl Progress {
+  fn new(total: usize) -> Self {
+    Progress {
+      done: AtomicUsize::new(0),
+      total,
+    }
+  }
+
+  /// Count one more finished pixel, and return the new percentage done if that
+  /// just ticked over to the next whole percent. Each percentage is returned
+  /// exactly once, so only one thread reports it.
+  fn tick(&self) -> Option<usize> {
+    let done = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+    let percent = done * 100 / self.total;
+    if percent != (done - 1) * 100 / self.total {
+      Some(percent)
+    } else {
+      None
+    }
+  }
+}
+
+/// The color of pixel `(x, y)` (before any post-processing), and how many
+/// samples it took, recording what it hit in `gbuffer_sample`.
+fn render_pixel(
+  scene: &Scene,
+  x: usize,
+  y: usize,
+  gbuffer_sample: &mut GBufferSample,
+) -> (HDRColor, usize) {
+  let settings = &scene.settings;
+
+  // Each pixel gets its own generator, seeded by its index when the settings
+  // ask for a reproducible render, so the result doesn't depend on how rayon
+  // happens to split up the work (or on which pixels are rendered at all):
+  let i = y * scene.cam.screen_width as usize + x;
+  let (mut seeded, mut unseeded);
+  let rng: &mut dyn RngCore = match settings.seed {
+    Some(seed) => {
+      seeded = StdRng::seed_from_u64(pixel_seed(seed, i));
+      &mut seeded
+    }
+    None => {
+      unseeded = thread_rng();
+      &mut unseeded
+    }
+  };
+
+  let mut stats = Welford::new();
+  for (dx, dy) in pixel_offsets(rng, settings.samples_per_pixel, settings.pixel_sampling) {
+    stats.add(sample(
+      scene,
+      rng,
+      x as f32 + dx,
+      y as f32 + dy,
+      gbuffer_sample,
+    ));
+  }
+  if let Some(threshold) = settings.adaptive_threshold {
+    while stats.count < settings.max_samples_per_pixel && stats.variance_of_mean() > threshold {
+      let (dx, dy): (f32, f32) = (rng.gen(), rng.gen());
+      stats.add(sample(
+        scene,
+        rng,
+        x as f32 + dx,
+        y as f32 + dy,
+        gbuffer_sample,
+      ));
+    }
+  }
+  (stats.mean, stats.count)
+}
+
+// A seed for pixel number `i` of a render seeded with `seed`. Mixing the two
+// up (with SplitMix64's finalizer) rather than just adding them keeps renders
+// with neighboring seeds from being shifted copies of each other.
+fn pixel_seed(seed: u64, i: usize) -> u64 {
+  let mut z = seed ^ (i as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+  z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+  z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+  z ^ (z >> 31)
+}
+
+/// Trace screen position `(u, v)` and return the color it sees, recording what
+/// it hit in `gbuffer_sample`.
+fn sample(
+  scene: &Scene,
+  rng: &mut dyn RngCore,
+  u: f32,
+  v: f32,
+  gbuffer_sample: &mut GBufferSample,
+) -> HDRColor {
+  // Positions outside the camera's image (e.g. the corners of a fisheye view)
+  // are left black.
+  if scene.cam.chromatic_aberration == 0.0 {
+    return match scene.cam.get_ray_from_uv(rng, u, v) {
+      Some(ray) => trace(scene, rng, &ray, gbuffer_sample),
+      None => BLACK,
+    };
+  }
+
+  // Each channel sees the scene through its own ray, and the G-buffer records
+  // what green saw:
+  let mut ignored = GBufferSample::MISS;
+  match scene.cam.get_channel_rays(rng, u, v) {
+    Some([red, green, blue]) => HDRColor {
+      r: trace(scene, rng, &red, &mut ignored).r,
+      g: trace(scene, rng, &green, gbuffer_sample).g,
+      b: trace(scene, rng, &blue, &mut ignored).b,
+    },
+    None => BLACK,
+  }
+}
+
+/// The color seen along `pixel_ray`, recording what it hit in
+/// `gbuffer_sample`.
+fn trace(
+  scene: &Scene,
+  rng: &mut dyn RngCore,
+  pixel_ray: &Ray,
+  gbuffer_sample: &mut GBufferSample,
+) -> HDRColor {
+  let mut tests = 0;
+  match scene.cast_counting(pixel_ray, 0, &mut tests) {
+    None => match scene.settings.mode {
+      RenderMode::Shaded => scene.background(pixel_ray),
+      RenderMode::Heatmap { max_tests } => heat(tests, max_tests),
+      _ => BLACK,
+    },
+    Some(intersection) => {
+      let point = pixel_ray.origin + pixel_ray.direction * intersection.hit.t;
+      let object = &scene.renderables[intersection.renderable_idx];
+      let normal = intersection.hit.normal;
+      *gbuffer_sample = GBufferSample {
+        normal,
+        depth: intersection.hit.t,
+        albedo: object.material().albedo(&point),
+      };
+      match scene.settings.mode {
+        RenderMode::Shaded => object
+          .material()
+          .color_at(rng, &point, &normal, pixel_ray, scene, 0),
+        RenderMode::Depth { near, far } => {
+          let brightness =
+            (1.0 - (intersection.hit.t - near) / (far - near)).clamp(0.0, 1.0) as f32;
+          HDRColor {
+            r: brightness,
+            g: brightness,
+            b: brightness,
+          }
+        }
+        RenderMode::Heatmap { max_tests } => heat(tests, max_tests),
+        RenderMode::Wireframe { threshold } => {
+          if is_edge(&intersection.hit, pixel_ray, threshold) {
+            HDRColor {
+              r: 1.0,
+              g: 1.0,
+              b: 1.0,
+            }
+          } else {
+            BLACK
+          }
+        }
+        // Drawn by `object_outlines` instead:
+        RenderMode::Edges => BLACK,
+      }
+    }
+  }
+}
+
+/// `RenderMode::Edges`: white wherever the object seen through the middle of a
+/// pixel differs from the one seen through the next pixel over or down.
+fn object_outlines(scene: &Scene) -> Vec<HDRColor> {
+  let width = scene.cam.screen_width as usize;
+  let height = scene.cam.screen_height as usize;
+  (0..width * height)
+    .into_par_iter()
+    .map(|i| outline_at(scene, i % width, i / width))
+    .collect()
+}
+
+/// `object_outlines`' color for pixel `(x, y)`.
+fn outline_at(scene: &Scene, x: usize, y: usize) -> HDRColor {
+  let (width, height) = (scene.cam.screen_width, scene.cam.screen_height);
+  let (x, y) = (x as u32, y as u32);
+  let here = scene.pick(x, y);
+  let differs = |x, y| scene.pick(x, y) != here;
+  if (x + 1 < width && differs(x + 1, y)) || (y + 1 < height && differs(x, y + 1)) {
+    HDRColor::new(1.0, 1.0, 1.0)
+  } else {
+    BLACK
+  }
+}
+
+/// `RenderMode::Heatmap`'s color for a ray that took `tests` intersection tests.
+fn heat(tests: usize, max_tests: usize) -> HDRColor {
+  let heat = (tests as f32 / max_tests.max(1) as f32).min(1.0);
+  if heat < 0.5 {
+    HDRColor {
+      r: 0.0,
+      g: heat * 2.0,
+      b: 1.0 - heat * 2.0,
+    }
+  } else {
+    HDRColor {
+      r: heat * 2.0 - 1.0,
+      g: 2.0 - heat * 2.0,
+      b: 0.0,
+    }
+  }
+}
+
+/// Whether `hit` should be drawn as an edge in `RenderMode::Wireframe`.
+fn is_edge(hit: &Hit, ray: &Ray, threshold: f64) -> bool {
+  // `uv` holds the weights of a triangle's second and third vertices, and the
+  // first's is whatever's left; one of them being small puts us near the
+  // opposite edge.
+  let near_triangle_edge = hit
+    .uv
+    .is_some_and(|(v, w)| v.min(w).min(1.0 - v - w) < threshold);
+  let near_silhouette = hit.normal.dot(&ray.direction).abs() < threshold;
+  near_triangle_edge || near_silhouette
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::camera::Camera;
+  use crate::material::DiffuseColor;
+  use crate::scene::Background;
+  use crate::settings::RenderSettings;
+  use crate::sphere::Sphere;
+  use crate::vector::Vector;
+
+  #[test]
+  fn renders_background_to_buffer() {
+    let settings = RenderSettings {
+      width: 4,
+      height: 3,
+      ..RenderSettings::default()
+    };
+    let scene = Scene {
+      cam: Camera::new(Vector::new(), 45.0, settings.width, settings.height),
+      renderables: vec![],
+      background: Background::Solid(HDRColor {
+        r: 1.0,
+        g: 0.0,
+        b: 0.0,
+      }),
+      lights: vec![],
+      ambient: BLACK,
+      settings,
+      emitters: Default::default(),
+    };
+
+    let pixels = render_to_buffer(&scene);
+
+    assert_eq!(pixels.len(), 4 * 3 * 4);
+    for pixel in pixels.chunks(4) {
+      assert_eq!(pixel, &[0, 0, 255, 255]);
+    }
+  }
+
+  // A white sphere straight ahead of the camera, five units away, lit from
+  // behind the camera.
+  fn sphere_scene(settings: RenderSettings) -> Scene {
+    let mut cam = Camera::new(Vector::new(), 45.0, settings.width, settings.height);
+    cam.set_angle(std::f64::consts::PI);
+    cam.shutter = 0.0;
+    Scene {
+      cam,
+      renderables: vec![Box::new(Sphere::new(
+        Vector {
+          x: 0.0,
+          y: 0.0,
+          z: 5.0,
+        },
+        1.0,
+        std::sync::Arc::new(DiffuseColor {
+          color: HDRColor {
+            r: 1.0,
+            g: 1.0,
+            b: 1.0,
+          },
+        }),
+      ))],
+      background: Background::Solid(BLACK),
+      lights: vec![Light {
+        center: Vector {
+          x: 0.0,
+          y: 0.0,
+          z: -1.0,
+        },
+        color: HDRColor {
+          r: 4.0,
+          g: 4.0,
+          b: 4.0,
+        },
+        radius: 0.0,
+      }],
+      ambient: BLACK,
+      settings,
+      emitters: Default::default(),
+    }
+  }
+
+  #[test]
+  fn history_starts_over_when_a_light_moves() {
+    let settings = RenderSettings {
+      width: 16,
+      height: 16,
+      seed: Some(5),
+      ..RenderSettings::default()
+    };
+    let mut scene = sphere_scene(settings);
+    let mut history = History::new(0.8);
+    let mut screen = vec![0; 16 * 16 * 4];
+    render_with_history(&scene, &mut screen, &mut history);
+    let before = screen.clone();
+
+    // Light the sphere from the side instead:
+    scene.lights[0].center = Vector {
+      x: 5.0,
+      y: 0.0,
+      z: 5.0,
+    };
+    render_with_history(&scene, &mut screen, &mut history);
+
+    let after = render_to_buffer(&scene);
+    assert!(after != before);
+    assert!(screen == after);
+  }
+
+  #[test]
+  fn adaptive_sampling_spends_rays_on_edges() {
+    let settings = RenderSettings {
+      width: 16,
+      height: 16,
+      samples_per_pixel: 4,
+      adaptive_threshold: Some(1e-4),
+      max_samples_per_pixel: 64,
+      ..RenderSettings::default()
+    };
+    let scene = sphere_scene(settings);
+    let pixels = (settings.width * settings.height) as usize;
+    let mut screen = vec![0; pixels * 4];
+
+    let rays = render(&scene, &mut screen);
+
+    // The flat background and the smooth middle of the sphere settle after the
+    // first few samples, while pixels straddling the sphere's edge keep going:
+    assert!(rays > pixels * settings.samples_per_pixel);
+    assert!(rays < pixels * settings.max_samples_per_pixel / 4);
+  }
+
+  #[test]
+  fn depth_mode_shades_by_distance() {
+    let settings = RenderSettings {
+      width: 16,
+      height: 16,
+      mode: RenderMode::Depth {
+        near: 0.0,
+        far: 8.0,
+      },
+      ..RenderSettings::default()
+    };
+    let mut scene = sphere_scene(settings);
+    // Neither of these should matter:
+    scene.background = Background::Solid(HDRColor {
+      r: 1.0,
+      g: 1.0,
+      b: 1.0,
+    });
+    scene.lights.clear();
+
+    let pixels = render_to_buffer(&scene);
+
+    // The middle of the sphere is 4 units away, halfway between near and far:
+    let center = (8 * 16 + 8) * 4;
+    let expected = HDRColor {
+      r: 0.5,
+      g: 0.5,
+      b: 0.5,
+    }
+    .into_display_rgb(1.0, settings.tone_map);
+    assert!((pixels[center] as i32 - expected.b as i32).abs() <= 2);
+    assert_eq!(pixels[center], pixels[center + 1]);
+    // ...and the corners miss it entirely:
+    assert_eq!(&pixels[0..3], &[0, 0, 0]);
+  }
+
+  #[test]
+  fn wireframe_edges_are_near_triangle_edges_and_silhouettes() {
+    let ray = Ray {
+      origin: Vector::new(),
+      direction: Vector {
+        x: 0.0,
+        y: 0.0,
+        z: 1.0,
+      },
+      time: 0.0,
+      channel: None,
+    };
+    let facing = Hit {
+      t: 1.0,
+      normal: Vector {
+        x: 0.0,
+        y: 0.0,
+        z: -1.0,
+      },
+      uv: Some((0.3, 0.3)),
+    };
+    assert!(!is_edge(&facing, &ray, 0.05));
+
+    // Right next to the edge opposite the second vertex:
+    let near_edge = Hit {
+      uv: Some((0.01, 0.5)),
+      ..facing
+    };
+    assert!(is_edge(&near_edge, &ray, 0.05));
+    // ...or the first vertex's weight getting small:
+    let near_edge = Hit {
+      uv: Some((0.5, 0.48)),
+      ..facing
+    };
+    assert!(is_edge(&near_edge, &ray, 0.05));
+
+    // Grazing a sphere-like surface with no `uv`:
+    let grazing = Hit {
+      normal: Vector {
+        x: 1.0,
+        y: 0.0,
+        z: -0.01,
+      }
+      .normalized(),
+      uv: None,
+      ..facing
+    };
+    assert!(is_edge(&grazing, &ray, 0.05));
+  }
+
+  #[test]
+  fn heatmap_counts_intersection_tests() {
+    let settings = RenderSettings {
+      width: 16,
+      height: 16,
+      mode: RenderMode::Heatmap { max_tests: 2 },
+      ..RenderSettings::default()
+    };
+    let scene = sphere_scene(settings);
+    assert_eq!(scene.renderables.len(), 1);
+
+    // A corner ray misses the only object, after testing just that one:
+    let mut rng = thread_rng();
+    let corner = scene.cam.get_ray_from_uv(&mut rng, 0.0, 0.0).unwrap();
+    let mut tests = 0;
+    assert!(scene.cast_counting(&corner, 0, &mut tests).is_none());
+    assert_eq!(tests, 1);
+
+    // ...so it's colored halfway up the ramp, whether or not it hits:
+    let pixels = render_to_buffer(&scene);
+    let halfway = heat(1, 2).into_display_rgb(1.0, settings.tone_map);
+    let center = (8 * 16 + 8) * 4;
+    for pixel in [0, center].iter() {
+      assert_eq!(
+        &pixels[*pixel..*pixel + 3],
+        &[halfway.b, halfway.g, halfway.r]
+      );
+    }
+  }
+
+  #[test]
+  fn edges_outline_objects() {
+    let settings = RenderSettings {
+      width: 16,
+      height: 16,
+      mode: RenderMode::Edges,
+      ..RenderSettings::default()
+    };
+    let scene = sphere_scene(settings);
+
+    let pixels = render_to_buffer(&scene);
+    let white = |x: usize, y: usize| pixels[(y * 16 + x) * 4] == 255;
+
+    // Neither the middle of the sphere nor the empty corner is an edge...
+    assert!(!white(8, 8));
+    assert!(!white(0, 0));
+    // ...but somewhere between the two, along every row through the sphere,
+    // there is one:
+    for y in 6..10 {
+      assert!((0..8).any(|x| white(x, y)), "no edge in row {}", y);
+    }
+  }
+
+  #[test]
+  fn render_region_only_touches_its_pixels() {
+    let settings = RenderSettings {
+      width: 16,
+      height: 16,
+      seed: Some(7),
+      ..RenderSettings::default()
+    };
+    let scene = sphere_scene(settings);
+    let full = render_to_buffer(&scene);
+
+    let mut pixels = vec![42; 16 * 16 * 4];
+    let rays = render_region(&scene, &mut pixels, 4, 6, 12, 10);
+    assert_eq!(rays, 8 * 4 * settings.samples_per_pixel);
+    for y in 0..16 {
+      for x in 0..16 {
+        let at = (y * 16 + x) * 4;
+        let pixel = &pixels[at..at + 4];
+        if (4..12).contains(&x) && (6..10).contains(&y) {
+          assert_eq!(pixel, &full[at..at + 4], "({}, {})", x, y);
+        } else {
+          assert_eq!(pixel, &[42, 42, 42, 42], "({}, {})", x, y);
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn neighboring_seeds_give_unrelated_pixels() {
+    use std::collections::HashSet;
+    // Seed 1's pixels mustn't just be seed 0's moved along by one:
+    for i in 0..100 {
+      assert_ne!(pixel_seed(1, i), pixel_seed(0, i + 1));
+    }
+    let seeds: HashSet<u64> = (0..16)
+      .flat_map(|seed| (0..1000).map(move |i| pixel_seed(seed, i)))
+      .collect();
+    assert_eq!(seeds.len(), 16 * 1000);
+  }
+
+  #[test]
+  fn preview_blends_between_block_middles() {
+    let settings = RenderSettings {
+      width: 16,
+      height: 16,
+      seed: Some(3),
+      ..RenderSettings::default()
+    };
+    let full = render_to_buffer(&sphere_scene(settings));
+    let preview_settings = RenderSettings {
+      preview_scale: 4,
+      ..settings
+    };
+    let mut preview = vec![0; 16 * 16 * 4];
+    let rays = render(&sphere_scene(preview_settings), &mut preview);
+
+    assert_eq!(rays, 4 * 4 * settings.samples_per_pixel);
+    let pixel = |pixels: &[u8], x: usize, y: usize| {
+      let at = (y * 16 + x) * 4;
+      pixels[at..at + 4].to_vec()
+    };
+    // Each block's middle pixel comes out just as it would at full size...
+    for y in (2..16).step_by(4) {
+      for x in (2..16).step_by(4) {
+        assert_eq!(pixel(&preview, x, y), pixel(&full, x, y));
+      }
+    }
+    // ...and the pixels between two of them fall somewhere between the two:
+    for y in (2..16).step_by(4) {
+      for x in (2..12).step_by(4) {
+        let (left, right) = (pixel(&preview, x, y), pixel(&preview, x + 4, y));
+        for between in x + 1..x + 4 {
+          let between = pixel(&preview, between, y);
+          for c in 0..3 {
+            let (low, high) = (left[c].min(right[c]), left[c].max(right[c]));
+            assert!(
+              (low..=high).contains(&between[c]),
+              "{:?} {:?} {:?}",
+              left,
+              between,
+              right
+            );
+          }
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn progress_reports_each_percent_once() {
+    for &total in &[7, 100, 1234] {
+      let progress = Progress::new(total);
+      let reported: Vec<usize> = (0..total).filter_map(|_| progress.tick()).collect();
+      // Small images skip some percentages, but never repeat one:
+      assert_eq!(reported.len(), total.min(100));
+      assert!(reported.windows(2).all(|pair| pair[0] < pair[1]));
+      assert_eq!(reported.last(), Some(&100));
+    }
+  }
+
+  #[test]
+  fn aovs_describe_the_first_hit() {
+    let settings = RenderSettings {
+      width: 16,
+      height: 16,
+      ..RenderSettings::default()
+    };
+    let mut screen = vec![0; 16 * 16 * 4];
+
+    let aovs = render_aovs(&sphere_scene(settings), &mut screen);
+
+    // Dead center, we're looking straight at the white sphere, whose normal
+    // points back at us (-Z):
+    let center = (8 * 16 + 8) * 4;
+    assert_eq!(&aovs.albedo[center..center + 4], &[255, 255, 255, 255]);
+    let normal = &aovs.normal[center..center + 4];
+    assert!(normal[0] < 5, "{:?}", normal);
+    assert!((normal[1] as i32 - 128).abs() < 5, "{:?}", normal);
+    assert!((normal[2] as i32 - 128).abs() < 5, "{:?}", normal);
+    // ...while the corners see nothing at all:
+    assert_eq!(&aovs.albedo[0..3], &[0, 0, 0]);
+    assert_eq!(&aovs.normal[0..3], &[0, 0, 0]);
+  }
+}
@@ -0,0 +1,155 @@
+use rand::Rng;
+
+use crate::material::{HDRColor, BLACK};
+use crate::settings::PixelSampling;
+
+/// Sub-pixel offsets (each in `[0, 1)`) at which to sample a pixel.
+///
+/// When `sampling` is `Stratified` and `samples` is a perfect square, the pixel
+/// is split into a `sqrt(samples)` x `sqrt(samples)` grid and one randomly
+/// jittered sample is placed in each cell. This spreads samples out more evenly
+/// than picking them all independently (which tends to clump), so edges
+/// converge faster.
+///
+/// Otherwise we fall back to independent uniform offsets. A single sample
+/// always lands on the pixel's corner, which is the same as rendering without
+/// antialiasing.
+pub fn pixel_offsets<R: Rng + ?Sized>(
+  rng: &mut R,
+  samples: usize,
+  sampling: PixelSampling,
+) -> Vec<(f32, f32)> {
+  if samples == 1 {
+    return vec![(0.0, 0.0)];
+  }
+
+  let strata = (samples as f64).sqrt().round() as usize;
+  if sampling == PixelSampling::Random || strata * strata != samples {
+    return (0..samples).map(|_| (rng.gen(), rng.gen())).collect();
+  }
+
+  jittered_grid(rng, strata)
+}
+
+/// One randomly placed point (each coordinate in `[0, 1)`) in every cell of a
+/// `strata` x `strata` grid over the unit square.
+pub fn jittered_grid<R: Rng + ?Sized>(rng: &mut R, strata: usize) -> Vec<(f32, f32)> {
+  let cell_size = 1.0 / strata as f32;
+  let mut offsets = Vec::with_capacity(strata * strata);
+  for cell_y in 0..strata {
+    for cell_x in 0..strata {
+      offsets.push((
+        (cell_x as f32 + rng.gen::<f32>()) * cell_size,
+        (cell_y as f32 + rng.gen::<f32>()) * cell_size,
+      ));
+    }
+  }
+  offsets
+}
+
+/// Running mean and variance of a stream of samples, updated one sample at a
+/// time with Welford's algorithm (which, unlike summing squares, doesn't lose
+/// precision when the variance is small relative to the mean).
+#[derive(Clone, Copy)]
+pub struct Welford {
+  pub count: usize,
+  pub mean: HDRColor,
+  // Sum of squared differences from the mean, per channel.
+  m2: HDRColor,
+}
+
+impl Welford {
+  pub fn new() -> Self {
+    Welford {
+      count: 0,
+      mean: BLACK,
+      m2: BLACK,
+    }
+  }
+
+  pub fn add(&mut self, sample: HDRColor) {
+    self.count += 1;
+    let delta = sample - self.mean;
+    self.mean += delta / self.count as f32;
+    self.m2 += delta * (sample - self.mean);
+  }
+
+  /// How uncertain `mean` still is: the variance of the samples divided by
+  /// how many there are, for whichever channel is noisiest.
+  pub fn variance_of_mean(&self) -> f32 {
+    if self.count < 2 {
+      return f32::INFINITY;
+    }
+    let variance = self.m2 / (self.count * (self.count - 1)) as f32;
+    variance.r.max(variance.g).max(variance.b)
+  }
+}
+
+impl Default for Welford {
+  fn default() -> Self {
+    Welford::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rand::SeedableRng;
+
+  #[test]
+  fn stratified_offsets_cover_every_cell_once() {
+    let mut rng = rand::thread_rng();
+    let strata = 4;
+    let offsets = pixel_offsets(&mut rng, strata * strata, PixelSampling::Stratified);
+
+    let mut counts = vec![0; strata * strata];
+    for (x, y) in offsets {
+      assert!((0.0..1.0).contains(&x));
+      assert!((0.0..1.0).contains(&y));
+      let cell_x = (x * strata as f32) as usize;
+      let cell_y = (y * strata as f32) as usize;
+      counts[cell_y * strata + cell_x] += 1;
+    }
+    assert!(counts.iter().all(|&count| count == 1));
+  }
+
+  #[test]
+  fn non_square_counts_fall_back_to_uniform() {
+    let mut rng = rand::thread_rng();
+    let offsets = pixel_offsets(&mut rng, 5, PixelSampling::Stratified);
+
+    assert_eq!(offsets.len(), 5);
+    for (x, y) in offsets {
+      assert!((0.0..1.0).contains(&x));
+      assert!((0.0..1.0).contains(&y));
+    }
+  }
+
+  #[test]
+  fn random_sampling_ignores_the_grid() {
+    // Sixteen independent samples all land in a grid of sixteen cells once
+    // each only about once in a million tries:
+    let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+    let strata = 4;
+    let offsets = pixel_offsets(&mut rng, strata * strata, PixelSampling::Random);
+
+    let mut counts = vec![0; strata * strata];
+    for (x, y) in offsets {
+      counts[(y * strata as f32) as usize * strata + (x * strata as f32) as usize] += 1;
+    }
+    assert!(counts.iter().any(|&count| count != 1));
+  }
+
+  #[test]
+  fn welford_matches_direct_mean_and_variance() {
+    let mut stats = Welford::new();
+    for &x in [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0].iter() {
+      stats.add(HDRColor { r: x, g: 0.0, b: x });
+    }
+
+    assert_eq!(stats.count, 8);
+    assert!((stats.mean.r - 5.0).abs() < 1e-6);
+    // The sample variance is 32 / 7, and the mean is over 8 samples:
+    assert!((stats.variance_of_mean() - 32.0 / 7.0 / 8.0).abs() < 1e-6);
+  }
+}
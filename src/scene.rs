@@ -1,65 +1,1128 @@
+use core::f64::consts::PI;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::sync::{Arc, OnceLock};
+
+use crate::aabb::Aabb;
 use crate::camera::Camera;
 use crate::material::*;
+use crate::plane::Plane;
 use crate::ray::Ray;
+use crate::settings::RenderSettings;
+use crate::sphere::{Sphere, SPHERE_BATCH};
+use crate::texture::{Texture, Wrap};
 use crate::vector::Vector;
 
-#[derive(Copy, Clone)]
+#[derive(PartialEq, Copy, Clone)]
 pub struct Light {
   pub center: Vector,
   pub color: HDRColor,
   pub radius: f32,
 }
 
+impl Light {
+  /// The total (luminance-weighted) light this gives off in every direction.
+  ///
+  /// A light's `color` is already its whole output however big it is: shading
+  /// averages over points on its surface rather than adding them up, and
+  /// `emitters` folds an emissive object's area into the color it gives its
+  /// stand-in light. So `radius` spreads the light out, but doesn't change how
+  /// much there is.
+  pub fn power(&self) -> f32 {
+    4.0 * std::f32::consts::PI * self.color.luminance()
+  }
+
+  /// A direction from `point` toward the light, picked from `(u, v)` in
+  /// `[0, 1)²` so that every direction in the cone the light's sphere fills
+  /// (as seen from `point`) is equally likely. Returns that direction, how far
+  /// along it the light's surface is, and how much it's weighted by (the
+  /// cone's solid angle over the light's cross-section).
+  ///
+  /// That weight is `1 / distance²` for a point light, and stays about that for
+  /// small or distant ones, so a light's `color` means the same at any radius.
+  pub fn sample_from(&self, point: &Vector, u: f64, v: f64) -> (Vector, f64, f64) {
+    let to_center = self.center - point;
+    let distance_squared = to_center.length_squared();
+    let distance = distance_squared.sqrt();
+    let axis = to_center / distance;
+    let radius = self.radius as f64;
+
+    // The cone's half-angle `θ` has `sin²θ = r² / d²`; from inside the light,
+    // it's the whole hemisphere. (`1 - cos θ` is worked out from `sin²θ`
+    // rather than subtracted, which would lose everything for tiny lights.)
+    let sin2_max = (radius * radius / distance_squared).min(1.0);
+    let cos_max = (1.0 - sin2_max).sqrt();
+    let one_minus_cos_max = sin2_max / (1.0 + cos_max);
+
+    let cos_theta = 1.0 - u * one_minus_cos_max;
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let direction = axis * cos_theta + Vector::on_unit_disk(v, 1.0, &axis) * sin_theta;
+
+    // The nearer of the two places that direction crosses the sphere:
+    let along = distance * cos_theta;
+    let across_squared = distance_squared * sin_theta * sin_theta;
+    let surface = along - (radius * radius - across_squared).max(0.0).sqrt();
+
+    // The cone's solid angle is `2π(1 - cos θ)`, and the light's cross-section
+    // `πr²`:
+    let weight = 2.0 / (distance_squared * (1.0 + cos_max));
+    (direction, surface, weight)
+  }
+}
+
+/// What a ray sees when it doesn't hit anything.
+#[derive(Clone)]
+pub enum Background {
+  Solid(HDRColor),
+  /// Blends from `bottom` (looking straight down) to `top` (looking straight
+  /// up) by the ray's vertical direction.
+  Gradient {
+    top: HDRColor,
+    bottom: HDRColor,
+  },
+  /// A latitude/longitude ("equirectangular") environment image wrapped around
+  /// the whole scene. Make these with `Background::equirect`, which sets the
+  /// texture up to wrap around the horizon but not over the poles.
+  Equirect(Texture),
+}
+
+/// Map a direction onto equirectangular texture coordinates in `[0, 1]`.
+///
+/// `u` wraps around the horizon (with +Z at the center of the image), and `v`
+/// goes from straight up (0) to straight down (1).
+pub fn equirect_uv(direction: &Vector) -> (f64, f64) {
+  let u = 0.5 + direction.x.atan2(direction.z) / (2.0 * PI);
+  let v = direction.y.clamp(-1.0, 1.0).acos() / PI;
+  (u, v)
+}
+
+impl Background {
+  /// Wrap `texture` around the whole scene as an equirectangular environment.
+  pub fn equirect(mut texture: Texture) -> Background {
+    texture.wrap_u = Wrap::Repeat;
+    texture.wrap_v = Wrap::Clamp;
+    Background::Equirect(texture)
+  }
+
+  /// Load an equirectangular environment image from any format SDL_image
+  /// understands. The image is assumed to be sRGB-encoded.
+  pub fn load_equirect(path: &str) -> Result<Background, String> {
+    Ok(Background::equirect(Texture::load(path)?))
+  }
+
+  /// Load an equirectangular environment from a Radiance `.hdr` file. Unlike
+  /// `load_equirect`, this keeps the image's full (linear) range, so bright
+  /// skies and suns stay brighter than white in reflections.
+  pub fn load_hdr(path: &str) -> Result<Background, String> {
+    let bytes = std::fs::read(path).map_err(|error| format!("{}: {}", path, error))?;
+    parse_hdr(&bytes)
+  }
+
+  pub fn color(&self, direction: &Vector) -> HDRColor {
+    match self {
+      Background::Solid(color) => *color,
+      Background::Gradient { top, bottom } => {
+        let t = ((direction.y + 1.0) / 2.0).clamp(0.0, 1.0) as f32;
+        bottom.lerp(top, t)
+      }
+      Background::Equirect(texture) => {
+        let (u, v) = equirect_uv(direction);
+        texture.sample(u, v)
+      }
+    }
+  }
+}
+
+/// The most pixels `parse_hdr` will decode; about 3GB once they're colors.
+const MAX_HDR_PIXELS: usize = 1 << 28;
+
+/// Decode the contents of a Radiance `.hdr` (RGBE) file into an equirectangular
+/// `Background`. Only the usual top-to-bottom, left-to-right orientation is
+/// supported.
+pub fn parse_hdr(bytes: &[u8]) -> Result<Background, String> {
+  let mut rest = bytes;
+  let mut line = || {
+    let end = rest
+      .iter()
+      .position(|&b| b == b'\n')
+      .ok_or("unexpected end of .hdr header")?;
+    let text = String::from_utf8_lossy(&rest[..end]).into_owned();
+    rest = &rest[end + 1..];
+    Ok::<_, String>(text)
+  };
+
+  let magic = line()?;
+  if !magic.starts_with("#?") {
+    return Err(String::from("not a Radiance .hdr file"));
+  }
+  // Variables like `FORMAT=` and `EXPOSURE=`, up to a blank line:
+  loop {
+    let variable = line()?;
+    if variable.is_empty() {
+      break;
+    }
+    if variable.starts_with("FORMAT=") && variable != "FORMAT=32-bit_rle_rgbe" {
+      return Err(format!("unsupported .hdr {}", variable));
+    }
+  }
+  let resolution = line()?;
+  let (height, width) = match resolution.split_whitespace().collect::<Vec<_>>()[..] {
+    ["-Y", height, "+X", width] => (
+      height.parse::<usize>().map_err(|error| error.to_string())?,
+      width.parse::<usize>().map_err(|error| error.to_string())?,
+    ),
+    _ => return Err(format!("unsupported .hdr orientation: {}", resolution)),
+  };
+  let size = match width.checked_mul(height) {
+    Some(size) if size > 0 && size <= MAX_HDR_PIXELS => size,
+    _ => return Err(format!("unsupported .hdr size: {}", resolution)),
+  };
+
+  let mut data = rest.iter().copied();
+  let mut next = || data.next().ok_or("unexpected end of .hdr pixels");
+  let mut pixels = Vec::with_capacity(size);
+  let mut scanline = vec![[0u8; 4]; width];
+  for _ in 0..height {
+    let start = [next()?, next()?, next()?, next()?];
+    let run_length_encoded =
+      (8..0x8000).contains(&width) && start[0] == 2 && start[1] == 2 && start[2] & 0x80 == 0;
+    if run_length_encoded {
+      // Each channel in turn, as a mix of runs of one repeated byte and
+      // stretches of bytes copied as they are:
+      for channel in 0..4 {
+        let mut x = 0;
+        while x < width {
+          let count = next()? as usize;
+          let (count, repeated) = if count > 128 {
+            (count - 128, Some(next()?))
+          } else {
+            (count, None)
+          };
+          if count == 0 || x + count > width {
+            return Err(String::from("corrupt .hdr scanline"));
+          }
+          for pixel in &mut scanline[x..x + count] {
+            pixel[channel] = match repeated {
+              Some(byte) => byte,
+              None => next()?,
+            };
+          }
+          x += count;
+        }
+      }
+    } else {
+      // Plain RGBE, four bytes per pixel:
+      scanline[0] = start;
+      for pixel in scanline.iter_mut().skip(1) {
+        *pixel = [next()?, next()?, next()?, next()?];
+      }
+    }
+    pixels.extend(scanline.iter().map(|&rgbe| rgbe_to_color(rgbe)));
+  }
+
+  Ok(Background::equirect(Texture::new(width, height, pixels)))
+}
+
+/// Each channel is an 8-bit mantissa sharing the exponent in the fourth byte.
+fn rgbe_to_color([r, g, b, e]: [u8; 4]) -> HDRColor {
+  if e == 0 {
+    return BLACK;
+  }
+  let scale = 2f32.powi(e as i32 - (128 + 8));
+  HDRColor::new(r as f32 * scale, g as f32 * scale, b as f32 * scale)
+}
+
+/// Everything there is to render. Start one with `Scene::new`.
 pub struct Scene {
   pub cam: Camera,
+  /// Changing these in place once the scene has been rendered leaves the
+  /// `emitters` it found then behind; go through `renderable_mut` (or `add`
+  /// and `remove_renderable`) instead.
   pub renderables: Vec<Box<dyn Renderable>>,
-  pub bg_color: HDRColor,
+  pub background: Background,
   pub lights: Vec<Light>,
+  /// Constant light received by diffuse surfaces regardless of shadowing, so
+  /// areas facing away from every light aren't pitch black.
+  pub ambient: HDRColor,
+  pub settings: RenderSettings,
+  /// Worked out by `emitters` the first time shading needs it, since every
+  /// diffuse hit does.
+  pub(crate) emitters: OnceLock<Vec<(usize, Light)>>,
 }
 
 #[derive(Copy, Clone)]
 pub struct Intersection {
   pub renderable_idx: usize,
-  pub t: f64,
+  pub hit: Hit,
   pub depth: u8,
 }
 
+/// Where a ray meets a `Renderable`'s surface, along with whatever the
+/// renderable worked out about that spot while finding it (so shading doesn't
+/// have to redo the geometry).
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Hit {
+  /// Distance along the ray.
+  pub t: f64,
+  /// Unit surface normal, facing the way the surface should be lit from.
+  pub normal: Vector,
+  /// Surface coordinates of the hit, for renderables that have them. For a
+  /// triangle, these are the barycentric weights of its second and third
+  /// vertices.
+  pub uv: Option<(f64, f64)>,
+}
+
 impl Scene {
+  /// An empty scene, with a black background and no lights, to be filled in
+  /// with `add` and friends.
+  pub fn new(cam: Camera, settings: RenderSettings) -> Self {
+    Scene {
+      cam,
+      renderables: vec![],
+      background: Background::Solid(BLACK),
+      lights: vec![],
+      ambient: BLACK,
+      settings,
+      emitters: OnceLock::new(),
+    }
+  }
+
+  pub fn add<R: Renderable + 'static>(&mut self, renderable: R) -> &mut Scene {
+    self.renderables.push(Box::new(renderable));
+    self.emitters.take();
+    self
+  }
+
+  /// Renderable number `idx`, to change however you like.
+  pub fn renderable_mut(&mut self, idx: usize) -> &mut dyn Renderable {
+    self.emitters.take();
+    &mut *self.renderables[idx]
+  }
+
+  pub fn add_sphere(
+    &mut self,
+    center: Vector,
+    radius: f64,
+    material: Arc<dyn Material>,
+  ) -> &mut Scene {
+    self.add(Sphere::new(center, radius, material))
+  }
+
+  pub fn add_plane(
+    &mut self,
+    center: Vector,
+    normal: Vector,
+    material: Arc<dyn Material>,
+  ) -> &mut Scene {
+    self.add(Plane::new(center, normal, material))
+  }
+
+  pub fn add_light(&mut self, center: Vector, color: HDRColor, radius: f32) -> &mut Scene {
+    self.lights.push(Light {
+      center,
+      color,
+      radius,
+    });
+    self
+  }
+
+  /// Like `add`, but taking and returning the scene itself, so a whole scene
+  /// can be built in one expression starting from `Scene::new(..)`.
+  pub fn with<R: Renderable + 'static>(mut self, renderable: R) -> Self {
+    self.add(renderable);
+    self
+  }
+
+  /// Like `add_light`, but by value; see `with`.
+  pub fn with_light(mut self, center: Vector, color: HDRColor, radius: f32) -> Self {
+    self.add_light(center, color, radius);
+    self
+  }
+
+  /// The same scene, seen through `cam` instead.
+  pub fn with_camera(mut self, cam: Camera) -> Self {
+    self.cam = cam;
+    self
+  }
+
+  /// Take renderable number `idx` out of the scene and hand it back, or
+  /// `None` if there's no such renderable. Every renderable after it moves
+  /// down an index.
+  pub fn remove_renderable(&mut self, idx: usize) -> Option<Box<dyn Renderable>> {
+    if idx >= self.renderables.len() {
+      return None;
+    }
+    self.emitters.take();
+    Some(self.renderables.remove(idx))
+  }
+
+  /// A box around every finite renderable in the scene (infinite ones, like
+  /// planes, are left out). Empty if there aren't any.
+  pub fn bounds(&self) -> Aabb {
+    self
+      .renderables
+      .iter()
+      .filter_map(|object| object.bounds())
+      .fold(Aabb::EMPTY, |bounds, object_bounds| {
+        bounds.union(&object_bounds)
+      })
+  }
+
+  pub fn background(&self, ray: &Ray) -> HDRColor {
+    self.background.color(&ray.direction)
+  }
+
   pub fn cast(&self, ray: &Ray, depth: u8) -> Option<Intersection> {
+    self.cast_counting(ray, depth, &mut 0)
+  }
+
+  /// The index (into `renderables`) of the object seen through the center of
+  /// pixel (`x`, `y`), if there is one.
+  pub fn pick(&self, x: u32, y: u32) -> Option<usize> {
+    // Only the shutter uses this, and it doesn't matter when we look:
+    let mut rng = StdRng::seed_from_u64(0);
+    let ray = self
+      .cam
+      .get_ray_from_uv(&mut rng, x as f32 + 0.5, y as f32 + 0.5)?;
+    self
+      .cast(&ray, 0)
+      .map(|intersection| intersection.renderable_idx)
+  }
+
+  /// Like `cast`, but adds the number of intersection tests it took to
+  /// `tests`.
+  pub fn cast_counting(&self, ray: &Ray, depth: u8, tests: &mut usize) -> Option<Intersection> {
+    // NaNs would otherwise just quietly miss everything, so catch whatever
+    // produced them while it's still easy to find:
+    debug_assert!(
+      ray.origin.is_finite() && ray.direction.is_finite(),
+      "ray must be finite: {:?}",
+      ray
+    );
+    debug_assert!(
+      ray.is_normalized() || ray.is_degenerate(),
+      "ray direction must be unit length: {:?}",
+      ray
+    );
     let mut maybe_closest_intersection: Option<Intersection> = None;
+    let is_closer = |closest: &Option<Intersection>, t: f64| match closest {
+      Some(closest_intersection) => t < closest_intersection.hit.t,
+      None => true,
+    };
+
     let mut renderable_idx = 0;
-    for object in &self.renderables {
-      match object.intersects(ray) {
-        None => (),
-        Some(t) => match maybe_closest_intersection {
-          None => {
-            maybe_closest_intersection = Some(Intersection {
-              renderable_idx,
-              t,
-              depth,
-            })
-          }
-          Some(closest_intersection) => {
-            if closest_intersection.t > t {
+    while renderable_idx < self.renderables.len() {
+      // Runs of spheres get tested several at a time:
+      if let Some(spheres) = self.sphere_batch(renderable_idx) {
+        let distances = Sphere::distances_batch(&spheres, ray);
+        *tests += SPHERE_BATCH;
+        for (lane, distance) in distances.iter().enumerate() {
+          if let Some(t) = *distance {
+            // Only work out the normal for hits we're keeping:
+            if is_closer(&maybe_closest_intersection, t) {
               maybe_closest_intersection = Some(Intersection {
-                renderable_idx,
-                t,
+                renderable_idx: renderable_idx + lane,
+                hit: spheres[lane].hit_at(ray, t),
                 depth,
-              })
+              });
             }
           }
-        },
+        }
+        renderable_idx += SPHERE_BATCH;
+        continue;
       }
 
+      *tests += 1;
+      if let Some(hit) = self.renderables[renderable_idx].intersects(ray) {
+        if is_closer(&maybe_closest_intersection, hit.t) {
+          maybe_closest_intersection = Some(Intersection {
+            renderable_idx,
+            hit,
+            depth,
+          });
+        }
+      }
       renderable_idx += 1;
     }
 
     maybe_closest_intersection
   }
+
+  /// Every finite renderable with an emissive material, by index, along with
+  /// a spherical `Light` standing in for it: one around its bounds, as bright
+  /// as a glowing disk that size (in the units `DiffuseColor` lights with, so
+  /// it matches what bouncing into the object directly would add).
+  pub fn emitters(&self) -> &[(usize, Light)] {
+    self.emitters.get_or_init(|| {
+      self
+        .renderables
+        .iter()
+        .enumerate()
+        .filter_map(|(renderable_idx, object)| {
+          let emission = object.material().emission()?;
+          let bounds = object.bounds()?;
+          let half_size = bounds.diagonal() / 2.0;
+          let radius = half_size.x.max(half_size.y).max(half_size.z);
+          let light = Light {
+            center: bounds.center(),
+            color: emission * (radius * radius) as f32,
+            radius: radius as f32,
+          };
+          Some((renderable_idx, light))
+        })
+        .collect()
+    })
+  }
+
+  /// The combined `power` of every light in the scene, including emissive
+  /// objects.
+  pub fn total_light_power(&self) -> f32 {
+    let lights = self.lights.iter().map(Light::power);
+    let emitters = self.emitters().iter().map(|(_, light)| light.power());
+    lights.chain(emitters).sum()
+  }
+
+  /// Whether `ray` hits anything closer than `max_t`. Unlike `cast`, this can
+  /// stop at the first hit it finds, which is all a shadow ray needs to know.
+  pub fn cast_any(&self, ray: &Ray, max_t: f64) -> bool {
+    let mut renderable_idx = 0;
+    while renderable_idx < self.renderables.len() {
+      if let Some(spheres) = self.sphere_batch(renderable_idx) {
+        let distances = Sphere::distances_batch(&spheres, ray);
+        if distances.iter().flatten().any(|&t| t < max_t) {
+          return true;
+        }
+        renderable_idx += SPHERE_BATCH;
+        continue;
+      }
+
+      match self.renderables[renderable_idx].intersects(ray) {
+        Some(hit) if hit.t < max_t => return true,
+        _ => renderable_idx += 1,
+      }
+    }
+    false
+  }
+
+  /// The `SPHERE_BATCH` renderables starting at `start`, if they're all
+  /// spheres.
+  #[inline]
+  fn sphere_batch(&self, start: usize) -> Option<[&Sphere; SPHERE_BATCH]> {
+    let renderables = self.renderables.get(start..start + SPHERE_BATCH)?;
+    let first = renderables[0].as_sphere()?;
+    let mut spheres = [first; SPHERE_BATCH];
+    for (sphere, renderable) in spheres.iter_mut().zip(renderables).skip(1) {
+      *sphere = renderable.as_sphere()?;
+    }
+    Some(spheres)
+  }
+}
+
+/// A reproducibly heavy scene for profiling: an `n`×`n`×`n` grid of unit
+/// spheres, cycling through diffuse, mirror and glass materials, framed by the
+/// camera and lit from above. Rendered at the default settings.
+pub fn grid_scene(n: usize) -> Scene {
+  let materials: [Arc<dyn Material>; 5] = [
+    Arc::new(DiffuseColor::new(0.8, 0.2, 0.2)),
+    Arc::new(DiffuseColor::new(0.2, 0.8, 0.2)),
+    Arc::new(DiffuseColor::new(0.2, 0.2, 0.8)),
+    Arc::new(MIRROR),
+    Arc::new(GLASS),
+  ];
+
+  let settings = RenderSettings::default();
+  let mut scene = Scene::new(
+    Camera::new(Vector::new(), 45.0, settings.width, settings.height),
+    settings,
+  );
+  scene.background = Background::Gradient {
+    top: HDRColor::new(0.5, 0.7, 1.0),
+    bottom: HDRColor::new(1.0, 1.0, 1.0),
+  };
+
+  let spacing = 3.0;
+  for x in 0..n {
+    for y in 0..n {
+      for z in 0..n {
+        let center = Vector {
+          x: x as f64 * spacing,
+          y: y as f64 * spacing,
+          z: z as f64 * spacing,
+        };
+        let material = &materials[(x + y + z) % materials.len()];
+        scene.add_sphere(center, 1.0, material.clone());
+      }
+    }
+  }
+
+  let bounds = scene.bounds();
+  let top = bounds.center()
+    + Vector {
+      x: 0.0,
+      y: bounds.diagonal().y,
+      z: 0.0,
+    };
+  scene.add_light(top, HDRColor::new(3.0, 3.0, 3.0), 0.0);
+  scene.cam.frame(
+    &bounds,
+    Vector {
+      x: 0.0,
+      y: 0.0,
+      z: 1.0,
+    },
+  );
+  scene
 }
 
 pub trait Renderable: Sync {
-  fn intersects(&self, ray: &Ray) -> Option<f64>;
-  fn normal(&self, point: &Vector) -> Vector;
+  fn intersects(&self, ray: &Ray) -> Option<Hit>;
   fn material(&self) -> &dyn Material;
+
+  /// Shade the object with `material` from now on.
+  fn set_material(&mut self, material: Arc<dyn Material>);
+
+  /// A box enclosing the whole object, or `None` if it's infinite (like a
+  /// `Plane`).
+  fn bounds(&self) -> Option<Aabb> {
+    None
+  }
+
+  /// Every point along the whole line through `ray` (including behind its
+  /// origin, so `t` may be negative) where it crosses into or out of the
+  /// object's interior, nearest first; `true` means entering. Normals here
+  /// always point out of the interior.
+  ///
+  /// This is what `Csg` needs to combine solids. Objects without an interior
+  /// report no crossings, so they can't be combined.
+  fn intersect_all(&self, _ray: &Ray) -> Vec<(Hit, bool)> {
+    vec![]
+  }
+
+  /// `Some` for plain `Sphere`s, which `Scene::cast` can test in batches.
+  fn as_sphere(&self) -> Option<&Sphere> {
+    None
+  }
+
+  /// Like `as_sphere`, but for moving or resizing the sphere in place.
+  fn as_sphere_mut(&mut self) -> Option<&mut Sphere> {
+    None
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rand::Rng;
+
+  fn point_light(color: HDRColor) -> Light {
+    Light {
+      center: Vector::new(),
+      color,
+      radius: 0.0,
+    }
+  }
+
+  #[test]
+  fn light_power_scales_with_color() {
+    let white = HDRColor {
+      r: 1.0,
+      g: 1.0,
+      b: 1.0,
+    };
+    let unit = point_light(white);
+    assert!((unit.power() - 4.0 * std::f32::consts::PI).abs() < 1e-5);
+
+    let doubled = point_light(white * 2.0);
+    assert!((doubled.power() - 2.0 * unit.power()).abs() < 1e-5);
+
+    // Green looks brighter than blue at the same strength:
+    let green = point_light(HDRColor {
+      r: 0.0,
+      g: 1.0,
+      b: 0.0,
+    });
+    let blue = point_light(HDRColor {
+      r: 0.0,
+      g: 0.0,
+      b: 1.0,
+    });
+    assert!(green.power() > blue.power());
+  }
+
+  #[test]
+  fn light_samples_stay_inside_the_light() {
+    let point = Vector::new();
+    let light = Light {
+      center: Vector {
+        x: 0.0,
+        y: 4.0,
+        z: 3.0,
+      },
+      color: HDRColor::new(1.0, 1.0, 1.0),
+      radius: 1.5,
+    };
+    let mut rng = rand::thread_rng();
+    for _ in 0..1000 {
+      let (u, v) = (rng.gen(), rng.gen());
+      let (direction, distance, _) = light.sample_from(&point, u, v);
+      assert!((direction.length() - 1.0).abs() < 1e-9);
+      let on_light = point + direction * distance;
+      assert!((on_light.distance(&light.center) - 1.5).abs() < 1e-6);
+    }
+  }
+
+  #[test]
+  fn small_lights_sample_like_points() {
+    let point = Vector {
+      x: 1.0,
+      y: 0.0,
+      z: 0.0,
+    };
+    let light = |radius| Light {
+      center: Vector {
+        x: 1.0,
+        y: 2.0,
+        z: 0.0,
+      },
+      color: HDRColor::new(1.0, 1.0, 1.0),
+      radius,
+    };
+    let (direction, distance, weight) = light(0.0).sample_from(&point, 0.3, 0.7);
+    assert!(direction.approx_eq(
+      &Vector {
+        x: 0.0,
+        y: 1.0,
+        z: 0.0,
+      },
+      1e-12
+    ));
+    assert_eq!(distance, 2.0);
+    assert_eq!(weight, 0.25);
+
+    // Shrinking a light toward a point converges on the same weight:
+    let mut last_error = f64::INFINITY;
+    for &radius in &[1.0, 0.1, 0.01, 1e-4] {
+      let (_, _, weight) = light(radius).sample_from(&point, 0.5, 0.5);
+      let error = (weight - 0.25).abs();
+      assert!(error < last_error);
+      last_error = error;
+    }
+    assert!(last_error < 1e-8);
+  }
+
+  #[test]
+  fn total_light_power_adds_up_every_light() {
+    let mut scene = grid_scene(1);
+    let one = scene.total_light_power();
+    let light = scene.lights[0];
+    scene.add_light(light.center, light.color, light.radius);
+    assert!((scene.total_light_power() - 2.0 * one).abs() < 1e-3);
+  }
+
+  #[test]
+  fn grid_scene_has_n_cubed_spheres() {
+    let scene = grid_scene(3);
+    assert_eq!(scene.renderables.len(), 27);
+    assert_eq!(scene.lights.len(), 1);
+
+    // The camera can see the middle of the grid:
+    let (u, v) = (
+      scene.settings.width as f32 / 2.0,
+      scene.settings.height as f32 / 2.0,
+    );
+    let ray = scene
+      .cam
+      .get_ray_from_uv(&mut rand::thread_rng(), u, v)
+      .unwrap();
+    assert!(scene.cast(&ray, 0).is_some());
+  }
+
+  #[test]
+  fn pick_finds_the_object_under_a_pixel() {
+    let scene = grid_scene(1);
+    let (width, height) = (scene.settings.width, scene.settings.height);
+    let picked = scene.pick(width / 2, height / 2);
+    assert_eq!(picked, Some(0));
+    assert_eq!(scene.renderables[0].material().name(), "DiffuseColor");
+    // Nothing out in the corners but the background:
+    assert_eq!(scene.pick(0, 0), None);
+  }
+
+  #[test]
+  fn set_material_swaps_what_an_object_is_made_of() {
+    let mut scene = grid_scene(1);
+    let (x, y) = (scene.settings.width / 2, scene.settings.height / 2);
+    let point = Vector::new();
+    let idx = scene.pick(x, y).unwrap();
+    assert_eq!(scene.renderables[idx].material().albedo(&point).g, 0.2);
+
+    scene.renderables[idx].set_material(Arc::new(MIRROR));
+    assert_eq!(scene.renderables[idx].material().name(), "Mirror");
+    assert_eq!(scene.renderables[idx].material().albedo(&point).g, 0.8);
+  }
+
+  #[test]
+  fn fluent_building_matches_adding_by_hand() {
+    let cam = Camera::new(Vector::new(), 45.0, 4, 4);
+    let material = || Arc::new(DiffuseColor::new(0.5, 0.5, 0.5));
+    let at = |z| Vector { x: 0.0, y: 0.0, z };
+    let white = HDRColor::new(1.0, 1.0, 1.0);
+
+    let mut manual = Scene::new(cam, RenderSettings::default());
+    manual
+      .renderables
+      .push(Box::new(Sphere::new(at(3.0), 1.0, material())));
+    manual
+      .renderables
+      .push(Box::new(Plane::new(at(9.0), at(-1.0), material())));
+    manual.lights.push(Light {
+      center: at(0.0),
+      color: white,
+      radius: 0.0,
+    });
+
+    let moved = Camera::new(at(-1.0), 45.0, 4, 4);
+    let fluent = Scene::new(cam, RenderSettings::default())
+      .with(Sphere::new(at(3.0), 1.0, material()))
+      .with(Plane::new(at(9.0), at(-1.0), material()))
+      .with_light(at(0.0), white, 0.0)
+      .with_camera(moved);
+
+    assert_eq!(fluent.renderables.len(), manual.renderables.len());
+    assert_eq!(fluent.lights.len(), manual.lights.len());
+    assert_eq!(fluent.cam.eye, moved.eye);
+  }
+
+  #[test]
+  fn emitters_keep_up_with_the_renderables() {
+    let mut scene = grid_scene(1);
+    assert!(scene.emitters().is_empty());
+
+    let glow = Arc::new(Emissive {
+      color: HDRColor::new(2.0, 2.0, 2.0),
+    });
+    scene.renderable_mut(0).set_material(glow.clone());
+    assert_eq!(scene.emitters().len(), 1);
+    scene.add_sphere(Vector::new(), 1.0, glow);
+    assert_eq!(scene.emitters().len(), 2);
+    scene.remove_renderable(0);
+    assert_eq!(scene.emitters().len(), 1);
+  }
+
+  #[test]
+  fn renderables_can_be_moved_and_removed() {
+    let mut scene = grid_scene(1);
+    let (x, y) = (scene.settings.width / 2, scene.settings.height / 2);
+    assert_eq!(scene.pick(x, y), Some(0));
+
+    let sphere = scene.renderables[0].as_sphere_mut().unwrap();
+    sphere.center.y += 100.0;
+    assert_eq!(scene.pick(x, y), None);
+    scene.renderables[0].as_sphere_mut().unwrap().center.y -= 100.0;
+    assert_eq!(scene.pick(x, y), Some(0));
+
+    let removed = scene.remove_renderable(0).unwrap();
+    assert!(removed.as_sphere().is_some());
+    assert!(scene.renderables.is_empty());
+    assert_eq!(scene.pick(x, y), None);
+    assert!(scene.remove_renderable(0).is_none());
+  }
+
+  #[test]
+  fn batched_cast_finds_the_nearest_hit() {
+    // Spheres in a row along the view, with a plane splitting up the run so
+    // some get batched and some don't:
+    let mut scene = Scene::new(
+      Camera::new(Vector::new(), 45.0, 1, 1),
+      RenderSettings::default(),
+    );
+    for i in (0..9).rev() {
+      scene.add_sphere(
+        Vector {
+          x: 0.0,
+          y: 0.0,
+          z: 3.0 + i as f64 * 2.5,
+        },
+        1.0,
+        Arc::new(MIRROR),
+      );
+      if i == 6 {
+        scene.add_plane(
+          Vector {
+            x: 0.0,
+            y: -5.0,
+            z: 0.0,
+          },
+          Vector {
+            x: 0.0,
+            y: 1.0,
+            z: 0.0,
+          },
+          Arc::new(MIRROR),
+        );
+      }
+    }
+
+    for &(y, z) in [(0.0, 1.0), (0.3, 1.0), (-1.0, 1.0), (2.0, 1.0)].iter() {
+      let ray = Ray::new(Vector::new(), Vector { x: 0.0, y, z }, 0.0);
+      let expected = scene
+        .renderables
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, object)| object.intersects(&ray).map(|hit| (idx, hit)))
+        .fold(
+          None,
+          |nearest: Option<(usize, Hit)>, (idx, hit)| match nearest {
+            Some((_, nearest_hit)) if nearest_hit.t <= hit.t => nearest,
+            _ => Some((idx, hit)),
+          },
+        );
+      let actual = scene
+        .cast(&ray, 0)
+        .map(|intersection| (intersection.renderable_idx, intersection.hit));
+      assert_eq!(actual, expected);
+    }
+  }
+
+  #[test]
+  fn cast_any_agrees_with_cast() {
+    let scene = grid_scene(3);
+    let mut rng = rand::thread_rng();
+    for _ in 0..500 {
+      let (u, v) = (rng.gen_range(0.0, 320.0), rng.gen_range(0.0, 320.0));
+      let ray = scene.cam.get_ray_from_uv(&mut rng, u, v).unwrap();
+      let max_t = rng.gen_range(0.0, 40.0);
+      let nearest = scene.cast(&ray, 0).map(|intersection| intersection.hit.t);
+      assert_eq!(
+        scene.cast_any(&ray, max_t),
+        nearest.is_some_and(|t| t < max_t)
+      );
+    }
+  }
+
+  #[test]
+  #[cfg(debug_assertions)]
+  #[should_panic(expected = "unit length")]
+  fn cast_rejects_unnormalized_rays() {
+    let scene = Scene::new(
+      Camera::new(Vector::new(), 45.0, 1, 1),
+      RenderSettings::default(),
+    );
+    let ray = Ray {
+      origin: Vector::new(),
+      direction: Vector {
+        x: 0.0,
+        y: 0.0,
+        z: 2.0,
+      },
+      time: 0.0,
+      channel: None,
+    };
+    scene.cast(&ray, 0);
+  }
+
+  #[test]
+  fn builds_scenes_by_chaining() {
+    let mut scene = Scene::new(
+      Camera::new(Vector::new(), 45.0, 1, 1),
+      RenderSettings::default(),
+    );
+    scene
+      .add_plane(
+        Vector::new(),
+        Vector {
+          x: 0.0,
+          y: 1.0,
+          z: 0.0,
+        },
+        Arc::new(MIRROR),
+      )
+      .add_light(
+        Vector {
+          x: 0.0,
+          y: 5.0,
+          z: 0.0,
+        },
+        HDRColor {
+          r: 1.0,
+          g: 1.0,
+          b: 1.0,
+        },
+        0.0,
+      );
+    for i in 0..3 {
+      scene.add_sphere(
+        Vector {
+          x: i as f64 * 3.0,
+          y: 1.0,
+          z: 10.0,
+        },
+        1.0,
+        Arc::new(GLASS),
+      );
+    }
+
+    assert_eq!(scene.renderables.len(), 4);
+    assert_eq!(scene.lights.len(), 1);
+    // Everything but the (infinite) plane:
+    assert_eq!(
+      scene.bounds(),
+      Aabb::new(
+        Vector {
+          x: -1.0,
+          y: 0.0,
+          z: 9.0,
+        },
+        Vector {
+          x: 7.0,
+          y: 2.0,
+          z: 11.0,
+        },
+      )
+    );
+  }
+
+  #[test]
+  fn gradient_background_blends_by_direction() {
+    let top = HDRColor {
+      r: 0.4,
+      g: 0.7,
+      b: 1.0,
+    };
+    let bottom = HDRColor {
+      r: 1.0,
+      g: 1.0,
+      b: 1.0,
+    };
+    let background = Background::Gradient { top, bottom };
+
+    let up = background.color(&Vector {
+      x: 0.0,
+      y: 1.0,
+      z: 0.0,
+    });
+    let down = background.color(&Vector {
+      x: 0.0,
+      y: -1.0,
+      z: 0.0,
+    });
+    let horizon = background.color(&Vector {
+      x: 0.0,
+      y: 0.0,
+      z: 1.0,
+    });
+
+    assert_eq!(up.b, top.b);
+    assert_eq!(up.r, top.r);
+    assert_eq!(down.r, bottom.r);
+    assert_eq!(horizon.r, 0.7);
+  }
+
+  #[test]
+  fn equirect_uv_centers_positive_z() {
+    let (u, v) = equirect_uv(&Vector {
+      x: 0.0,
+      y: 0.0,
+      z: 1.0,
+    });
+    assert_eq!((u, v), (0.5, 0.5));
+
+    let (u, v) = equirect_uv(&Vector {
+      x: 1.0,
+      y: 0.0,
+      z: 0.0,
+    });
+    assert_eq!((u, v), (0.75, 0.5));
+
+    let (_, v) = equirect_uv(&Vector {
+      x: 0.0,
+      y: 1.0,
+      z: 0.0,
+    });
+    assert_eq!(v, 0.0);
+  }
+
+  #[test]
+  fn equirect_background_samples_pixels() {
+    let red = HDRColor {
+      r: 1.0,
+      g: 0.0,
+      b: 0.0,
+    };
+    let background = Background::equirect(Texture::new(4, 2, vec![red; 8]));
+    let color = background.color(&Vector {
+      x: 0.3,
+      y: 0.2,
+      z: -0.9,
+    });
+    assert!((color.r - 1.0).abs() < 0.0001);
+    assert_eq!(color.g, 0.0);
+  }
+
+  // A `width` by `height` .hdr image filled with one RGBE color, either stored
+  // plainly or run-length encoded.
+  fn constant_hdr(width: usize, height: usize, rgbe: [u8; 4], run_length_encoded: bool) -> Vec<u8> {
+    let mut bytes = format!(
+      "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n",
+      height, width
+    )
+    .into_bytes();
+    for _ in 0..height {
+      if run_length_encoded {
+        bytes.extend_from_slice(&[2, 2, (width >> 8) as u8, width as u8]);
+        for &byte in &rgbe {
+          let mut left = width;
+          while left > 0 {
+            let run = left.min(127);
+            bytes.extend_from_slice(&[128 + run as u8, byte]);
+            left -= run;
+          }
+        }
+      } else {
+        for _ in 0..width {
+          bytes.extend_from_slice(&rgbe);
+        }
+      }
+    }
+    bytes
+  }
+
+  #[test]
+  fn constant_hdr_environment_is_the_same_everywhere() {
+    // (0.5, 0.25, 1.0), sharing an exponent of 2^-7:
+    let rgbe = [64, 32, 128, 129];
+    for &run_length_encoded in &[false, true] {
+      let background = parse_hdr(&constant_hdr(200, 3, rgbe, run_length_encoded)).unwrap();
+      let mut rng = rand::thread_rng();
+      for _ in 0..100 {
+        let color = background.color(&Vector::random_norm(&mut rng));
+        assert_eq!((color.r, color.g, color.b), (0.5, 0.25, 1.0));
+      }
+    }
+  }
+
+  #[test]
+  fn hdr_keeps_values_brighter_than_white() {
+    // 8.0 in every channel:
+    let background = parse_hdr(&constant_hdr(4, 2, [128, 128, 128, 132], false)).unwrap();
+    let color = background.color(&Vector {
+      x: 0.0,
+      y: 1.0,
+      z: 0.0,
+    });
+    assert_eq!(color.r, 8.0);
+  }
+
+  #[test]
+  fn rejects_files_that_are_not_hdr() {
+    assert!(parse_hdr(b"P6\n1 1\n255\n\0\0\0").is_err());
+    let mut truncated = constant_hdr(16, 4, [1, 2, 3, 128], true);
+    truncated.truncate(truncated.len() - 5);
+    assert!(parse_hdr(&truncated).is_err());
+    // Empty, or too big to even try:
+    for resolution in &["-Y 0 +X 4", "-Y 1 +X 0", "-Y 4000000000 +X 4000000000"] {
+      let header = format!("#?RADIANCE\n\n{}\n", resolution);
+      assert!(parse_hdr(header.as_bytes()).is_err());
+    }
+  }
 }
@@ -0,0 +1,133 @@
+use crate::material::ToneMap;
+
+/// What `render` draws at each pixel.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum RenderMode {
+  /// The full picture: materials, lights, reflections and all.
+  Shaded,
+  /// Just how far away the first thing each ray hits is, as grayscale: white at
+  /// `near` (or closer), fading to black at `far`. Misses are black. Ignores
+  /// materials and lighting entirely, so it's handy for checking geometry.
+  Depth { near: f64, far: f64 },
+  /// White outlines on black: a pixel is an edge if its first hit lands within
+  /// `threshold` (in barycentric coordinates) of a triangle's edge, or on a
+  /// silhouette, where the surface turns away from the camera (the cosine
+  /// between the normal and the ray is below `threshold`). Outlines each face
+  /// of a mesh.
+  Wireframe { threshold: f64 },
+  /// White outlines on black around each object: a pixel is an edge if the
+  /// object seen through its middle isn't the one seen through its neighbor's
+  /// (to the right or below). Unlike `Wireframe`, this outlines whole objects,
+  /// including where one passes in front of another.
+  Edges,
+  /// How much work each pixel's first ray took to trace, as a color ramp from
+  /// blue (no intersection tests) through green to red (`max_tests` or more).
+  /// Shows where the scene is expensive to render.
+  Heatmap { max_tests: usize },
+}
+
+/// How shadow rays pick points on area lights (lights with a `radius`).
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum LightSampling {
+  /// Independent uniformly random points.
+  Random,
+  /// One random point in each cell of a grid over the light's surface, so
+  /// samples can't clump together and soft shadows converge faster. The
+  /// number of samples is rounded up to a perfect square.
+  Stratified,
+}
+
+/// Where within each pixel camera rays go.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum PixelSampling {
+  /// Independent uniformly random points.
+  Random,
+  /// One random point in each cell of a grid over the pixel, so samples can't
+  /// clump together and edges converge faster. Only applies when the number
+  /// of samples is a perfect square; otherwise it's the same as `Random`.
+  Stratified,
+}
+
+/// Knobs that control how a scene gets rendered, as opposed to what's in it.
+#[derive(Debug, Clone, Copy)]
+pub struct RenderSettings {
+  /// Output resolution in pixels.
+  pub width: u32,
+  pub height: u32,
+  pub mode: RenderMode,
+  /// Rays traced per pixel.
+  pub samples_per_pixel: usize,
+  pub pixel_sampling: PixelSampling,
+  /// With adaptive sampling, pixels keep getting more samples (beyond
+  /// `samples_per_pixel`, up to `max_samples_per_pixel`) until the variance of
+  /// their mean drops below this threshold, so effort goes to noisy pixels
+  /// (edges, soft shadows) rather than flat ones. `None` samples every pixel
+  /// exactly `samples_per_pixel` times.
+  pub adaptive_threshold: Option<f32>,
+  pub max_samples_per_pixel: usize,
+  /// How many times a ray may bounce/refract before we give up and return
+  /// black.
+  pub max_depth: u8,
+  /// How far off a surface rays bouncing off (or through) it start, so that
+  /// rounding error doesn't make them hit the very surface they're leaving
+  /// ("shadow acne"). Too small for the scene's scale and surfaces speckle
+  /// with false shadows; too large and shadows visibly detach from the
+  /// objects casting them. Scale it with the scene.
+  pub epsilon: f64,
+  pub light_sampling: LightSampling,
+  /// How many times light may bounce between diffuse surfaces before reaching
+  /// the camera. `0` only lights diffuse surfaces directly from lights.
+  pub diffuse_bounces: u8,
+  /// At every diffuse surface, aim shadow rays straight at each light ("next
+  /// event estimation"), rather than only finding lights by bouncing into them
+  /// at random. Far less noisy, and the only way to see point lights; turning
+  /// it off is mostly useful for comparison.
+  pub next_event_estimation: bool,
+  /// Linear multiplier applied to radiance before tone mapping.
+  pub exposure: f32,
+  pub tone_map: ToneMap,
+  /// Radius (in pixels) of the edge-aware denoise filter applied after
+  /// rendering, or `None` to skip denoising entirely.
+  pub denoise_radius: Option<usize>,
+  /// Channel intensity above which pixels start to glow, or `None` to skip
+  /// bloom.
+  pub bloom_threshold: Option<f32>,
+  pub bloom_radius: usize,
+  /// Seed for all random sampling; with a seed, rendering the same scene twice
+  /// produces identical output. `None` draws fresh randomness every frame.
+  pub seed: Option<u64>,
+  /// Only trace the middle pixel of every `preview_scale` by `preview_scale`
+  /// block, and blend between those for the rest: a blurry image, but many
+  /// times faster, for moving around interactively. `1` traces every pixel.
+  pub preview_scale: u32,
+  /// Report how far along each frame is on stderr. Handy for long headless
+  /// renders; too chatty for the interactive viewer.
+  pub progress: bool,
+}
+
+impl Default for RenderSettings {
+  fn default() -> Self {
+    RenderSettings {
+      width: 320,
+      height: 320,
+      mode: RenderMode::Shaded,
+      samples_per_pixel: 1,
+      pixel_sampling: PixelSampling::Stratified,
+      adaptive_threshold: None,
+      max_samples_per_pixel: 64,
+      max_depth: 15,
+      epsilon: 0.0001,
+      light_sampling: LightSampling::Random,
+      diffuse_bounces: 0,
+      next_event_estimation: true,
+      exposure: 1.0,
+      tone_map: ToneMap::Clamp,
+      denoise_radius: None,
+      bloom_threshold: None,
+      bloom_radius: 6,
+      seed: None,
+      preview_scale: 1,
+      progress: false,
+    }
+  }
+}
@@ -0,0 +1,157 @@
+use crate::render::render_region;
+use crate::scene::Scene;
+
+/// Width and height, in pixels, of the square tiles the image is dealt out in.
+pub const TILE_SIZE: u32 = 16;
+
+/// One of `total` roughly equal parts of an image, so that several processes
+/// (or machines) can each render a part of the same scene and merge the
+/// results.
+///
+/// The image is cut into `TILE_SIZE` tiles, numbered across each row from the
+/// top-left, and shard `index` gets every `total`th tile starting from tile
+/// `index`. Dealing tiles out like this (rather than giving each shard one
+/// solid block) keeps expensive parts of the image from all landing on the
+/// same shard.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Shard {
+  pub index: usize,
+  pub total: usize,
+}
+
+impl Shard {
+  /// The `(x0, y0, x1, y1)` rectangles (with `x1` and `y1` exclusive) this
+  /// shard covers in a `width` by `height` image.
+  pub fn tiles(&self, width: u32, height: u32) -> Vec<(u32, u32, u32, u32)> {
+    let across = width.div_ceil(TILE_SIZE);
+    let down = height.div_ceil(TILE_SIZE);
+    (0..(across * down) as usize)
+      .skip(self.index)
+      .step_by(self.total.max(1))
+      .map(|tile| {
+        let (x0, y0) = (
+          tile as u32 % across * TILE_SIZE,
+          tile as u32 / across * TILE_SIZE,
+        );
+        (
+          x0,
+          y0,
+          (x0 + TILE_SIZE).min(width),
+          (y0 + TILE_SIZE).min(height),
+        )
+      })
+      .collect()
+  }
+}
+
+/// Render just `shard`'s tiles of `scene`, into a full-size buffer of B, G, R,
+/// A bytes like `render_to_buffer`'s. Everything outside them is left zeroed.
+///
+/// Give the scene a `seed` so every shard samples its pixels exactly as a
+/// single process would. Like `render_region`, this skips denoising and bloom.
+pub fn render_shard(scene: &Scene, shard: Shard) -> Vec<u8> {
+  let (width, height) = (scene.cam.screen_width, scene.cam.screen_height);
+  let mut pixels = vec![0; width as usize * height as usize * 4];
+  for (x0, y0, x1, y1) in shard.tiles(width, height) {
+    render_region(scene, &mut pixels, x0, y0, x1, y1);
+  }
+  pixels
+}
+
+/// Stitch the buffers `render_shard` produced for a `width` by `height` image
+/// back into one. Tiles belonging to shards that are missing stay zeroed.
+pub fn merge_shards(width: u32, height: u32, shards: &[(Shard, Vec<u8>)]) -> Vec<u8> {
+  let mut merged = vec![0; width as usize * height as usize * 4];
+  for (shard, pixels) in shards {
+    for (x0, y0, x1, y1) in shard.tiles(width, height) {
+      for y in y0..y1 {
+        let start = (y * width + x0) as usize * 4;
+        let end = (y * width + x1) as usize * 4;
+        merged[start..end].copy_from_slice(&pixels[start..end]);
+      }
+    }
+  }
+  merged
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::camera::Camera;
+  use crate::material::{DiffuseColor, HDRColor, GLASS};
+  use crate::render::render_to_buffer;
+  use crate::settings::RenderSettings;
+  use crate::vector::Vector;
+  use std::sync::Arc;
+
+  #[test]
+  fn shards_cover_every_tile_once() {
+    // 3 tiles across (the last one narrow) and 2 down:
+    let (width, height) = (40, 20);
+    let mut covered = vec![0; (width * height) as usize];
+    for index in 0..4 {
+      for (x0, y0, x1, y1) in (Shard { index, total: 4 }).tiles(width, height) {
+        for y in y0..y1 {
+          for x in x0..x1 {
+            covered[(y * width + x) as usize] += 1;
+          }
+        }
+      }
+    }
+    assert!(covered.iter().all(|&count| count == 1));
+  }
+
+  #[test]
+  fn merged_shards_match_a_single_render() {
+    // Big enough for some ragged tiles along the right and bottom, with a soft
+    // shadow and some glass so there's plenty of randomness to get wrong:
+    let (width, height) = (40, 36);
+    let settings = RenderSettings {
+      width,
+      height,
+      seed: Some(11),
+      ..RenderSettings::default()
+    };
+    let mut cam = Camera::new(Vector::new(), 45.0, width, height);
+    cam.set_angle(std::f64::consts::PI);
+    let mut scene = Scene::new(cam, settings);
+    scene
+      .add_sphere(
+        Vector {
+          x: -1.0,
+          y: 0.0,
+          z: 6.0,
+        },
+        1.0,
+        Arc::new(DiffuseColor::new(0.8, 0.5, 0.2)),
+      )
+      .add_sphere(
+        Vector {
+          x: 1.0,
+          y: 0.0,
+          z: 5.0,
+        },
+        1.0,
+        Arc::new(GLASS),
+      )
+      .add_light(
+        Vector {
+          x: 0.0,
+          y: 4.0,
+          z: 2.0,
+        },
+        HDRColor::new(3.0, 3.0, 3.0),
+        0.5,
+      );
+
+    let total = 3;
+    let shards: Vec<(Shard, Vec<u8>)> = (0..total)
+      .map(|index| {
+        let shard = Shard { index, total };
+        (shard, render_shard(&scene, shard))
+      })
+      .collect();
+
+    assert!(merge_shards(width, height, &shards) == render_to_buffer(&scene));
+  }
+}
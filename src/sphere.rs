@@ -1,18 +1,26 @@
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
 use crate::material::Material;
 use crate::ray::Ray;
-use crate::scene::Renderable;
+use crate::scene::{equirect_uv, Hit, Renderable};
 use crate::vector::Vector;
 
-#[derive(Copy, Clone)]
+/// How many spheres `Sphere::distances_batch` tests at once.
+pub const SPHERE_BATCH: usize = 4;
+
+#[derive(Clone)]
 pub struct Sphere {
   pub center: Vector,
-  pub radius: f64,
-  pub radius_squared: f64,
-  pub material: &'static dyn Material,
+  // Kept private so `radius_squared` (which `distance` leans on) can't fall
+  // out of step; see `set_radius`.
+  radius: f64,
+  radius_squared: f64,
+  pub material: Arc<dyn Material>,
 }
 
 impl Sphere {
-  pub fn new(center: Vector, radius: f64, material: &'static dyn Material) -> Self {
+  pub fn new(center: Vector, radius: f64, material: Arc<dyn Material>) -> Self {
     Sphere {
       center,
       radius,
@@ -20,10 +28,178 @@ impl Sphere {
       material,
     }
   }
+
+  pub fn radius(&self) -> f64 {
+    self.radius
+  }
+
+  pub fn set_radius(&mut self, radius: f64) {
+    self.radius = radius;
+    self.radius_squared = radius * radius;
+  }
+
+  fn ball(&self) -> Ball {
+    Ball {
+      center: self.center,
+      radius_squared: self.radius_squared,
+    }
+  }
+
+  pub(crate) fn hit_at(&self, ray: &Ray, t: f64) -> Hit {
+    self.ball().hit_at(ray, t)
+  }
+
+  /// `distance` for several spheres at once.
+  ///
+  /// Each step of the math is done for every sphere before moving on to the
+  /// next, on plain arrays with one "lane" per sphere, which the compiler can
+  /// turn into SIMD instructions. `distance` itself is still what tests one
+  /// sphere at a time.
+  #[inline]
+  pub fn distances_batch(
+    spheres: &[&Sphere; SPHERE_BATCH],
+    ray: &Ray,
+  ) -> [Option<f64>; SPHERE_BATCH] {
+    let mut distances = [None; SPHERE_BATCH];
+    if ray.is_degenerate() {
+      return distances;
+    }
+
+    let (o, d) = (ray.origin, ray.direction);
+    let mut to_center_x = [0.0; SPHERE_BATCH];
+    let mut to_center_y = [0.0; SPHERE_BATCH];
+    let mut to_center_z = [0.0; SPHERE_BATCH];
+    let mut radius_squared = [0.0; SPHERE_BATCH];
+    for lane in 0..SPHERE_BATCH {
+      to_center_x[lane] = spheres[lane].center.x - o.x;
+      to_center_y[lane] = spheres[lane].center.y - o.y;
+      to_center_z[lane] = spheres[lane].center.z - o.z;
+      radius_squared[lane] = spheres[lane].radius_squared;
+    }
+
+    let mut t = [0.0; SPHERE_BATCH];
+    for lane in 0..SPHERE_BATCH {
+      t[lane] = d.x * to_center_x[lane] + d.y * to_center_y[lane] + d.z * to_center_z[lane];
+    }
+
+    let mut y_squared = [0.0; SPHERE_BATCH];
+    for lane in 0..SPHERE_BATCH {
+      let y_x = d.x * t[lane] - to_center_x[lane];
+      let y_y = d.y * t[lane] - to_center_y[lane];
+      let y_z = d.z * t[lane] - to_center_z[lane];
+      y_squared[lane] = y_x * y_x + y_y * y_y + y_z * y_z;
+    }
+
+    // Most rays miss most spheres, so skip the rest when they miss all of
+    // these:
+    if (0..SPHERE_BATCH).all(|lane| y_squared[lane] > radius_squared[lane]) {
+      return distances;
+    }
+
+    let mut x = [0.0; SPHERE_BATCH];
+    for lane in 0..SPHERE_BATCH {
+      x[lane] = (radius_squared[lane] - y_squared[lane]).max(0.0).sqrt();
+    }
+
+    // The same case analysis as the end of `distance`:
+    for lane in 0..SPHERE_BATCH {
+      let (t0, t1) = (t[lane] - x[lane], t[lane] + x[lane]);
+      distances[lane] = if y_squared[lane] > radius_squared[lane] || t1 < 0.0 {
+        None
+      } else if t0 < 0.0 {
+        Some(t1)
+      } else {
+        Some(t0)
+      }
+      .filter(|t| t.is_finite());
+    }
+    distances
+  }
+
+  /// Texture coordinates for the point on a sphere with the given (unit)
+  /// surface `normal`: `u` is the longitude as a fraction of a full turn, and
+  /// `v` runs from the north pole (0) to the south pole (1), so a world map in
+  /// the usual equirectangular layout wraps on the right way up. This is the
+  /// same mapping environments use (see `equirect_uv`).
+  ///
+  /// Every point on a pole gets the same `u`, and coordinates are always
+  /// finite, even for normals that rounding has pushed slightly past the poles.
+  pub fn uv(normal: &Vector) -> (f64, f64) {
+    equirect_uv(normal)
+  }
+
+  /// Unit vectors along the surface at the point with the given (unit)
+  /// `normal`, pointing the way `u` and `v` (from `Sphere::uv`) increase:
+  /// east, and south. At the poles, where east isn't defined, we pick +X.
+  pub fn tangent_frame(normal: &Vector) -> (Vector, Vector) {
+    let east = Vector {
+      x: normal.z,
+      y: 0.0,
+      z: -normal.x,
+    };
+    let east = if east.length_squared() < 1e-18 {
+      Vector {
+        x: 1.0,
+        y: 0.0,
+        z: 0.0,
+      }
+    } else {
+      east.normalized()
+    };
+    (east, east.cross(normal))
+  }
 }
 
 impl Renderable for Sphere {
-  fn intersects(&self, ray: &Ray) -> Option<f64> {
+  fn intersects(&self, ray: &Ray) -> Option<Hit> {
+    self.ball().intersects(ray)
+  }
+
+  fn as_sphere(&self) -> Option<&Sphere> {
+    Some(self)
+  }
+
+  fn as_sphere_mut(&mut self) -> Option<&mut Sphere> {
+    Some(self)
+  }
+
+  fn material(&self) -> &dyn Material {
+    &*self.material
+  }
+
+  fn set_material(&mut self, material: Arc<dyn Material>) {
+    self.material = material;
+  }
+
+  fn bounds(&self) -> Option<Aabb> {
+    let extent = Vector {
+      x: self.radius,
+      y: self.radius,
+      z: self.radius,
+    };
+    Some(Aabb::new(self.center - extent, self.center + extent))
+  }
+
+  fn intersect_all(&self, ray: &Ray) -> Vec<(Hit, bool)> {
+    self.ball().intersect_all(ray)
+  }
+}
+
+// Just the shape of a sphere, without the material, so a `MovingSphere` can
+// be tested where it is at a ray's time without building a whole `Sphere`.
+#[derive(Clone, Copy)]
+struct Ball {
+  center: Vector,
+  radius_squared: f64,
+}
+
+impl Ball {
+  /// How far along `ray` it first hits the sphere.
+  fn distance(&self, ray: &Ray) -> Option<f64> {
+    if ray.is_degenerate() {
+      return None;
+    }
+
     // ```text
     //                      , - ~ ~ ~ - ,
     //                  , '               ' ,
@@ -138,10 +314,10 @@ impl Renderable for Sphere {
 
     // If both intersection points are positive, we want the smaller of the two
     // since that is closest to our ray origin:
-    Some(t0.min(t1))
+    Some(t0.min(t1)).filter(|t| t.is_finite())
   }
 
-  fn normal(&self, point: &Vector) -> Vector {
+  fn normal_at(&self, point: &Vector) -> Vector {
     // The normal at this intersection point can be determined by drawing a
     // vector from our sphere's center to our intersection point and normalizing
     // it.
@@ -150,8 +326,104 @@ impl Renderable for Sphere {
     normal
   }
 
+  fn hit_at(&self, ray: &Ray, t: f64) -> Hit {
+    Hit {
+      t,
+      normal: self.normal_at(&(ray.origin + ray.direction * t)),
+      uv: None,
+    }
+  }
+
+  fn intersects(&self, ray: &Ray) -> Option<Hit> {
+    self.distance(ray).map(|t| self.hit_at(ray, t))
+  }
+
+  fn intersect_all(&self, ray: &Ray) -> Vec<(Hit, bool)> {
+    if ray.is_degenerate() {
+      return vec![];
+    }
+    // The same construction as in `distance`, but keeping both ends:
+    let to_center = self.center - ray.origin;
+    let t = ray.direction.dot(&to_center);
+    let y_squared = ((ray.direction * t) - to_center).length_squared();
+    if y_squared >= self.radius_squared {
+      return vec![];
+    }
+    let x = (self.radius_squared - y_squared).sqrt();
+    vec![
+      (self.hit_at(ray, t - x), true),
+      (self.hit_at(ray, t + x), false),
+    ]
+  }
+}
+
+/// A sphere that travels in a straight line from `center_start` to
+/// `center_end` while the camera's shutter is open, producing motion blur.
+#[derive(Clone)]
+pub struct MovingSphere {
+  /// Where the sphere is when `ray.time == 0.0`.
+  pub center_start: Vector,
+  /// Where the sphere is when `ray.time == 1.0`.
+  pub center_end: Vector,
+  pub radius: f64,
+  pub material: Arc<dyn Material>,
+}
+
+impl MovingSphere {
+  pub fn new(
+    center_start: Vector,
+    center_end: Vector,
+    radius: f64,
+    material: Arc<dyn Material>,
+  ) -> Self {
+    MovingSphere {
+      center_start,
+      center_end,
+      radius,
+      material,
+    }
+  }
+
+  pub fn center_at(&self, time: f64) -> Vector {
+    self.center_start + (self.center_end - self.center_start) * time
+  }
+
+  // A moving sphere is just a regular one frozen at the ray's time:
+  fn at(&self, time: f64) -> Ball {
+    Ball {
+      center: self.center_at(time),
+      radius_squared: self.radius * self.radius,
+    }
+  }
+}
+
+impl Renderable for MovingSphere {
+  fn intersects(&self, ray: &Ray) -> Option<Hit> {
+    self.at(ray.time).intersects(ray)
+  }
+
   fn material(&self) -> &dyn Material {
-    self.material
+    &*self.material
+  }
+
+  fn set_material(&mut self, material: Arc<dyn Material>) {
+    self.material = material;
+  }
+
+  // Everywhere the sphere could be while the shutter is open:
+  fn bounds(&self) -> Option<Aabb> {
+    let extent = Vector {
+      x: self.radius,
+      y: self.radius,
+      z: self.radius,
+    };
+    let (start, end) = (self.center_at(0.0), self.center_at(1.0));
+    let bounds = Aabb::new(start - extent, start + extent);
+    Some(bounds.union(&Aabb::new(end - extent, end + extent)))
+  }
+
+  fn intersect_all(&self, ray: &Ray) -> Vec<(Hit, bool)> {
+    self.at(ray.time).intersect_all(ray)
   }
 }
 
@@ -159,6 +431,7 @@ impl Renderable for Sphere {
 mod tests {
   use super::*;
   use crate::material::MIRROR;
+  use crate::vector::{approx_eq, EPSILON};
 
   #[test]
   fn direct_at_sphere() {
@@ -169,7 +442,7 @@ mod tests {
         z: 4.0,
       },
       1.0,
-      &MIRROR,
+      Arc::new(MIRROR),
     );
 
     let ray = Ray {
@@ -183,14 +456,105 @@ mod tests {
         y: 0.0,
         z: 1.0,
       },
+      time: 0.0,
+      channel: None,
     };
 
     match sphere.intersects(&ray) {
       None => panic!("Expected an intersection to occur, but got None"),
-      Some(t) => assert_eq!(t, 3.0),
+      Some(hit) => assert!(approx_eq(hit.t, 3.0, EPSILON)),
     }
   }
 
+  #[test]
+  fn set_radius_keeps_hits_in_step() {
+    let mut sphere = Sphere::new(Vector::new(), 1.0, Arc::new(MIRROR));
+    sphere.set_radius(3.0);
+    assert_eq!(sphere.radius(), 3.0);
+    assert_eq!(sphere.radius_squared, 9.0);
+
+    // A ray passing 2 units from the center now clips it:
+    let ray = Ray {
+      origin: Vector {
+        x: 2.0,
+        y: 0.0,
+        z: -10.0,
+      },
+      direction: Vector {
+        x: 0.0,
+        y: 0.0,
+        z: 1.0,
+      },
+      time: 0.0,
+      channel: None,
+    };
+    let hit = sphere.intersects(&ray).unwrap();
+    assert!(approx_eq(hit.t, 10.0 - 5f64.sqrt(), EPSILON));
+  }
+
+  #[test]
+  fn batched_distances_match_scalar() {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    let mut rng = StdRng::seed_from_u64(1);
+    let random_point = |rng: &mut StdRng| Vector {
+      x: rng.gen_range(-5.0, 5.0),
+      y: rng.gen_range(-5.0, 5.0),
+      z: rng.gen_range(-5.0, 5.0),
+    };
+
+    for _ in 0..1000 {
+      let spheres: Vec<Sphere> = (0..SPHERE_BATCH)
+        .map(|_| {
+          Sphere::new(
+            random_point(&mut rng),
+            rng.gen_range(0.1, 3.0),
+            Arc::new(MIRROR),
+          )
+        })
+        .collect();
+      let batch = [&spheres[0], &spheres[1], &spheres[2], &spheres[3]];
+      let ray = Ray::new(random_point(&mut rng), random_point(&mut rng), 0.0);
+
+      let batched = Sphere::distances_batch(&batch, &ray);
+      for (sphere, distance) in spheres.iter().zip(batched.iter()) {
+        assert_eq!(*distance, sphere.ball().distance(&ray));
+      }
+    }
+  }
+
+  #[test]
+  fn degenerate_rays_miss() {
+    let sphere = Sphere::new(Vector::new(), 1.0, Arc::new(MIRROR));
+    let origin = Vector {
+      x: 0.0,
+      y: 0.0,
+      z: -4.0,
+    };
+
+    let nan = Ray {
+      origin,
+      direction: Vector {
+        x: f64::NAN,
+        y: 0.0,
+        z: 1.0,
+      },
+      time: 0.0,
+      channel: None,
+    };
+    assert_eq!(sphere.intersects(&nan), None);
+    assert_eq!(sphere.intersect_all(&nan), vec![]);
+
+    let zero = Ray {
+      origin,
+      direction: Vector::new(),
+      time: 0.0,
+      channel: None,
+    };
+    assert_eq!(sphere.intersects(&zero), None);
+  }
+
   #[test]
   fn inside_sphere_at_center() {
     let sphere = Sphere::new(
@@ -200,12 +564,13 @@ mod tests {
         z: 0.0,
       },
       1.0,
-      &MIRROR,
+      Arc::new(MIRROR),
     );
 
     // We test 1000 random rays out from the center; they should always be equal
     // to the sphere's radius, since the ray is located at the exact center of
     // the sphere.
+    let mut rng = rand::thread_rng();
     for _ in 0..1000 {
       let ray = Ray {
         origin: Vector {
@@ -213,11 +578,13 @@ mod tests {
           y: 0.0,
           z: 0.0,
         },
-        direction: Vector::random_norm(),
+        direction: Vector::random_norm(&mut rng),
+        time: 0.0,
+        channel: None,
       };
       match sphere.intersects(&ray) {
         None => panic!("Expected an intersection to occur, but got None"),
-        Some(t) => assert_eq!(t, sphere.radius),
+        Some(hit) => assert!(approx_eq(hit.t, sphere.radius, EPSILON)),
       }
     }
   }
@@ -231,7 +598,7 @@ mod tests {
         z: 0.0,
       },
       1.0,
-      &MIRROR,
+      Arc::new(MIRROR),
     );
 
     // We test 1000 random rays out from the center; they should always be equal
@@ -248,10 +615,12 @@ mod tests {
         y: 0.0,
         z: 1.0,
       },
+      time: 0.0,
+      channel: None,
     };
     match sphere.intersects(&ray) {
       None => panic!("Expected an intersection to occur, but got None"),
-      Some(t) => assert_eq!(t, 0.5),
+      Some(hit) => assert!(approx_eq(hit.t, 0.5, EPSILON)),
     }
 
     let sphere = Sphere::new(
@@ -261,7 +630,7 @@ mod tests {
         z: 0.0,
       },
       1.0,
-      &MIRROR,
+      Arc::new(MIRROR),
     );
 
     // We test 1000 random rays out from the center; they should always be equal
@@ -278,10 +647,133 @@ mod tests {
         y: 0.0,
         z: 1.0,
       },
+      time: 0.0,
+      channel: None,
     };
     match sphere.intersects(&ray) {
       None => panic!("Expected an intersection to occur, but got None"),
-      Some(t) => assert_eq!(t, 1.5),
+      Some(hit) => assert!(approx_eq(hit.t, 1.5, EPSILON)),
+    }
+  }
+
+  #[test]
+  fn moving_sphere_follows_time() {
+    let sphere = MovingSphere::new(
+      Vector {
+        x: 0.0,
+        y: 0.0,
+        z: 4.0,
+      },
+      Vector {
+        x: 0.0,
+        y: 0.0,
+        z: 8.0,
+      },
+      1.0,
+      Arc::new(MIRROR),
+    );
+
+    let ray_at = |time| Ray {
+      origin: Vector::new(),
+      direction: Vector {
+        x: 0.0,
+        y: 0.0,
+        z: 1.0,
+      },
+      time,
+      channel: None,
+    };
+
+    for (time, expected_t) in [(0.0, 3.0), (0.5, 5.0), (1.0, 7.0)].iter() {
+      match sphere.intersects(&ray_at(*time)) {
+        None => panic!("Expected an intersection to occur, but got None"),
+        Some(hit) => assert!(approx_eq(hit.t, *expected_t, EPSILON)),
+      }
+    }
+
+    // Halfway through, the sphere is centered at `z = 6`, so we hit it head-on
+    // at `z = 5`:
+    assert!(sphere.intersects(&ray_at(0.5)).unwrap().normal.approx_eq(
+      &Vector {
+        x: 0.0,
+        y: 0.0,
+        z: -1.0,
+      },
+      EPSILON
+    ));
+  }
+
+  #[test]
+  fn uv_at_the_poles_and_equator() {
+    let at = |x, y, z| Sphere::uv(&Vector { x, y, z });
+
+    let (u, v) = at(0.0, 1.0, 0.0);
+    assert_eq!(v, 0.0);
+    assert!((0.0..=1.0).contains(&u));
+    let (u, v) = at(0.0, -1.0, 0.0);
+    assert_eq!(v, 1.0);
+    assert!((0.0..=1.0).contains(&u));
+    // Rounding can leave normals ever so slightly longer than 1:
+    let (u, v) = at(-0.0, 1.0 + 1e-12, -0.0);
+    assert!(u.is_finite() && v == 0.0);
+
+    // Around the equator, `v` stays put while `u` goes all the way around:
+    for &(x, z, expected_u) in &[
+      (0.0, 1.0, 0.5),
+      (1.0, 0.0, 0.75),
+      (0.0, -1.0, 1.0),
+      (-1.0, 0.0, 0.25),
+    ] {
+      let (u, v) = at(x, 0.0, z);
+      assert!(approx_eq(u, expected_u, EPSILON), "{} != {}", u, expected_u);
+      assert!(approx_eq(v, 0.5, EPSILON));
+    }
+  }
+
+  #[test]
+  fn tangent_frame_follows_uv() {
+    let mut rng = rand::thread_rng();
+    for _ in 0..100 {
+      let normal = Vector::random_norm(&mut rng);
+      let (tangent, bitangent) = Sphere::tangent_frame(&normal);
+      assert!(approx_eq(tangent.length(), 1.0, EPSILON));
+      assert!(approx_eq(bitangent.length(), 1.0, EPSILON));
+      assert!(approx_eq(tangent.dot(&normal), 0.0, EPSILON));
+      assert!(approx_eq(bitangent.dot(&normal), 0.0, EPSILON));
+
+      // Nudging the normal along each one moves its UV the same way (well
+      // away from the seam):
+      let (u, v) = Sphere::uv(&normal);
+      if !(0.01..0.99).contains(&u) {
+        continue;
+      }
+      let (east_u, east_v) = Sphere::uv(&(normal + tangent * 1e-6).normalized());
+      assert!(east_u > u && approx_eq(east_v, v, 1e-6));
+      let (south_u, south_v) = Sphere::uv(&(normal + bitangent * 1e-6).normalized());
+      assert!(south_v > v && approx_eq(south_u, u, 1e-6));
+    }
+  }
+
+  #[test]
+  fn uv_is_continuous_apart_from_the_seam() {
+    // Walking around a circle of latitude in small steps, `u` only ever jumps
+    // by (nearly) a whole turn, where it wraps from 1 back to 0:
+    let steps = 1000;
+    let mut last = None;
+    for i in 0..=steps {
+      let angle = i as f64 / steps as f64 * std::f64::consts::PI * 2.0;
+      let normal = Vector {
+        x: angle.sin() * 0.6,
+        y: 0.8,
+        z: angle.cos() * 0.6,
+      };
+      let (u, v) = Sphere::uv(&normal);
+      assert!(approx_eq(v, 0.8f64.acos() / std::f64::consts::PI, EPSILON));
+      if let Some(last) = last {
+        let jump: f64 = u - last;
+        assert!(jump.abs() < 0.01 || (jump.abs() - 1.0).abs() < 0.01);
+      }
+      last = Some(u);
     }
   }
 }
@@ -0,0 +1,187 @@
+use sdl2::image::LoadSurface;
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::surface::Surface;
+
+use crate::material::HDRColor;
+
+/// What a `Texture` does with coordinates outside `[0, 1]`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Wrap {
+  /// Tile the image endlessly.
+  Repeat,
+  /// Stretch the edge pixels outward.
+  Clamp,
+}
+
+/// An image to look colors up in by `(u, v)` coordinates, with `(0, 0)` at the
+/// top-left corner and `(1, 1)` at the bottom-right.
+#[derive(Clone)]
+pub struct Texture {
+  width: usize,
+  height: usize,
+  /// Linear colors, row-major from the top-left.
+  pixels: Vec<HDRColor>,
+  pub wrap_u: Wrap,
+  pub wrap_v: Wrap,
+}
+
+impl Texture {
+  /// A texture from linear `pixels`, row-major from the top-left, which
+  /// repeats in both directions.
+  pub fn new(width: usize, height: usize, pixels: Vec<HDRColor>) -> Self {
+    assert_eq!(pixels.len(), width * height);
+    assert!(width > 0 && height > 0);
+    Texture {
+      width,
+      height,
+      pixels,
+      wrap_u: Wrap::Repeat,
+      wrap_v: Wrap::Repeat,
+    }
+  }
+
+  /// A texture from sRGB-encoded R, G, B, A bytes (alpha is ignored), like
+  /// most 8-bit images store them.
+  pub fn from_rgba(width: usize, height: usize, bytes: &[u8]) -> Self {
+    let pixels = bytes
+      .chunks(4)
+      .map(|rgba| HDRColor::from_srgb8(rgba[0], rgba[1], rgba[2]))
+      .collect();
+    Texture::new(width, height, pixels)
+  }
+
+  /// Load an image from any format SDL_image understands. The image is assumed
+  /// to be sRGB-encoded.
+  pub fn load(path: &str) -> Result<Texture, String> {
+    Texture::load_with(path, HDRColor::from_srgb8)
+  }
+
+  /// Like `load`, but for images that hold data rather than colors (like
+  /// normal maps), whose bytes map straight onto `[0, 1]`.
+  pub fn load_data(path: &str) -> Result<Texture, String> {
+    Texture::load_with(path, |r, g, b| {
+      HDRColor::new(r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0)
+    })
+  }
+
+  fn load_with(path: &str, decode: fn(u8, u8, u8) -> HDRColor) -> Result<Texture, String> {
+    let surface = Surface::from_file(path)?.convert_format(PixelFormatEnum::RGB24)?;
+    let width = surface.width() as usize;
+    let height = surface.height() as usize;
+    let pitch = surface.pitch() as usize;
+
+    let pixels = surface.with_lock(|bytes| {
+      let mut pixels = Vec::with_capacity(width * height);
+      for y in 0..height {
+        for x in 0..width {
+          let offset = y * pitch + x * 3;
+          pixels.push(decode(bytes[offset], bytes[offset + 1], bytes[offset + 2]));
+        }
+      }
+      pixels
+    });
+    Ok(Texture::new(width, height, pixels))
+  }
+
+  pub fn width(&self) -> usize {
+    self.width
+  }
+
+  pub fn height(&self) -> usize {
+    self.height
+  }
+
+  /// The color at `(u, v)`, blended bilinearly between the four nearest pixel
+  /// centers.
+  pub fn sample(&self, u: f64, v: f64) -> HDRColor {
+    let x = u * self.width as f64 - 0.5;
+    let y = v * self.height as f64 - 0.5;
+    let (x0, y0) = (x.floor(), y.floor());
+    let fx = (x - x0) as f32;
+    let fy = (y - y0) as f32;
+
+    let (x0, x1) = (
+      wrap(x0, self.width, self.wrap_u),
+      wrap(x0 + 1.0, self.width, self.wrap_u),
+    );
+    let (y0, y1) = (
+      wrap(y0, self.height, self.wrap_v),
+      wrap(y0 + 1.0, self.height, self.wrap_v),
+    );
+    let pixel = |x: usize, y: usize| &self.pixels[y * self.width + x];
+
+    let top = pixel(x0, y0).lerp(pixel(x1, y0), fx);
+    let bottom = pixel(x0, y1).lerp(pixel(x1, y1), fx);
+    top.lerp(&bottom, fy)
+  }
+}
+
+// The pixel index for (whole-number) coordinate `i` along an axis `size` pixels
+// long.
+fn wrap(i: f64, size: usize, wrap: Wrap) -> usize {
+  match wrap {
+    Wrap::Repeat => (i as isize).rem_euclid(size as isize) as usize,
+    Wrap::Clamp => i.clamp(0.0, (size - 1) as f64) as usize,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn checker() -> Texture {
+    Texture::new(
+      2,
+      2,
+      vec![
+        HDRColor::new(1.0, 0.0, 0.0),
+        HDRColor::new(0.0, 1.0, 0.0),
+        HDRColor::new(0.0, 0.0, 1.0),
+        HDRColor::new(1.0, 1.0, 1.0),
+      ],
+    )
+  }
+
+  #[test]
+  fn center_of_a_2x2_image_is_the_average() {
+    for &wrap in &[Wrap::Repeat, Wrap::Clamp] {
+      let texture = Texture {
+        wrap_u: wrap,
+        wrap_v: wrap,
+        ..checker()
+      };
+      let color = texture.sample(0.5, 0.5);
+      assert_eq!((color.r, color.g, color.b), (0.5, 0.5, 0.5));
+    }
+  }
+
+  #[test]
+  fn pixel_centers_are_exact() {
+    let color = checker().sample(0.75, 0.25);
+    assert_eq!((color.r, color.g, color.b), (0.0, 1.0, 0.0));
+  }
+
+  #[test]
+  fn edges_repeat_or_clamp() {
+    let mut texture = checker();
+    // Halfway between the top-left pixel and (wrapping around) the top-right:
+    let color = texture.sample(0.0, 0.25);
+    assert_eq!((color.r, color.g), (0.5, 0.5));
+    // One whole tile over is the same spot:
+    let tiled = texture.sample(-1.0, 1.25);
+    assert_eq!((tiled.r, tiled.g), (0.5, 0.5));
+
+    texture.wrap_u = Wrap::Clamp;
+    let color = texture.sample(0.0, 0.25);
+    assert_eq!((color.r, color.g), (1.0, 0.0));
+  }
+
+  #[test]
+  fn rgba_bytes_are_decoded_from_srgb() {
+    let texture = Texture::from_rgba(1, 1, &[255, 188, 0, 255]);
+    let color = texture.sample(0.5, 0.5);
+    assert_eq!(color.r, 1.0);
+    assert!((color.g - 0.5).abs() < 0.005);
+    assert_eq!(color.b, 0.0);
+  }
+}
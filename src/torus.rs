@@ -0,0 +1,311 @@
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::scene::{Hit, Renderable};
+use crate::vector::Vector;
+
+/// A donut: a tube of radius `minor_radius` swept around a circle of radius
+/// `major_radius`, which lies in the plane perpendicular to `axis` through
+/// `center`.
+#[derive(Clone)]
+pub struct Torus {
+  pub center: Vector,
+  axis: Vector,
+  pub major_radius: f64,
+  pub minor_radius: f64,
+  pub material: Arc<dyn Material>,
+}
+
+impl Torus {
+  pub fn new(
+    center: Vector,
+    axis: Vector,
+    major_radius: f64,
+    minor_radius: f64,
+    material: Arc<dyn Material>,
+  ) -> Self {
+    Torus {
+      center,
+      axis: axis.normalized(),
+      major_radius,
+      minor_radius,
+      material,
+    }
+  }
+
+  /// Every distance along `ray` at which it crosses the surface, nearest
+  /// first.
+  fn hits(&self, ray: &Ray) -> Vec<f64> {
+    // Relative to our center, a point `p` is on the surface when
+    //
+    //   (|p|² + R² - r²)² = 4R²(|p|² - (p·axis)²)
+    //
+    // (`|p|² - (p·axis)²` is just the squared distance from `p` to the axis).
+    //
+    // Substituting `p = o + t·d` gives a quartic in `t`, whose coefficients we
+    // expand here, lowest degree first:
+    let o = ray.origin - self.center;
+    let d = ray.direction;
+    let (oo, od, dd) = (o.dot(&o), o.dot(&d), d.dot(&d));
+    let (oa, da) = (o.dot(&self.axis), d.dot(&self.axis));
+    let r2 = self.major_radius * self.major_radius;
+    let k = oo + r2 - self.minor_radius * self.minor_radius;
+
+    let coefficients = [
+      k * k - 4.0 * r2 * (oo - oa * oa),
+      4.0 * od * k - 8.0 * r2 * (od - oa * da),
+      4.0 * od * od + 2.0 * dd * k - 4.0 * r2 * (dd - da * da),
+      4.0 * dd * od,
+      dd * dd,
+    ];
+
+    // Only search where the ray is inside a sphere around the whole torus
+    // (padded a bit, so roots right at its edge don't land on the endpoints):
+    let bounding_radius = (self.major_radius + self.minor_radius) * 1.01;
+    let discriminant = od * od - dd * (oo - bounding_radius * bounding_radius);
+    if discriminant < 0.0 {
+      return vec![];
+    }
+    let near = (-od - discriminant.sqrt()) / dd;
+    let far = (-od + discriminant.sqrt()) / dd;
+    if far < 0.0001 {
+      return vec![];
+    }
+
+    real_roots(&coefficients, near.max(0.0001), far)
+  }
+
+  fn normal_at(&self, point: &Vector) -> Vector {
+    // The gradient of the implicit surface equation from `hits`:
+    let p = point - self.center;
+    let r2 = self.major_radius * self.major_radius;
+    let k = p.length_squared() + r2 - self.minor_radius * self.minor_radius;
+    let to_axis = p - self.axis * p.dot(&self.axis);
+    (p * (4.0 * k) - to_axis * (8.0 * r2)).normalized()
+  }
+}
+
+impl Renderable for Torus {
+  fn intersects(&self, ray: &Ray) -> Option<Hit> {
+    let t = *self.hits(ray).first()?;
+    Some(Hit {
+      t,
+      normal: self.normal_at(&(ray.origin + ray.direction * t)),
+      uv: None,
+    })
+  }
+
+  fn material(&self) -> &dyn Material {
+    &*self.material
+  }
+
+  fn set_material(&mut self, material: Arc<dyn Material>) {
+    self.material = material;
+  }
+
+  fn bounds(&self) -> Option<Aabb> {
+    // The ring's circle reaches `R * sin(angle between axis and X)` along X,
+    // and so on; the tube adds `r` in every direction.
+    let extent = |a: f64| self.major_radius * (1.0 - a * a).max(0.0).sqrt() + self.minor_radius;
+    let extent = Vector {
+      x: extent(self.axis.x),
+      y: extent(self.axis.y),
+      z: extent(self.axis.z),
+    };
+    Some(Aabb::new(self.center - extent, self.center + extent))
+  }
+}
+
+fn evaluate(coefficients: &[f64], t: f64) -> f64 {
+  coefficients.iter().rev().fold(0.0, |sum, c| sum * t + c)
+}
+
+/// The real roots of the polynomial `coefficients[0] + coefficients[1]·t + …`
+/// that lie in `[lo, hi]`, in ascending order.
+///
+/// Between two neighboring roots of a polynomial's derivative it is monotonic,
+/// so it can cross zero at most once there; we find the derivative's roots
+/// recursively, then bisect each of those intervals that changes sign. This is
+/// slower than solving the quartic in closed form, but doesn't fall apart
+/// numerically on near-tangent rays.
+fn real_roots(coefficients: &[f64], lo: f64, hi: f64) -> Vec<f64> {
+  let degree = match coefficients.iter().rposition(|&c| c != 0.0) {
+    Some(degree) => degree,
+    None => return vec![],
+  };
+  let coefficients = &coefficients[..=degree];
+
+  if degree == 0 {
+    return vec![];
+  }
+  if degree == 1 {
+    let t = -coefficients[0] / coefficients[1];
+    return if t >= lo && t <= hi { vec![t] } else { vec![] };
+  }
+
+  let derivative: Vec<f64> = coefficients
+    .iter()
+    .enumerate()
+    .skip(1)
+    .map(|(power, c)| c * power as f64)
+    .collect();
+  let mut bounds = vec![lo];
+  bounds.extend(real_roots(&derivative, lo, hi));
+  bounds.push(hi);
+
+  // A turning point that only just touches zero (say, a ray grazing a surface)
+  // is a root too, even though there's no sign change around it:
+  let tolerance = 1e-12
+    * coefficients
+      .iter()
+      .fold(0.0, |max: f64, c| max.max(c.abs()));
+  let values: Vec<f64> = bounds
+    .iter()
+    .enumerate()
+    .map(|(i, &t)| {
+      let value = evaluate(coefficients, t);
+      let is_turning_point = i > 0 && i < bounds.len() - 1;
+      if is_turning_point && value.abs() <= tolerance {
+        0.0
+      } else {
+        value
+      }
+    })
+    .collect();
+
+  let mut roots = vec![];
+  for i in 0..bounds.len() - 1 {
+    if i > 0 && values[i] == 0.0 {
+      roots.push(bounds[i]);
+    }
+    if values[i] * values[i + 1] < 0.0 {
+      roots.push(bisect(coefficients, bounds[i], bounds[i + 1]));
+    }
+  }
+  roots
+}
+
+fn bisect(coefficients: &[f64], mut lo: f64, mut hi: f64) -> f64 {
+  let lo_is_negative = evaluate(coefficients, lo) < 0.0;
+  loop {
+    let mid = (lo + hi) / 2.0;
+    if mid <= lo || mid >= hi {
+      return mid;
+    }
+    if (evaluate(coefficients, mid) < 0.0) == lo_is_negative {
+      lo = mid;
+    } else {
+      hi = mid;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::material::MIRROR;
+  use crate::vector::approx_eq;
+
+  // Lying flat in the XZ plane, with its ring 2 units from the center and a
+  // tube half a unit thick.
+  fn donut() -> Torus {
+    Torus::new(
+      Vector::new(),
+      Vector {
+        x: 0.0,
+        y: 1.0,
+        z: 0.0,
+      },
+      2.0,
+      0.5,
+      Arc::new(MIRROR),
+    )
+  }
+
+  fn along_x(y: f64) -> Ray {
+    Ray {
+      origin: Vector {
+        x: -10.0,
+        y,
+        z: 0.0,
+      },
+      direction: Vector {
+        x: 1.0,
+        y: 0.0,
+        z: 0.0,
+      },
+      time: 0.0,
+      channel: None,
+    }
+  }
+
+  #[test]
+  fn ray_through_the_hole() {
+    let torus = donut();
+
+    // Straight across the middle, passing through the tube on both sides of the
+    // hole:
+    let hits = torus.hits(&along_x(0.0));
+    assert_eq!(hits.len(), 4);
+    for (hit, expected) in hits.iter().zip([7.5, 8.5, 11.5, 12.5].iter()) {
+      assert!(approx_eq(*hit, *expected, 1e-6), "{:?}", hits);
+    }
+
+    // From inside the hole, we only see the far side of the tube:
+    let ray = Ray {
+      origin: Vector::new(),
+      ..along_x(0.0)
+    };
+    let hit = torus.intersects(&ray).unwrap();
+    assert!(approx_eq(hit.t, 1.5, 1e-6));
+    assert!(hit.normal.approx_eq(
+      &Vector {
+        x: -1.0,
+        y: 0.0,
+        z: 0.0,
+      },
+      1e-6
+    ));
+
+    // Down the axis, we fall through without touching anything:
+    let down_the_axis = Ray {
+      origin: Vector {
+        x: 0.0,
+        y: 10.0,
+        z: 0.0,
+      },
+      direction: Vector {
+        x: 0.0,
+        y: -1.0,
+        z: 0.0,
+      },
+      time: 0.0,
+      channel: None,
+    };
+    assert_eq!(torus.intersects(&down_the_axis), None);
+  }
+
+  #[test]
+  fn ray_missing_the_torus() {
+    assert_eq!(donut().intersects(&along_x(3.0)), None);
+    assert_eq!(donut().intersects(&along_x(0.51)), None);
+  }
+
+  #[test]
+  fn tangent_ray() {
+    // Skimming the very top of the tube, touching it at `x = -2` and `x = 2`:
+    let ray = along_x(0.5);
+    let hit = donut().intersects(&ray).unwrap();
+    assert!(approx_eq(hit.t, 8.0, 1e-3));
+    assert!(hit.normal.approx_eq(
+      &Vector {
+        x: 0.0,
+        y: 1.0,
+        z: 0.0,
+      },
+      1e-3
+    ));
+  }
+}
@@ -0,0 +1,197 @@
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::scene::{Hit, Renderable};
+use crate::vector::Vector;
+
+/// A single triangle, the building block of meshes.
+#[derive(Clone)]
+pub struct Triangle {
+  pub vertices: [Vector; 3],
+  /// Per-vertex normals to interpolate across the face, so a mesh
+  /// approximating a curved surface shades smoothly instead of looking
+  /// faceted. `None` shades the whole face with its geometric normal.
+  pub normals: Option<[Vector; 3]>,
+  pub material: Arc<dyn Material>,
+  /// Only visible from the side its (counter-clockwise wound) front faces.
+  pub single_sided: bool,
+}
+
+impl Triangle {
+  pub fn new(a: Vector, b: Vector, c: Vector, material: Arc<dyn Material>) -> Self {
+    Triangle {
+      vertices: [a, b, c],
+      normals: None,
+      material,
+      single_sided: false,
+    }
+  }
+
+  /// A triangle with a normal for each vertex (in the same order).
+  pub fn smooth(
+    a: Vector,
+    b: Vector,
+    c: Vector,
+    normals: [Vector; 3],
+    material: Arc<dyn Material>,
+  ) -> Self {
+    Triangle {
+      normals: Some([
+        normals[0].normalized(),
+        normals[1].normalized(),
+        normals[2].normalized(),
+      ]),
+      ..Triangle::new(a, b, c, material)
+    }
+  }
+
+  fn face_normal(&self) -> Vector {
+    let [a, b, c] = self.vertices;
+    (b - a).cross(&(c - a)).normalized()
+  }
+}
+
+impl Renderable for Triangle {
+  fn intersects(&self, ray: &Ray) -> Option<Hit> {
+    // Möller–Trumbore: solve `origin + t·direction = a + v·(b - a) + w·(c - a)`
+    // for `t`, `v` and `w` using Cramer's rule, bailing out as soon as the hit
+    // lands outside the triangle.
+    let [a, b, c] = self.vertices;
+    let (ab, ac) = (b - a, c - a);
+    let p = ray.direction.cross(&ac);
+    let determinant = ab.dot(&p);
+    // A negative determinant means we're looking at the back:
+    if determinant.abs() < 1e-12 || (self.single_sided && determinant < 0.0) {
+      return None;
+    }
+
+    let to_origin = ray.origin - a;
+    let v = to_origin.dot(&p) / determinant;
+    if !(0.0..=1.0).contains(&v) {
+      return None;
+    }
+    let q = to_origin.cross(&ab);
+    let w = ray.direction.dot(&q) / determinant;
+    if w < 0.0 || v + w > 1.0 {
+      return None;
+    }
+
+    let t = ac.dot(&q) / determinant;
+    if t < 0.0001 {
+      return None;
+    }
+
+    // `v` and `w` are how much of `b` and `c` make up the hit point (the rest
+    // being `a`), which is just what we need to blend vertex normals:
+    let normal = match self.normals {
+      Some([na, nb, nc]) => (na * (1.0 - v - w) + nb * v + nc * w).normalized(),
+      None => self.face_normal(),
+    };
+    // Like a plane, a triangle has no inside, so face whoever's looking:
+    let normal = if determinant < 0.0 {
+      normal * -1.0
+    } else {
+      normal
+    };
+    Some(Hit {
+      t,
+      normal,
+      uv: Some((v, w)),
+    })
+  }
+
+  fn material(&self) -> &dyn Material {
+    &*self.material
+  }
+
+  fn set_material(&mut self, material: Arc<dyn Material>) {
+    self.material = material;
+  }
+
+  fn bounds(&self) -> Option<Aabb> {
+    let [a, b, c] = self.vertices;
+    Some(Aabb::new(a.min(&b).min(&c), a.max(&b).max(&c)))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::material::MIRROR;
+  use crate::vector::{approx_eq, EPSILON};
+
+  // In the `z = 0` plane, facing -Z (toward a camera looking down +Z).
+  fn vertices() -> (Vector, Vector, Vector) {
+    (
+      Vector::new(),
+      Vector {
+        x: 0.0,
+        y: 1.0,
+        z: 0.0,
+      },
+      Vector {
+        x: 1.0,
+        y: 0.0,
+        z: 0.0,
+      },
+    )
+  }
+
+  fn ray_at(x: f64, y: f64) -> Ray {
+    Ray {
+      origin: Vector { x, y, z: -2.0 },
+      direction: Vector {
+        x: 0.0,
+        y: 0.0,
+        z: 1.0,
+      },
+      time: 0.0,
+      channel: None,
+    }
+  }
+
+  #[test]
+  fn hits_inside_and_misses_outside() {
+    let (a, b, c) = vertices();
+    let triangle = Triangle::new(a, b, c, Arc::new(MIRROR));
+
+    let hit = triangle.intersects(&ray_at(0.25, 0.25)).unwrap();
+    assert!(approx_eq(hit.t, 2.0, EPSILON));
+    assert_eq!(hit.uv, Some((0.25, 0.25)));
+    assert_eq!(triangle.intersects(&ray_at(0.75, 0.75)), None);
+    assert_eq!(triangle.intersects(&ray_at(-0.1, 0.5)), None);
+    assert_eq!(
+      hit.normal,
+      Vector {
+        x: 0.0,
+        y: 0.0,
+        z: -1.0,
+      }
+    );
+  }
+
+  #[test]
+  fn smooth_normals_are_interpolated() {
+    let (a, b, c) = vertices();
+    let toward = |x: f64, y: f64| Vector { x, y, z: -1.0 };
+    let normals = [toward(0.0, 0.0), toward(0.0, 1.0), toward(1.0, 0.0)];
+    let triangle = Triangle::smooth(a, b, c, normals, Arc::new(MIRROR));
+
+    // At each corner we get exactly that corner's normal...
+    for (vertex, normal) in [a, b, c].iter().zip(normals.iter()) {
+      let hit = triangle.intersects(&ray_at(vertex.x, vertex.y)).unwrap();
+      assert!(hit.normal.approx_eq(&normal.normalized(), EPSILON));
+    }
+
+    // ...and in between, a blend of them:
+    let midpoint = (b + c) / 2.0;
+    let hit = triangle
+      .intersects(&ray_at(midpoint.x, midpoint.y))
+      .unwrap();
+    assert!(hit
+      .normal
+      .approx_eq(&toward(0.5, 0.5).normalized(), EPSILON));
+  }
+}
@@ -19,7 +19,7 @@ use core::f64::consts::PI;
 use rand::Rng;
 use std::ops;
 
-#[derive(Debug, PartialEq, Clone, Copy)]
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
 pub struct Vector {
   pub x: f64,
   pub y: f64,
@@ -82,6 +82,15 @@ impl_op_ex!(/=|a: &mut Vector, b: f64| {
   a.z /= b;
 });
 
+/// Tolerance that's comfortably above accumulated floating point error in our
+/// geometry math, for comparisons that shouldn't demand bit-exact results.
+pub const EPSILON: f64 = 1e-9;
+
+/// Whether two scalars are within `eps` of each other.
+pub fn approx_eq(a: f64, b: f64, eps: f64) -> bool {
+  (a - b).abs() <= eps
+}
+
 impl Vector {
   pub fn new() -> Self {
     Vector {
@@ -99,23 +108,44 @@ impl Vector {
     self.length_squared().sqrt()
   }
 
-  pub fn normalize(&mut self) -> &mut Self {
-    // TODO: The borrow-checker doesn't like this:
-    // self /= self.length();
+  /// The square of the distance between two points, which is cheaper than
+  /// `distance` and just as good for comparing distances.
+  pub fn distance_squared(&self, other: &Vector) -> f64 {
+    let (dx, dy, dz) = (self.x - other.x, self.y - other.y, self.z - other.z);
+    dx * dx + dy * dy + dz * dz
+  }
 
-    let length = self.length();
-    self.x /= length;
-    self.y /= length;
-    self.z /= length;
+  /// How far apart two points are.
+  pub fn distance(&self, other: &Vector) -> f64 {
+    self.distance_squared(other).sqrt()
+  }
 
+  /// Scale to unit length, in place. The zero vector has no direction to
+  /// keep, so it stays zero (rather than turning into NaNs).
+  pub fn normalize(&mut self) -> &mut Self {
+    *self = self.normalized();
     self
   }
 
+  /// This vector scaled to unit length, or the zero vector if it has no
+  /// length.
   pub fn normalized(&self) -> Self {
     let length = self.length();
+    if length == 0.0 {
+      return Vector::new();
+    }
     self / length
   }
 
+  /// Whether every component is an actual number (not NaN or infinite).
+  pub fn is_finite(&self) -> bool {
+    self.x.is_finite() && self.y.is_finite() && self.z.is_finite()
+  }
+
+  /// Dot product: `|a| * |b| * cos(angle between a and b)`.
+  ///
+  /// Zero for perpendicular vectors, positive when they point the same way and
+  /// negative when they point in opposite directions.
   pub fn dot(&self, other: &Self) -> f64 {
     self.x * other.x + self.y * other.y + self.z * other.z
   }
@@ -128,11 +158,61 @@ impl Vector {
     }
   }
 
+  /// This direction bounced off a surface with the given (unit) `normal`, like
+  /// light off a mirror. Which side the normal faces doesn't matter.
+  pub fn reflect(&self, normal: &Vector) -> Vector {
+    self - normal * (2.0 * self.dot(normal))
+  }
+
+  /// The point `t` of the way from `self` to `other` (or beyond, outside
+  /// `[0, 1]`).
+  pub fn lerp(&self, other: &Vector, t: f64) -> Vector {
+    self + (other - self) * t
+  }
+
+  /// Whether every component is within `eps` of `other`'s.
+  pub fn approx_eq(&self, other: &Vector, eps: f64) -> bool {
+    approx_eq(self.x, other.x, eps)
+      && approx_eq(self.y, other.y, eps)
+      && approx_eq(self.z, other.z, eps)
+  }
+
+  /// Component-wise minimum of two vectors.
+  pub fn min(&self, other: &Vector) -> Vector {
+    Vector {
+      x: self.x.min(other.x),
+      y: self.y.min(other.y),
+      z: self.z.min(other.z),
+    }
+  }
+
+  /// Component-wise maximum of two vectors.
+  pub fn max(&self, other: &Vector) -> Vector {
+    Vector {
+      x: self.x.max(other.x),
+      y: self.y.max(other.y),
+      z: self.z.max(other.z),
+    }
+  }
+
+  /// Component-wise absolute value.
+  pub fn abs(&self) -> Vector {
+    Vector {
+      x: self.x.abs(),
+      y: self.y.abs(),
+      z: self.z.abs(),
+    }
+  }
+
   /// Generate a normalized vector pointing in a random direction distributed
   /// evenly along the unit sphere.
-  pub fn random_norm() -> Vector {
-    let mut rng = rand::thread_rng();
-
+  ///
+  /// We pick a uniformly random longitude `phi` and a uniformly random
+  /// `cos(theta)`; because a sphere's surface area between two heights is
+  /// proportional to the difference in height, this is uniform over the whole
+  /// sphere (unlike normalizing a random point in a cube, which bunches up
+  /// toward the cube's corners).
+  pub fn random_norm<R: Rng + ?Sized>(rng: &mut R) -> Vector {
     let phi: f64 = rng.gen_range(0.0, PI * 2.0);
     let costheta: f64 = rng.gen_range(-1.0, 1.0);
 
@@ -142,12 +222,151 @@ impl Vector {
     let z = (theta).cos();
     Vector { x, y, z }
   }
+
+  /// The point at angle `2π·u` and distance `sqrt(v)` from the center of the
+  /// unit disk perpendicular to `normal`. Evenly spread `(u, v)`s in the unit
+  /// square give points evenly spread over the disk's area (the square root
+  /// makes up for there being more area toward the rim).
+  pub fn on_unit_disk(u: f64, v: f64, normal: &Vector) -> Vector {
+    // Any two directions perpendicular to `normal` and to each other will do;
+    // start from whichever axis is furthest from parallel to it:
+    let axis = if normal.x.abs() < 0.9 {
+      Vector {
+        x: 1.0,
+        y: 0.0,
+        z: 0.0,
+      }
+    } else {
+      Vector {
+        x: 0.0,
+        y: 1.0,
+        z: 0.0,
+      }
+    };
+    let tangent = normal.cross(&axis).normalized();
+    let bitangent = normal.cross(&tangent).normalized();
+
+    let angle = u * PI * 2.0;
+    let radius = v.sqrt();
+    tangent * (radius * angle.cos()) + bitangent * (radius * angle.sin())
+  }
+
+  /// Generate a random point inside the unit sphere, uniformly distributed by
+  /// volume (via rejection sampling from the enclosing cube).
+  pub fn random_in_unit_sphere<R: Rng + ?Sized>(rng: &mut R) -> Vector {
+    loop {
+      let v = Vector {
+        x: rng.gen_range(-1.0, 1.0),
+        y: rng.gen_range(-1.0, 1.0),
+        z: rng.gen_range(-1.0, 1.0),
+      };
+      if v.length_squared() < 1.0 {
+        return v;
+      }
+    }
+  }
+
+  /// Generate a random point inside the unit disk on the XY plane (`z` is
+  /// always 0), uniformly distributed by area. Handy for sampling a lens
+  /// aperture.
+  pub fn random_in_unit_disk<R: Rng + ?Sized>(rng: &mut R) -> Vector {
+    loop {
+      let v = Vector {
+        x: rng.gen_range(-1.0, 1.0),
+        y: rng.gen_range(-1.0, 1.0),
+        z: 0.0,
+      };
+      if v.length_squared() < 1.0 {
+        return v;
+      }
+    }
+  }
+
+  /// Generate a random direction in the hemisphere around `normal`, more likely
+  /// the closer it is to `normal` (proportional to the cosine of the angle
+  /// between them), which matches how much light arriving from that direction
+  /// a diffuse surface actually receives.
+  ///
+  /// Adding a uniformly random unit vector to the normal and normalizing the
+  /// result happens to produce exactly this distribution.
+  pub fn random_cosine_hemisphere<R: Rng + ?Sized>(rng: &mut R, normal: &Vector) -> Vector {
+    let direction = normal + Vector::random_norm(rng);
+    // ...unless we drew (almost) exactly `-normal`:
+    if direction.length_squared() < EPSILON {
+      return *normal;
+    }
+    direction.normalized()
+  }
 }
 
 #[cfg(test)]
 mod tests {
   use super::*;
 
+  #[test]
+  fn reflect_mirrors_about_the_normal() {
+    let direction = Vector {
+      x: 1.0,
+      y: -1.0,
+      z: 0.0,
+    };
+    let up = Vector {
+      x: 0.0,
+      y: 1.0,
+      z: 0.0,
+    };
+    let expected = Vector {
+      x: 1.0,
+      y: 1.0,
+      z: 0.0,
+    };
+    assert_eq!(direction.reflect(&up), expected);
+    assert_eq!(direction.reflect(&(up * -1.0)), expected);
+  }
+
+  #[test]
+  fn distance_between_points() {
+    let a = Vector {
+      x: 3.0,
+      y: 4.0,
+      z: 0.0,
+    };
+    assert_eq!(Vector::new().distance(&a), 5.0);
+    assert_eq!(a.distance(&Vector::new()), 5.0);
+    assert_eq!(a.distance_squared(&Vector::new()), 25.0);
+    let b = Vector {
+      x: 1.0,
+      y: 2.0,
+      z: 2.0,
+    };
+    assert_eq!(a.distance_squared(&b), 12.0);
+    assert_eq!(b.distance(&b), 0.0);
+  }
+
+  #[test]
+  fn lerp_runs_from_self_to_other() {
+    let a = Vector {
+      x: 1.0,
+      y: -2.0,
+      z: 4.0,
+    };
+    let b = Vector {
+      x: 3.0,
+      y: 2.0,
+      z: -4.0,
+    };
+    assert_eq!(a.lerp(&b, 0.0), a);
+    assert_eq!(a.lerp(&b, 1.0), b);
+    assert_eq!(
+      a.lerp(&b, 0.5),
+      Vector {
+        x: 2.0,
+        y: 0.0,
+        z: 0.0,
+      }
+    );
+  }
+
   #[test]
   fn equal_operator() {
     let a = Vector {
@@ -332,7 +551,7 @@ mod tests {
       z: 3.0,
     };
 
-    assert_eq!(a.length(), (a.length_squared()).sqrt());
+    assert!(approx_eq(a.length(), (a.length_squared()).sqrt(), EPSILON));
   }
 
   #[test]
@@ -345,7 +564,7 @@ mod tests {
 
     a.normalize();
 
-    assert_eq!(a.length(), 1.0);
+    assert!(approx_eq(a.length(), 1.0, EPSILON));
   }
 
   #[test]
@@ -356,6 +575,215 @@ mod tests {
       z: 3.0,
     };
 
-    assert_eq!(a.normalized().length(), 1.0);
+    assert!(approx_eq(a.normalized().length(), 1.0, EPSILON));
+  }
+
+  #[test]
+  fn normalizing_zero_gives_zero() {
+    assert_eq!(Vector::new().normalized(), Vector::new());
+    let mut zero = Vector::new();
+    zero.normalize();
+    assert_eq!(zero, Vector::new());
+    assert!(zero.is_finite());
+  }
+
+  #[test]
+  fn is_finite_catches_nans_and_infinities() {
+    let a = Vector {
+      x: 1.0,
+      y: -2.0,
+      z: 1e300,
+    };
+    assert!(a.is_finite());
+    assert!(!Vector { x: f64::NAN, ..a }.is_finite());
+    assert!(!Vector {
+      z: f64::NEG_INFINITY,
+      ..a
+    }
+    .is_finite());
+    // Overflowing to infinity along the way counts too:
+    assert!(!(a * 1e10).is_finite());
+  }
+
+  #[test]
+  fn min() {
+    let a = Vector {
+      x: 1.0,
+      y: -2.0,
+      z: 3.0,
+    };
+    let b = Vector {
+      x: -1.0,
+      y: 2.0,
+      z: 3.5,
+    };
+
+    assert_eq!(
+      a.min(&b),
+      Vector {
+        x: -1.0,
+        y: -2.0,
+        z: 3.0,
+      }
+    );
+  }
+
+  #[test]
+  fn max() {
+    let a = Vector {
+      x: 1.0,
+      y: -2.0,
+      z: 3.0,
+    };
+    let b = Vector {
+      x: -1.0,
+      y: 2.0,
+      z: 3.5,
+    };
+
+    assert_eq!(
+      a.max(&b),
+      Vector {
+        x: 1.0,
+        y: 2.0,
+        z: 3.5,
+      }
+    );
+  }
+
+  #[test]
+  fn abs() {
+    let a = Vector {
+      x: -1.0,
+      y: 2.0,
+      z: -3.0,
+    };
+
+    assert_eq!(
+      a.abs(),
+      Vector {
+        x: 1.0,
+        y: 2.0,
+        z: 3.0,
+      }
+    );
+  }
+
+  #[test]
+  fn dot_orthogonal() {
+    let a = Vector {
+      x: 1.0,
+      y: 0.0,
+      z: 0.0,
+    };
+    let b = Vector {
+      x: 0.0,
+      y: 2.0,
+      z: 0.0,
+    };
+
+    assert_eq!(a.dot(&b), 0.0);
+  }
+
+  #[test]
+  fn dot_parallel() {
+    let a = Vector {
+      x: 1.0,
+      y: 2.0,
+      z: 2.0,
+    };
+    let b = a * 2.0;
+
+    assert!(approx_eq(a.dot(&b), a.length() * b.length(), EPSILON));
+  }
+
+  #[test]
+  fn dot_anti_parallel() {
+    let a = Vector {
+      x: 1.0,
+      y: 2.0,
+      z: 2.0,
+    };
+    let b = a * -2.0;
+
+    assert!(approx_eq(a.dot(&b), -(a.length() * b.length()), EPSILON));
+  }
+
+  #[test]
+  fn random_norm_is_uniform() {
+    // For a uniform distribution on the unit sphere, each component has a mean
+    // of 0 and a variance of 1/3.
+    let n = 100_000;
+    let mut rng = rand::thread_rng();
+    let samples: Vec<Vector> = (0..n).map(|_| Vector::random_norm(&mut rng)).collect();
+    let mean = samples.iter().fold(Vector::new(), |sum, v| sum + v) / n as f64;
+    let variance = samples.iter().fold(Vector::new(), |sum, v| {
+      sum
+        + Vector {
+          x: v.x * v.x,
+          y: v.y * v.y,
+          z: v.z * v.z,
+        }
+    }) / n as f64;
+
+    assert!(mean.length() < 0.02);
+    for component in [variance.x, variance.y, variance.z].iter() {
+      assert!((component - 1.0 / 3.0).abs() < 0.01);
+    }
+    for v in &samples {
+      assert!(approx_eq(v.length(), 1.0, EPSILON));
+    }
+  }
+
+  #[test]
+  fn random_in_unit_sphere_is_inside() {
+    let mut rng = rand::thread_rng();
+    for _ in 0..1000 {
+      assert!(Vector::random_in_unit_sphere(&mut rng).length() < 1.0);
+    }
+  }
+
+  #[test]
+  fn random_in_unit_disk_is_flat_and_inside() {
+    let mut rng = rand::thread_rng();
+    for _ in 0..1000 {
+      let v = Vector::random_in_unit_disk(&mut rng);
+      assert_eq!(v.z, 0.0);
+      assert!(v.length() < 1.0);
+    }
+  }
+
+  #[test]
+  fn random_cosine_hemisphere_faces_normal() {
+    let mut rng = rand::thread_rng();
+    let normal = Vector {
+      x: 1.0,
+      y: 2.0,
+      z: -2.0,
+    }
+    .normalized();
+    for _ in 0..1000 {
+      let v = Vector::random_cosine_hemisphere(&mut rng, &normal);
+      assert!(approx_eq(v.length(), 1.0, EPSILON));
+      assert!(v.dot(&normal) >= 0.0);
+    }
+  }
+
+  #[test]
+  fn approx_eq_tolerates_drift() {
+    let a = Vector {
+      x: 0.1 + 0.2,
+      y: 1.0,
+      z: -1.0,
+    };
+    let b = Vector {
+      x: 0.3,
+      y: 1.0,
+      z: -1.0,
+    };
+
+    assert_ne!(a, b);
+    assert!(a.approx_eq(&b, EPSILON));
+    assert!(!a.approx_eq(&(b * 2.0), EPSILON));
   }
 }
@@ -0,0 +1,129 @@
+extern crate racy;
+
+use racy::camera::Camera;
+use racy::material::*;
+use racy::render::render_to_buffer;
+use racy::scene::*;
+use racy::settings::RenderSettings;
+use racy::sphere::Sphere;
+use racy::vector::Vector;
+use std::sync::Arc;
+
+const SIZE: u32 = 16;
+
+const WHITE: DiffuseColor = DiffuseColor {
+  color: HDRColor {
+    r: 1.0,
+    g: 1.0,
+    b: 1.0,
+  },
+};
+
+const SKY: HDRColor = HDRColor {
+  r: 0.0,
+  g: 0.0,
+  b: 0.5,
+};
+
+// A single white sphere straight ahead of the camera, lit by a point light
+// behind the camera. Everything here is deterministic: one sample per pixel,
+// a closed shutter and a light with no radius mean no random numbers are
+// drawn.
+fn tiny_scene() -> Scene {
+  let settings = RenderSettings {
+    width: SIZE,
+    height: SIZE,
+    ..RenderSettings::default()
+  };
+  let mut cam = Camera::new(Vector::new(), 45.0, SIZE, SIZE);
+  cam.set_angle(std::f64::consts::PI);
+  cam.shutter = 0.0;
+
+  let mut scene = Scene::new(cam, settings)
+    .with(Sphere::new(
+      Vector {
+        x: 0.0,
+        y: 0.0,
+        z: 5.0,
+      },
+      1.0,
+      Arc::new(WHITE),
+    ))
+    .with_light(
+      Vector {
+        x: 0.0,
+        y: 0.0,
+        z: -1.0,
+      },
+      HDRColor {
+        r: 4.0,
+        g: 4.0,
+        b: 4.0,
+      },
+      0.0,
+    );
+  scene.background = Background::Solid(SKY);
+  scene
+}
+
+// Pixels are stored as B, G, R, A.
+fn pixel_at(pixels: &[u8], x: u32, y: u32) -> (u8, u8, u8) {
+  let i = ((y * SIZE + x) * 4) as usize;
+  (pixels[i + 2], pixels[i + 1], pixels[i])
+}
+
+#[test]
+fn sphere_center_is_lit() {
+  let pixels = render_to_buffer(&tiny_scene());
+  let (r, g, b) = pixel_at(&pixels, SIZE / 2, SIZE / 2);
+
+  assert!(r > 200, "expected a bright sphere, got {:?}", (r, g, b));
+  assert_eq!(r, g);
+  assert_eq!(g, b);
+}
+
+#[test]
+fn corners_show_background() {
+  let pixels = render_to_buffer(&tiny_scene());
+  let expected = SKY.into_display_rgb(1.0, ToneMap::Clamp);
+
+  for &(x, y) in [(0, 0), (SIZE - 1, 0), (0, SIZE - 1), (SIZE - 1, SIZE - 1)].iter() {
+    assert_eq!(
+      pixel_at(&pixels, x, y),
+      (expected.r, expected.g, expected.b)
+    );
+  }
+}
+
+#[test]
+fn sphere_shading_is_symmetric() {
+  let pixels = render_to_buffer(&tiny_scene());
+
+  // The light sits on the camera's axis, so brightness should fall off evenly
+  // from the middle of the sphere in every direction (within rounding).
+  let center = SIZE / 2;
+  let (left, _, _) = pixel_at(&pixels, center - 2, center);
+  let (right, _, _) = pixel_at(&pixels, center + 2, center);
+  let (middle, _, _) = pixel_at(&pixels, center, center);
+  assert!((left as i32 - right as i32).abs() <= 2);
+  assert!(left < middle);
+}
+
+#[test]
+fn seeded_renders_are_reproducible() {
+  // Turn on every source of randomness we have: jittered supersampling, an
+  // open shutter and a soft (area) light.
+  let noisy_scene = || {
+    let mut scene = tiny_scene();
+    scene.settings.samples_per_pixel = 4;
+    scene.settings.seed = Some(1234);
+    scene.cam.shutter = 1.0;
+    scene.lights[0].radius = 0.5;
+    scene
+  };
+
+  assert_eq!(
+    render_to_buffer(&noisy_scene()),
+    render_to_buffer(&noisy_scene())
+  );
+}